@@ -1,7 +1,5 @@
 use bollard::auth::DockerCredentials;
-use bollard::container::{
-    Config, CreateContainerOptions, InspectContainerOptions, RemoveContainerOptions,
-};
+use bollard::container::{Config, CreateContainerOptions, RemoveContainerOptions};
 use bollard::image::{CreateImageOptions, RemoveImageOptions};
 use bollard::Docker;
 use std::sync::mpsc::Sender;
@@ -10,19 +8,22 @@ use walkdir::WalkDir;
 
 use anyhow::{Context, Result};
 use futures_util::stream::StreamExt;
+use serde::Deserialize;
 use serde_json::json;
-use std::collections::HashMap;
 use std::default::Default;
 use std::fs;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use tokio_async_drop::tokio_async_drop;
 
 use rfs::fungi::Writer;
 use rfs::store::Store;
+use rfs::{PackEvent, StartupConfig};
 
 struct DockerInfo {
     image_name: String,
+    platform: Option<String>,
     container_name: String,
     docker: Docker,
 }
@@ -49,6 +50,9 @@ pub struct DockerImageToFlist {
     image_name: String,
     credentials: Option<DockerCredentials>,
     docker_tmp_dir: TempDir,
+    platform: Option<String>,
+    oci_tar: Option<PathBuf>,
+    startup: Option<StartupConfig>,
 }
 
 impl DockerImageToFlist {
@@ -63,14 +67,68 @@ impl DockerImageToFlist {
             image_name,
             credentials,
             docker_tmp_dir,
+            platform: None,
+            oci_tar: None,
+            startup: None,
         }
     }
 
+    /// from_oci_tar converts an image archive (the output of `docker save`, or an OCI
+    /// image layout) instead of pulling the image, no docker daemon is needed. If the
+    /// archive has images for multiple platforms, the platform must be selected with
+    /// [`DockerImageToFlist::with_platform`].
+    pub fn from_oci_tar<P: Into<PathBuf>>(meta: Writer, path: P, docker_tmp_dir: TempDir) -> Self {
+        let path = path.into();
+        DockerImageToFlist {
+            meta,
+            image_name: path.display().to_string(),
+            credentials: None,
+            docker_tmp_dir,
+            platform: None,
+            oci_tar: Some(path),
+            startup: None,
+        }
+    }
+
+    /// with_platform selects the platform (`os/arch[/variant]`, for example `linux/arm64`)
+    /// of the image to convert, the platform of the docker daemon is used by default
+    pub fn with_platform(mut self, platform: Option<String>) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// startup is the startup config of the image, it's known once the image is prepared
+    pub fn startup(&self) -> Option<&StartupConfig> {
+        self.startup.as_ref()
+    }
+
     pub fn files_count(&self) -> usize {
         WalkDir::new(self.docker_tmp_dir.path()).into_iter().count()
     }
 
     pub async fn prepare(&mut self) -> Result<()> {
+        if let Some(platform) = &self.platform {
+            parse_platform(platform)?;
+        }
+
+        if let Some(path) = self.oci_tar.clone() {
+            let root = self.docker_tmp_dir.path().to_owned();
+            let platform = self.platform.clone();
+            let startup = tokio::task::spawn_blocking(move || {
+                extract_oci_tar(&path, platform.as_deref(), &root)
+            })
+            .await
+            .context("image extraction task failed")?
+            .context("failed to extract image archive to a directory")?;
+            log::info!(
+                "image archive '{}' is extracted successfully",
+                self.image_name
+            );
+            self.startup = Some(startup);
+
+            return Ok(());
+        }
+
         #[cfg(unix)]
         let docker = Docker::connect_with_socket_defaults().context("failed to create docker")?;
 
@@ -83,13 +141,15 @@ impl DockerImageToFlist {
 
         let docker_info = DockerInfo {
             image_name: self.image_name.to_owned(),
+            platform: self.platform.clone(),
             container_name,
             docker,
         };
 
-        extract_image(
+        let startup = extract_image(
             &docker_info.docker,
             &docker_info.image_name,
+            docker_info.platform.as_deref(),
             &docker_info.container_name,
             self.docker_tmp_dir.path(),
             self.credentials.clone(),
@@ -100,17 +160,31 @@ impl DockerImageToFlist {
             "docker image '{}' is extracted successfully",
             docker_info.image_name
         );
+        self.startup = Some(startup);
 
         Ok(())
     }
 
-    pub async fn pack<S: Store>(&mut self, store: S, sender: Option<Sender<u32>>) -> Result<()> {
+    pub async fn pack<S: Store>(
+        &mut self,
+        store: S,
+        sender: Option<Sender<PackEvent>>,
+    ) -> Result<()> {
+        if let Some(startup) = &self.startup {
+            startup
+                .store(&self.meta)
+                .await
+                .context("failed to store startup config")?;
+        }
+
         rfs::pack(
             self.meta.clone(),
             store,
             &self.docker_tmp_dir.path(),
-            true,
-            sender,
+            rfs::PackOptions {
+                progress: sender,
+                ..rfs::PackOptions::default()
+            },
         )
         .await
         .context("failed to pack flist")?;
@@ -119,7 +193,11 @@ impl DockerImageToFlist {
         Ok(())
     }
 
-    pub async fn convert<S: Store>(&mut self, store: S, sender: Option<Sender<u32>>) -> Result<()> {
+    pub async fn convert<S: Store>(
+        &mut self,
+        store: S,
+        sender: Option<Sender<PackEvent>>,
+    ) -> Result<()> {
         self.prepare().await?;
         self.pack(store, sender).await?;
 
@@ -130,44 +208,95 @@ impl DockerImageToFlist {
 async fn extract_image(
     docker: &Docker,
     image_name: &str,
+    platform: Option<&str>,
     container_name: &str,
     docker_tmp_dir_path: &Path,
     credentials: Option<DockerCredentials>,
-) -> Result<()> {
-    pull_image(docker, image_name, credentials).await?;
-    create_container(docker, image_name, container_name)
+) -> Result<StartupConfig> {
+    pull_image(docker, image_name, platform, credentials).await?;
+    create_container(docker, image_name, platform, container_name)
         .await
         .context("failed to create docker container")?;
     export_container(container_name, docker_tmp_dir_path)
         .context("failed to export docker container")?;
-    container_boot(docker, container_name, docker_tmp_dir_path)
+    container_boot(docker, image_name, docker_tmp_dir_path)
         .await
-        .context("failed to boot docker container")?;
+        .context("failed to boot docker container")
+}
 
-    Ok(())
+/// parse_platform splits a platform in the `os/arch[/variant]` format
+pub fn parse_platform(platform: &str) -> Result<(&str, &str, Option<&str>)> {
+    let parts: Vec<&str> = platform.split('/').collect();
+    match parts.as_slice() {
+        [os, arch] if !os.is_empty() && !arch.is_empty() => Ok((*os, *arch, None)),
+        [os, arch, variant] if !os.is_empty() && !arch.is_empty() && !variant.is_empty() => {
+            Ok((*os, *arch, Some(*variant)))
+        }
+        _ => anyhow::bail!(
+            "invalid platform '{}', expected os/arch[/variant]",
+            platform
+        ),
+    }
 }
 
 async fn pull_image(
     docker: &Docker,
     image_name: &str,
+    platform: Option<&str>,
     credentials: Option<DockerCredentials>,
 ) -> Result<()> {
     log::info!("pulling docker image {}", image_name);
 
     let options = Some(CreateImageOptions {
         from_image: image_name,
+        platform: platform.unwrap_or_default(),
         ..Default::default()
     });
 
     let mut image_pull_stream = docker.create_image(options, None, credentials);
     while let Some(msg) = image_pull_stream.next().await {
-        msg.context("failed to pull docker image")?;
+        match platform {
+            Some(platform) => msg.with_context(|| {
+                format!(
+                    "failed to pull docker image for platform '{}', the platform might not be in the image manifest list",
+                    platform
+                )
+            })?,
+            None => msg.context("failed to pull docker image")?,
+        };
+    }
+
+    // the daemon can fall back to an image of another platform that is available locally
+    if let Some(platform) = platform {
+        let (os, arch, variant) = parse_platform(platform)?;
+        let image = docker
+            .inspect_image(image_name)
+            .await
+            .context("failed to inspect docker image")?;
+
+        let matches = image.os.as_deref() == Some(os)
+            && image.architecture.as_deref() == Some(arch)
+            && (variant.is_none() || image.variant.as_deref() == variant);
+        if !matches {
+            anyhow::bail!(
+                "docker image '{}' is not available for platform '{}', got '{}/{}'",
+                image_name,
+                platform,
+                image.os.unwrap_or_default(),
+                image.architecture.unwrap_or_default()
+            );
+        }
     }
 
     Ok(())
 }
 
-async fn create_container(docker: &Docker, image_name: &str, container_name: &str) -> Result<()> {
+async fn create_container(
+    docker: &Docker,
+    image_name: &str,
+    platform: Option<&str>,
+    container_name: &str,
+) -> Result<()> {
     log::debug!("Inspecting docker image configurations {}", image_name);
 
     let image = docker
@@ -185,7 +314,7 @@ async fn create_container(docker: &Docker, image_name: &str, container_name: &st
 
     let options = Some(CreateContainerOptions {
         name: container_name,
-        platform: None,
+        platform,
     });
 
     let config = Config {
@@ -221,61 +350,20 @@ fn export_container(container_name: &str, docker_tmp_dir_path: &Path) -> Result<
 
 async fn container_boot(
     docker: &Docker,
-    container_name: &str,
+    image_name: &str,
     docker_tmp_dir_path: &Path,
-) -> Result<()> {
-    log::debug!(
-        "Inspecting docker container configurations {}",
-        container_name
-    );
-
-    let options = Some(InspectContainerOptions { size: false });
-    let container = docker
-        .inspect_container(container_name, options)
-        .await
-        .context("failed to inspect docker container")?;
-
-    let container_config = container
-        .config
-        .context("failed to get docker container configs")?;
-
-    let command;
-    let args;
-    let mut env: HashMap<String, String> = HashMap::new();
-    let mut cwd = String::from("/");
-
-    let cmd = container_config.cmd.expect("failed to get cmd configs");
-
-    if let Some(entrypoint) = container_config.entrypoint {
-        command = (entrypoint.first().expect("failed to get first entrypoint")).to_string();
-
-        if entrypoint.len() > 1 {
-            let (_, entries) = entrypoint
-                .split_first()
-                .expect("failed to split entrypoint");
-            args = entries.to_vec();
-        } else {
-            args = cmd;
-        }
-    } else {
-        command = (cmd.first().expect("failed to get first cmd")).to_string();
-        let (_, entries) = cmd.split_first().expect("failed to split cmd");
-        args = entries.to_vec();
-    }
+) -> Result<StartupConfig> {
+    let startup = startup_config(docker, image_name).await?;
+    write_startup_file(&startup, docker_tmp_dir_path)?;
 
-    if let Some(envs) = container_config.env {
-        for entry in envs.iter() {
-            if let Some((key, value)) = entry.split_once('=') {
-                env.insert(key.to_string(), value.to_string());
-            }
-        }
-    }
+    Ok(startup)
+}
 
-    if let Some(ref working_dir) = container_config.working_dir {
-        if !working_dir.is_empty() {
-            cwd = working_dir.to_string();
-        }
-    }
+/// write_startup_file writes the `.startup.toml` file used by the runtimes to start
+/// the flist
+fn write_startup_file(startup: &StartupConfig, docker_tmp_dir_path: &Path) -> Result<()> {
+    let argv = startup.argv();
+    let (command, args) = argv.split_first().expect("startup command can't be empty");
 
     let metadata = json!({
         "startup": {
@@ -284,8 +372,8 @@ async fn container_boot(
                 "args": {
                     "name": command,
                     "args": args,
-                    "env": env,
-                    "dir": cwd,
+                    "env": startup.env,
+                    "dir": startup.workdir,
                 }
             }
         }
@@ -294,8 +382,7 @@ async fn container_boot(
     let toml_metadata: toml::Value = serde_json::from_str(&metadata.to_string())?;
 
     log::info!(
-        "Creating '.startup.toml' file from container {} contains {}",
-        container_name,
+        "Creating '.startup.toml' file contains {}",
         toml_metadata.to_string()
     );
 
@@ -303,11 +390,416 @@ async fn container_boot(
         docker_tmp_dir_path.join(".startup.toml"),
         toml_metadata.to_string(),
     )
-    .expect("failed to create '.startup.toml' file");
+    .context("failed to create '.startup.toml' file")?;
+
+    Ok(())
+}
+
+/// startup_config builds the startup config from the image configurations, images
+/// without a command start a shell like the created container
+async fn startup_config(docker: &Docker, image_name: &str) -> Result<StartupConfig> {
+    log::debug!("Inspecting docker image configurations {}", image_name);
+
+    let image = docker
+        .inspect_image(image_name)
+        .await
+        .context("failed to inspect docker image")?;
+    let image_config = image.config.context("failed to get docker image configs")?;
+
+    Ok(ImageConfig {
+        entrypoint: image_config.entrypoint,
+        cmd: image_config.cmd,
+        env: image_config.env,
+        working_dir: image_config.working_dir,
+        user: image_config.user,
+    }
+    .into())
+}
+
+/// ImageConfig is the part of the image configuration used to start the image, it's
+/// the same for docker and OCI images
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ImageConfig {
+    entrypoint: Option<Vec<String>>,
+    cmd: Option<Vec<String>>,
+    env: Option<Vec<String>>,
+    working_dir: Option<String>,
+    user: Option<String>,
+}
+
+impl From<ImageConfig> for StartupConfig {
+    fn from(config: ImageConfig) -> Self {
+        let mut startup = StartupConfig {
+            entrypoint: config.entrypoint.unwrap_or_default(),
+            cmd: config.cmd.unwrap_or_default(),
+            ..Default::default()
+        };
+
+        if startup.argv().is_empty() {
+            startup.cmd = vec!["/bin/sh".into()];
+        }
+
+        for entry in config.env.unwrap_or_default() {
+            if let Some((key, value)) = entry.split_once('=') {
+                startup.env.insert(key.to_string(), value.to_string());
+            }
+        }
+
+        if let Some(working_dir) = config.working_dir {
+            if !working_dir.is_empty() {
+                startup.workdir = working_dir;
+            }
+        }
+
+        startup.user = config.user.filter(|user| !user.is_empty());
+        startup
+    }
+}
+
+/// ImageFile is the image configuration file of docker and OCI images
+#[derive(Debug, Deserialize)]
+struct ImageFile {
+    #[serde(default)]
+    config: Option<ImageConfig>,
+}
+
+/// DockerManifest is an entry of the `manifest.json` file of `docker save` archives
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerManifest {
+    config: String,
+    layers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OciDescriptor {
+    digest: String,
+    #[serde(rename = "mediaType", default)]
+    media_type: String,
+    #[serde(default)]
+    platform: Option<OciPlatform>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OciPlatform {
+    os: String,
+    architecture: String,
+    #[serde(default)]
+    variant: Option<String>,
+}
+
+impl std::fmt::Display for OciPlatform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
+/// OciIndex is an OCI image index (or the `index.json` of an image layout)
+#[derive(Debug, Deserialize)]
+struct OciIndex {
+    manifests: Vec<OciDescriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OciManifest {
+    config: OciDescriptor,
+    layers: Vec<OciDescriptor>,
+}
+
+const OCI_INDEX_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+
+// prefix of the layer entries that remove a path of the lower layers
+const WHITEOUT_PREFIX: &str = ".wh.";
+// layer entry that hides all the content of the lower layers in its directory
+const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+
+/// extract_oci_tar extracts the root filesystem of the image archive to the root
+/// directory and returns the image startup config
+fn extract_oci_tar(path: &Path, platform: Option<&str>, root: &Path) -> Result<StartupConfig> {
+    // the archive is extracted next to the root, in the same tmp directory
+    let image_dir = match root.parent() {
+        Some(parent) => TempDir::new_in(parent, "docker2fl-image"),
+        None => TempDir::new("docker2fl-image"),
+    }
+    .context("failed to create tmp directory")?;
+    let file = fs::File::open(path)
+        .with_context(|| format!("failed to open image archive '{}'", path.display()))?;
+    tar::Archive::new(file)
+        .unpack(image_dir.path())
+        .context("failed to read image archive")?;
+
+    let (config, layers) = match image_dir.path().join("manifest.json").exists() {
+        true => docker_layout(image_dir.path())?,
+        false => oci_layout(image_dir.path(), platform)?,
+    };
+
+    let config: ImageFile = serde_json::from_slice(
+        &fs::read(image_dir.path().join(&config)).context("failed to read image config")?,
+    )
+    .context("failed to parse image config")?;
+
+    for layer in layers {
+        log::debug!("applying layer {}", layer.display());
+        apply_layer(&image_dir.path().join(&layer), root)
+            .with_context(|| format!("failed to apply layer '{}'", layer.display()))?;
+    }
+
+    let startup = config.config.unwrap_or_default().into();
+    write_startup_file(&startup, root)?;
+
+    Ok(startup)
+}
+
+/// docker_layout returns the config and layers paths of a `docker save` archive
+fn docker_layout(dir: &Path) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let manifests: Vec<DockerManifest> = serde_json::from_slice(
+        &fs::read(dir.join("manifest.json")).context("failed to read manifest.json")?,
+    )
+    .context("failed to parse manifest.json")?;
+
+    let manifest = match manifests.len() {
+        1 => manifests.into_iter().next().unwrap(),
+        count => anyhow::bail!("image archive must contain one image, found {}", count),
+    };
+
+    Ok((
+        manifest.config.into(),
+        manifest.layers.into_iter().map(PathBuf::from).collect(),
+    ))
+}
+
+/// oci_layout returns the config and layers paths of an OCI image layout, image
+/// indexes are resolved to the manifest of the requested platform
+fn oci_layout(dir: &Path, platform: Option<&str>) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let mut index: OciIndex = serde_json::from_slice(
+        &fs::read(dir.join("index.json")).context("image archive has no manifest")?,
+    )
+    .context("failed to parse index.json")?;
+
+    loop {
+        let descriptor = select_manifest(index.manifests, platform)?;
+        let blob = fs::read(dir.join(blob_path(&descriptor.digest)?))
+            .context("failed to read image manifest")?;
+
+        if !OCI_INDEX_MEDIA_TYPES.contains(&descriptor.media_type.as_str()) {
+            let manifest: OciManifest =
+                serde_json::from_slice(&blob).context("failed to parse image manifest")?;
+
+            return Ok((
+                blob_path(&manifest.config.digest)?,
+                manifest
+                    .layers
+                    .iter()
+                    .map(|layer| blob_path(&layer.digest))
+                    .collect::<Result<_>>()?,
+            ));
+        }
+
+        index = serde_json::from_slice(&blob).context("failed to parse image index")?;
+    }
+}
+
+/// select_manifest picks the manifest of the platform, the platform is only required
+/// if there are multiple manifests
+fn select_manifest(manifests: Vec<OciDescriptor>, platform: Option<&str>) -> Result<OciDescriptor> {
+    let platform = match platform {
+        Some(platform) => platform,
+        None if manifests.len() == 1 => return Ok(manifests.into_iter().next().unwrap()),
+        None => {
+            let platforms: Vec<String> = manifests
+                .iter()
+                .filter_map(|m| m.platform.as_ref().map(|p| p.to_string()))
+                .collect();
+            anyhow::bail!(
+                "image archive contains {} manifests ({}), a platform must be selected",
+                manifests.len(),
+                platforms.join(", ")
+            )
+        }
+    };
+
+    let (os, arch, variant) = parse_platform(platform)?;
+    let count = manifests.len();
+    let mut candidates = manifests.into_iter().filter(|m| match &m.platform {
+        Some(p) => {
+            p.os == os
+                && p.architecture == arch
+                && (variant.is_none() || p.variant.as_deref() == variant)
+        }
+        // manifests of a single image don't always have a platform
+        None => count == 1,
+    });
+
+    candidates
+        .next()
+        .with_context(|| format!("image archive has no manifest for platform '{}'", platform))
+}
+
+/// blob_path returns the path of the blob in the image layout
+fn blob_path(digest: &str) -> Result<PathBuf> {
+    match digest.split_once(':') {
+        Some((algorithm, hash))
+            if !algorithm.is_empty()
+                && !hash.is_empty()
+                && !algorithm.contains(['/', '.'])
+                && !hash.contains(['/', '.']) =>
+        {
+            Ok(Path::new("blobs").join(algorithm).join(hash))
+        }
+        _ => anyhow::bail!("invalid digest '{}'", digest),
+    }
+}
+
+/// open_layer opens a (possibly gzip compressed) layer archive
+fn open_layer(path: &Path) -> Result<tar::Archive<Box<dyn Read>>> {
+    let mut file = fs::File::open(path)?;
+    let mut magic = [0u8; 4];
+    let size = file.read(&mut magic)?;
+    let file = std::io::Cursor::new(magic[..size].to_vec()).chain(file);
+
+    let reader: Box<dyn Read> = match &magic[..size] {
+        [0x1f, 0x8b, ..] => Box::new(flate2::read::GzDecoder::new(file)),
+        [0x28, 0xb5, 0x2f, 0xfd] => anyhow::bail!("zstd compressed layers are not supported"),
+        _ => Box::new(file),
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_unpack_xattrs(true);
+    // only root can set the ownership of the extracted files
+    archive.set_preserve_ownerships(unsafe { libc::geteuid() } == 0);
+
+    Ok(archive)
+}
+
+/// apply_layer extracts the layer on top of the root directory, the whiteouts of the
+/// layer are applied first so they only remove the content of the lower layers
+fn apply_layer(path: &Path, root: &Path) -> Result<()> {
+    let mut archive = open_layer(path)?;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let entry_path = entry.path()?;
+        let name = match entry_path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.starts_with(WHITEOUT_PREFIX) => name,
+            _ => continue,
+        };
+
+        let dir = resolve_path(root, entry_path.parent().unwrap_or(Path::new("")))
+            .with_context(|| format!("invalid layer entry '{}'", entry_path.display()))?;
+
+        if name == WHITEOUT_OPAQUE {
+            // the directory itself must not be a link, its children are removed
+            let is_dir = fs::symlink_metadata(&dir).map_or(false, |meta| meta.is_dir());
+            if is_dir {
+                for child in fs::read_dir(&dir)? {
+                    remove_path(&child?.path())?;
+                }
+            }
+            continue;
+        }
+
+        let hidden = &name[WHITEOUT_PREFIX.len()..];
+        if hidden.is_empty() || hidden == "." || hidden == ".." {
+            anyhow::bail!("invalid whiteout '{}'", entry_path.display());
+        }
+        remove_path(&dir.join(hidden))?;
+    }
+
+    let mut archive = open_layer(path)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        let is_whiteout = entry_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with(WHITEOUT_PREFIX));
+        if is_whiteout {
+            continue;
+        }
+
+        // an entry replaces the path of a lower layer, unless both are directories
+        // in which case the content of the directories is merged
+        let target = resolve_path(root, &entry_path)
+            .with_context(|| format!("invalid layer entry '{}'", entry_path.display()))?;
+        if let Ok(existing) = fs::symlink_metadata(&target) {
+            if !(existing.is_dir() && entry.header().entry_type().is_dir()) {
+                remove_path(&target)?;
+            }
+        }
+
+        entry
+            .unpack_in(root)
+            .with_context(|| format!("failed to extract '{}'", entry_path.display()))?;
+    }
 
     Ok(())
 }
 
+/// resolve_path joins the layer entry path to the root. It fails if the path has `..`
+/// components, or if its parent directory resolves (through the links extracted by the
+/// lower layers) outside of the root. The returned path has no links but the last
+/// component, so it can be removed without touching files outside of the root.
+fn resolve_path(root: &Path, path: &Path) -> Result<PathBuf> {
+    use std::path::Component;
+
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(name),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                anyhow::bail!("path '{}' escapes the root", path.display())
+            }
+        }
+    }
+
+    let name = match names.pop() {
+        Some(name) => name,
+        None => return Ok(root.to_path_buf()),
+    };
+
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("failed to resolve '{}'", root.display()))?;
+    let parent = names
+        .iter()
+        .fold(root.clone(), |parent, name| parent.join(name));
+    let parent = match parent.canonicalize() {
+        Ok(parent) => parent,
+        // nothing exists at the path yet
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => parent,
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to resolve '{}'", path.display()))
+        }
+    };
+
+    if !parent.starts_with(&root) {
+        anyhow::bail!("path '{}' escapes the root", path.display());
+    }
+
+    Ok(parent.join(name))
+}
+
+/// remove_path removes the file or directory (recursively) if it exists
+fn remove_path(path: &Path) -> Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    };
+
+    result.with_context(|| format!("failed to remove '{}'", path.display()))
+}
+
 async fn clean(docker: &Docker, image_name: &str, container_name: &str) -> Result<()> {
     log::info!("cleaning docker image and container");
 
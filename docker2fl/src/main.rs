@@ -1,8 +1,9 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bollard::auth::DockerCredentials;
 use clap::{ArgAction, Parser};
 use rfs::fungi;
 use rfs::store::parse_router;
+use std::path::PathBuf;
 use tokio::runtime::Builder;
 use uuid::Uuid;
 
@@ -21,8 +22,24 @@ struct Options {
     store: Vec<String>,
 
     /// name of the docker image to be converted to flist
-    #[clap(short, long, required = true)]
-    image_name: String,
+    #[clap(short, long, required_unless_present = "oci_tar")]
+    image_name: Option<String>,
+
+    /// image archive to convert instead of pulling the image, it can be the output of
+    /// `docker save` or an OCI image layout. No docker daemon is needed
+    #[clap(long, conflicts_with = "image_name")]
+    oci_tar: Option<PathBuf>,
+
+    /// platform of the image in the format os/arch[/variant] (for example linux/arm64),
+    /// defaults to the platform of the docker daemon. The platform is added to the flist name.
+    /// Required with --oci-tar if the archive has images for multiple platforms
+    #[clap(long)]
+    platform: Option<String>,
+
+    /// directory the image is extracted in before it's packed, it must have enough
+    /// space for the whole image. Defaults to the system temp directory
+    #[clap(long)]
+    tmp_dir: Option<PathBuf>,
 
     // docker credentials
     /// docker hub server username
@@ -78,10 +95,12 @@ async fn run() -> Result<()> {
         .with_module_level("sqlx", log::Level::Error.to_level_filter())
         .init()?;
 
-    let mut docker_image = opts.image_name.to_string();
-    if !docker_image.contains(':') {
-        docker_image.push_str(":latest");
-    }
+    let docker_image = opts.image_name.map(|mut image| {
+        if !image.contains(':') {
+            image.push_str(":latest");
+        }
+        image
+    });
 
     let credentials = Some(DockerCredentials {
         username: opts.username,
@@ -93,21 +112,46 @@ async fn run() -> Result<()> {
         registrytoken: opts.registry_token,
     });
 
-    let fl_name = docker_image.replace([':', '/'], "-") + ".fl";
-    let meta = fungi::Writer::new(&fl_name, true).await?;
-    let store = parse_router(&opts.store).await?;
+    let mut fl_name = match (&docker_image, &opts.oci_tar) {
+        (Some(image), _) => image.replace([':', '/'], "-"),
+        (None, Some(path)) => path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("image")
+            .replace([':', '/'], "-"),
+        (None, None) => unreachable!("image name or archive is required"),
+    };
+    if let Some(platform) = &opts.platform {
+        fl_name = format!("{}-{}", fl_name, platform.replace('/', "-"));
+    }
+    let fl_name = fl_name + ".fl";
 
+    // the tmp directory is removed when it's dropped, including on errors
     let container_name = Uuid::new_v4().to_string();
-    let docker_tmp_dir =
-        tempdir::TempDir::new(&container_name).expect("failed to create tmp directory");
+    let docker_tmp_dir = match &opts.tmp_dir {
+        Some(dir) => tempdir::TempDir::new_in(dir, &container_name),
+        None => tempdir::TempDir::new(&container_name),
+    }
+    .context("failed to create tmp directory")?;
+
+    let meta = fungi::Writer::new(&fl_name, true).await?;
+    let store = parse_router(&opts.store).await?;
 
-    let mut docker_to_fl =
-        docker2fl::DockerImageToFlist::new(meta, docker_image, credentials, docker_tmp_dir);
+    let docker_to_fl = match (docker_image, opts.oci_tar) {
+        (Some(image), _) => {
+            docker2fl::DockerImageToFlist::new(meta, image, credentials, docker_tmp_dir)
+        }
+        (None, Some(path)) => {
+            docker2fl::DockerImageToFlist::from_oci_tar(meta, path, docker_tmp_dir)
+        }
+        (None, None) => unreachable!("image name or archive is required"),
+    };
+    let mut docker_to_fl = docker_to_fl.with_platform(opts.platform);
     let res = docker_to_fl.convert(store, None).await;
 
     // remove the file created with the writer if fl creation failed
     if res.is_err() {
-        tokio::fs::remove_file(fl_name).await?;
+        fungi::meta::remove(fl_name).await?;
         return res;
     }
 
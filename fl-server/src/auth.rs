@@ -1,10 +1,11 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{Json, Request, State},
+    extract::{Json, Path, Request, State},
     http::{self, StatusCode},
     middleware::Next,
     response::IntoResponse,
+    Extension,
 };
 use axum_macros::debug_handler;
 use chrono::{Duration, Utc};
@@ -14,6 +15,8 @@ use utoipa::ToSchema;
 
 use crate::{
     config,
+    db::User,
+    handlers,
     response::{ResponseError, ResponseResult},
 };
 
@@ -22,6 +25,8 @@ pub struct Claims {
     pub exp: usize,       // Expiry time of the token
     pub iat: usize,       // Issued at time of the token
     pub username: String, // Username associated with the token
+    #[serde(default)]
+    pub refresh: bool, // Refresh tokens can only be used to get new access tokens
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -33,6 +38,12 @@ pub struct SignInBody {
 #[derive(Serialize, ToSchema)]
 pub struct SignInResponse {
     pub access_token: String,
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RefreshBody {
+    pub refresh_token: String,
 }
 
 #[utoipa::path(
@@ -59,33 +70,253 @@ pub async fn sign_in_handler(
         }
     };
 
-    if user_data.password != user.password {
+    if !verify_password(&user_data.password, &user.password) {
         return Err(ResponseError::Unauthorized(
             "Wrong username or password".to_string(),
         ));
     }
 
-    let token = encode_jwt(
-        user.username.clone(),
+    Ok(ResponseResult::SignedIn(sign_in_response(
+        &state,
+        user.username,
+    )?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/api/refresh",
+    request_body = RefreshBody,
+    responses(
+        (status = 201, description = "Tokens refreshed successfully", body = SignInResponse),
+        (status = 500, description = "Internal server error"),
+        (status = 401, description = "Unauthorized user"),
+    )
+)]
+#[debug_handler]
+pub async fn refresh_handler(
+    State(state): State<Arc<config::AppState>>,
+    Json(body): Json<RefreshBody>,
+) -> impl IntoResponse {
+    let token_data = match decode_jwt(body.refresh_token, state.config.jwt_secret.clone()) {
+        Ok(data) if data.claims.refresh => data,
+        _ => {
+            return Err(ResponseError::Unauthorized(
+                "Invalid refresh token".to_string(),
+            ))
+        }
+    };
+
+    // the user might have been removed since the token was issued
+    let user = match state.db.get_user_by_username(&token_data.claims.username) {
+        Some(user) => user,
+        None => {
+            return Err(ResponseError::Unauthorized(
+                "User is not authorized".to_string(),
+            ));
+        }
+    };
+
+    Ok(ResponseResult::SignedIn(sign_in_response(
+        &state,
+        user.username,
+    )?))
+}
+
+/// sign_in_response issues a new access token and refresh token for the user
+fn sign_in_response(
+    state: &config::AppState,
+    username: String,
+) -> Result<SignInResponse, ResponseError> {
+    let access_token = encode_jwt(
+        username.clone(),
         state.config.jwt_secret.clone(),
         state.config.jwt_expire_hours,
     )
     .map_err(|_| ResponseError::InternalServerError)?;
 
-    Ok(ResponseResult::SignedIn(SignInResponse {
-        access_token: token,
-    }))
+    let refresh_token = encode_token(
+        username,
+        state.config.jwt_secret.clone(),
+        state.config.refresh_expire_hours(),
+        true,
+    )
+    .map_err(|_| ResponseError::InternalServerError)?;
+
+    Ok(SignInResponse {
+        access_token,
+        refresh_token,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    /// the key is only returned once, it must be sent in the `X-Api-Key` header
+    pub key: String,
+}
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Create an api key for the user. Api keys are only kept in memory, they stop
+/// working when the server restarts and must be created again.
+#[utoipa::path(
+    post,
+    path = "/v1/api/keys",
+    responses(
+        (status = 201, description = "Api key created", body = ApiKeyResponse),
+        (status = 401, description = "Unauthorized user"),
+        (status = 403, description = "Forbidden"),
+    )
+)]
+#[debug_handler]
+pub async fn create_api_key_handler(
+    State(state): State<Arc<config::AppState>>,
+    Extension(username): Extension<String>,
+) -> impl IntoResponse {
+    let id = uuid::Uuid::new_v4().to_string();
+    let key = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    state
+        .db
+        .create_api_key(&id, &username, &sha256::digest(key.as_str()));
+
+    ResponseResult::ApiKeyCreated(ApiKeyResponse { id, key })
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/api/keys/{id}",
+    responses(
+        (status = 200, description = "Api key revoked"),
+        (status = 401, description = "Unauthorized user"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Api key not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "id of the api key")
+    )
+)]
+#[debug_handler]
+pub async fn revoke_api_key_handler(
+    State(state): State<Arc<config::AppState>>,
+    Extension(username): Extension<String>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.db.revoke_api_key(&username, &id) {
+        Ok(()) => Ok(ResponseResult::ApiKeyRevoked),
+        Err(err) => Err(ResponseError::NotFound(err.to_string())),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RegisterBody {
+    pub username: String,
+    pub password: String,
+}
+
+/// Register a new user. Registered users are only kept in memory, they are lost
+/// when the server restarts. Add the user to the `users` of the config file to
+/// keep it.
+#[utoipa::path(
+    post,
+    path = "/v1/api/register",
+    request_body = RegisterBody,
+    responses(
+        (status = 201, description = "User registered successfully"),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "Registration is disabled"),
+        (status = 409, description = "Username already exists"),
+        (status = 500, description = "Internal server error"),
+    )
+)]
+#[debug_handler]
+pub async fn register_handler(
+    State(state): State<Arc<config::AppState>>,
+    Json(body): Json<RegisterBody>,
+) -> impl IntoResponse {
+    if !state.config.allow_registration {
+        return Err(ResponseError::Forbidden(
+            "Registration is disabled".to_string(),
+        ));
+    }
+
+    // usernames are used as the flists directory of the user
+    if !handlers::valid_name(&body.username) {
+        return Err(ResponseError::BadRequest(format!(
+            "invalid username '{}'",
+            body.username
+        )));
+    }
+
+    if body.password.is_empty() {
+        return Err(ResponseError::BadRequest(
+            "password can't be empty".to_string(),
+        ));
+    }
+
+    let password = hash_password(&body.password).map_err(|err| {
+        log::error!("failed to hash password: {}", err);
+        ResponseError::InternalServerError
+    })?;
+
+    match state.db.create_user(User {
+        username: body.username,
+        password,
+    }) {
+        Ok(()) => Ok(ResponseResult::Registered),
+        Err(err) => Err(ResponseError::Conflict(err.to_string())),
+    }
+}
+
+/// hash_password hashes the password with a random salt, the result is a phc string
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+
+    Ok(hash.to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+    match PasswordHash::new(hash) {
+        Ok(hash) => argon2::Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
 }
 
 pub fn encode_jwt(
     username: String,
     jwt_secret: String,
     jwt_expire: i64,
+) -> Result<String, StatusCode> {
+    encode_token(username, jwt_secret, jwt_expire, false)
+}
+
+fn encode_token(
+    username: String,
+    jwt_secret: String,
+    jwt_expire: i64,
+    refresh: bool,
 ) -> Result<String, StatusCode> {
     let now = Utc::now();
     let exp: usize = (now + Duration::hours(jwt_expire)).timestamp() as usize;
     let iat: usize = now.timestamp() as usize;
-    let claim = Claims { iat, exp, username };
+    let claim = Claims {
+        iat,
+        exp,
+        username,
+        refresh,
+    };
 
     encode(
         &Header::default(),
@@ -110,6 +341,21 @@ pub async fn authorize(
     mut req: Request,
     next: Next,
 ) -> impl IntoResponse {
+    // api keys are an alternative to the JWT token, mainly for automation
+    if let Some(key) = req.headers().get(API_KEY_HEADER) {
+        let key = key
+            .to_str()
+            .map_err(|_| ResponseError::Forbidden("Invalid api key".to_string()))?;
+
+        let user = match state.db.get_user_by_api_key(&sha256::digest(key)) {
+            Some(user) => user,
+            None => return Err(ResponseError::Unauthorized("Invalid api key".to_string())),
+        };
+
+        req.extensions_mut().insert(user.username);
+        return Ok(next.run(req).await);
+    }
+
     let auth_header = match req.headers_mut().get(http::header::AUTHORIZATION) {
         Some(header) => header
             .to_str()
@@ -132,8 +378,8 @@ pub async fn authorize(
     };
 
     let token_data = match decode_jwt(token_str, state.config.jwt_secret.clone()) {
-        Ok(data) => data,
-        Err(_) => {
+        Ok(data) if !data.claims.refresh => data,
+        _ => {
             return Err(ResponseError::Forbidden(
                 "Unable to decode JWT token".to_string(),
             ))
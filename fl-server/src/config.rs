@@ -10,7 +10,7 @@ use utoipa::ToSchema;
 
 use crate::{
     db::{User, DB},
-    handlers,
+    handlers, metrics,
 };
 
 #[derive(Debug, ToSchema, Serialize, Clone)]
@@ -24,6 +24,10 @@ pub struct AppState {
     pub flists_progress: Mutex<HashMap<PathBuf, f32>>,
     pub db: Arc<dyn DB>,
     pub config: Config,
+    pub metrics: metrics::Metrics,
+    /// flist jobs hold it for reading until they are done, the garbage collection
+    /// holds it for writing so it never runs while blocks are being uploaded
+    pub gc_lock: Arc<tokio::sync::RwLock<()>>,
 }
 
 #[derive(Debug, Default, Clone, Deserialize)]
@@ -35,7 +39,29 @@ pub struct Config {
 
     pub jwt_secret: String,
     pub jwt_expire_hours: i64,
+    /// life time of the refresh tokens, they are used to get new access tokens. See
+    /// [`Config::refresh_expire_hours`] for the default
+    #[serde(default)]
+    pub jwt_refresh_expire_hours: Option<i64>,
     pub users: Vec<User>,
+
+    /// allow anyone to create a user with the register endpoint
+    #[serde(default)]
+    pub allow_registration: bool,
+    /// usernames of the users allowed to manage the flists of all users
+    #[serde(default)]
+    pub admins: Vec<String>,
+    /// address (host:port) to serve the metrics on, they are served on the main
+    /// address if not set
+    #[serde(default)]
+    pub metrics_address: Option<String>,
+    /// maximum size in MiB of the tarballs uploaded to create flists
+    #[serde(default = "default_max_upload_size_mb")]
+    pub max_upload_size_mb: usize,
+    /// maximum time in seconds to upload a tarball to create a flist, the other
+    /// requests time out after 10 seconds
+    #[serde(default = "default_upload_timeout_secs")]
+    pub upload_timeout_secs: u64,
 }
 
 /// Parse the config file into Config struct.
@@ -59,5 +85,75 @@ pub async fn parse_config(filepath: &str) -> Result<Config> {
         ))
     }
 
+    if let Some(refresh) = c.jwt_refresh_expire_hours {
+        if refresh < c.jwt_expire_hours {
+            anyhow::bail!(format!(
+                "jwt refresh expiry interval in hours '{}' is invalid, must be at least the jwt expiry interval '{}'",
+                refresh, c.jwt_expire_hours
+            ))
+        }
+    }
+
     Ok(c)
 }
+
+impl Config {
+    /// refresh_expire_hours is the life time of the refresh tokens, if it's not set
+    /// they live for a week but never less than the access tokens
+    pub fn refresh_expire_hours(&self) -> i64 {
+        self.jwt_refresh_expire_hours
+            .unwrap_or_else(|| std::cmp::max(7 * 24, self.jwt_expire_hours))
+    }
+}
+
+fn default_max_upload_size_mb() -> usize {
+    1024
+}
+
+fn default_upload_timeout_secs() -> u64 {
+    60 * 60
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn parse(extra: &str) -> Result<Config> {
+        const ROOT: &str = "/tmp/fl-server-config-test";
+        fs::create_dir_all(ROOT).unwrap();
+        let path = format!("{}/config.toml", ROOT);
+        let content = format!(
+            r#"
+host = "localhost"
+port = 3000
+store_url = ["dir:///tmp/store0"]
+flist_dir = "{}/flists"
+jwt_secret = "secret"
+jwt_expire_hours = 5
+users = []
+{}
+"#,
+            ROOT, extra
+        );
+        fs::write(&path, content).unwrap();
+
+        parse_config(&path).await
+    }
+
+    #[tokio::test]
+    async fn test_refresh_expire_hours() {
+        let config = parse("").await.unwrap();
+        assert_eq!(config.refresh_expire_hours(), 7 * 24);
+
+        let config = parse("jwt_refresh_expire_hours = 10").await.unwrap();
+        assert_eq!(config.refresh_expire_hours(), 10);
+
+        assert!(parse("jwt_refresh_expire_hours = 2").await.is_err());
+
+        let config = Config {
+            jwt_expire_hours: 200,
+            ..Config::default()
+        };
+        assert_eq!(config.refresh_expire_hours(), 200);
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::RwLock};
 
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
@@ -6,31 +6,104 @@ use utoipa::ToSchema;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub username: String,
+    /// plain password in the config, the hash of the password (see
+    /// `auth::hash_password`) once stored in the DB
     pub password: String,
 }
 
+/// ApiKey is a long lived key a user can authenticate with instead of a JWT token,
+/// only the hash of the key itself is stored
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub username: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("user '{0}' already exists")]
+    UserExists(String),
+    #[error("api key '{0}' doesn't exist")]
+    ApiKeyNotFound(String),
+}
+
 pub trait DB: Send + Sync {
     fn get_user_by_username(&self, username: &str) -> Option<User>;
+    fn create_user(&self, user: User) -> Result<(), Error>;
+    /// create_api_key stores the hash of the key for the user
+    fn create_api_key(&self, id: &str, username: &str, key_hash: &str);
+    fn get_user_by_api_key(&self, key_hash: &str) -> Option<User>;
+    /// revoke_api_key deletes the key with the given id if it's owned by the user
+    fn revoke_api_key(&self, username: &str, id: &str) -> Result<(), Error>;
 }
 
 #[derive(Debug, ToSchema)]
 pub struct MapDB {
-    users: HashMap<String, User>,
+    users: RwLock<HashMap<String, User>>,
+    /// api keys by key hash
+    api_keys: RwLock<HashMap<String, ApiKey>>,
 }
 
 impl MapDB {
     pub fn new(users: &[User]) -> Self {
         Self {
-            users: users
-                .iter()
-                .map(|u| (u.username.clone(), u.to_owned()))
-                .collect(),
+            users: RwLock::new(
+                users
+                    .iter()
+                    .map(|u| (u.username.clone(), u.to_owned()))
+                    .collect(),
+            ),
+            api_keys: RwLock::default(),
         }
     }
 }
 
 impl DB for MapDB {
     fn get_user_by_username(&self, username: &str) -> Option<User> {
-        self.users.get(username).cloned()
+        self.users.read().unwrap().get(username).cloned()
+    }
+
+    fn create_user(&self, user: User) -> Result<(), Error> {
+        use std::collections::hash_map::Entry;
+
+        match self.users.write().unwrap().entry(user.username.clone()) {
+            Entry::Occupied(_) => Err(Error::UserExists(user.username)),
+            Entry::Vacant(entry) => {
+                entry.insert(user);
+                Ok(())
+            }
+        }
+    }
+
+    fn create_api_key(&self, id: &str, username: &str, key_hash: &str) {
+        self.api_keys.write().unwrap().insert(
+            key_hash.into(),
+            ApiKey {
+                id: id.into(),
+                username: username.into(),
+            },
+        );
+    }
+
+    fn get_user_by_api_key(&self, key_hash: &str) -> Option<User> {
+        let username = self
+            .api_keys
+            .read()
+            .unwrap()
+            .get(key_hash)
+            .map(|key| key.username.clone())?;
+
+        self.get_user_by_username(&username)
+    }
+
+    fn revoke_api_key(&self, username: &str, id: &str) -> Result<(), Error> {
+        let mut keys = self.api_keys.write().unwrap();
+        let len = keys.len();
+        keys.retain(|_, key| !(key.id == id && key.username == username));
+        if keys.len() == len {
+            return Err(Error::ApiKeyNotFound(id.into()));
+        }
+
+        Ok(())
     }
 }
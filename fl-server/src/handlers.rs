@@ -1,22 +1,32 @@
 use anyhow::Error;
 use axum::{
-    extract::{Path, State},
-    response::IntoResponse,
+    extract::{multipart::Field, Multipart, Path, Query, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
     Extension, Json,
 };
 use axum_macros::debug_handler;
+use futures_util::stream::{self, StreamExt};
 use std::{
     collections::HashMap,
     fs,
     path::PathBuf,
-    sync::{mpsc, Arc},
+    sync::{mpsc, Arc, OnceLock},
+    time::Duration,
 };
+use tokio::io::AsyncWriteExt;
 
 use bollard::auth::DockerCredentials;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    auth::{SignInBody, SignInResponse, __path_sign_in_handler},
+    auth::{
+        __path_create_api_key_handler, __path_refresh_handler, __path_register_handler,
+        __path_revoke_api_key_handler, __path_sign_in_handler, ApiKeyResponse, RefreshBody,
+        RegisterBody, SignInBody, SignInResponse,
+    },
     response::{DirListTemplate, DirLister, ErrorTemplate, TemplateErr},
 };
 use crate::{
@@ -25,13 +35,13 @@ use crate::{
     serve_flists::visit_dir_one_level,
 };
 use rfs::fungi::{Reader, Writer};
-use utoipa::{OpenApi, ToSchema};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 use uuid::Uuid;
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(health_check_handler, create_flist_handler, get_flist_state_handler, preview_flist_handler, list_flists_handler, sign_in_handler),
-    components(schemas(DirListTemplate, DirLister, FlistBody, Job, ResponseError, ErrorTemplate, TemplateErr, ResponseResult, FileInfo, SignInBody, FlistState, SignInResponse, FlistStateInfo, PreviewResponse)),
+    paths(health_check_handler, create_flist_handler, create_tar_flist_handler, get_flist_state_handler, stream_flist_state_handler, preview_flist_handler, list_flists_handler, sign_in_handler, register_handler, delete_flist_handler, gc_handler, create_api_key_handler, revoke_api_key_handler, refresh_handler),
+    components(schemas(DirListTemplate, DirLister, FlistBody, TarFlistBody, Job, ResponseError, ErrorTemplate, TemplateErr, ResponseResult, FileInfo, SignInBody, RegisterBody, FlistState, SignInResponse, FlistStateInfo, PreviewResponse, GcBody, GcResponse, ApiKeyResponse, RefreshBody)),
     tags(
         (name = "fl-server", description = "Flist conversion API")
     )
@@ -50,6 +60,38 @@ pub struct FlistBody {
     pub server_address: Option<String>,
     pub identity_token: Option<String>,
     pub registry_token: Option<String>,
+
+    /// platform of the image (os/arch[/variant]), defaults to the platform of the server
+    #[schema(example = "linux/amd64")]
+    pub platform: Option<String>,
+}
+
+/// TarFlistBody describes the multipart form used to create a flist from a tarball
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct TarFlistBody {
+    /// name of the flist, `.fl` is appended if missing
+    #[schema(example = "rootfs")]
+    name: String,
+    /// gzip compressed tarball of the root filesystem
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize, Default, ToSchema)]
+pub struct GcBody {
+    /// only report the unreferenced blobs without deleting them
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GcResponse {
+    pub dry_run: bool,
+    /// number of flists used to find the referenced blobs
+    pub flists: usize,
+    /// number of unreferenced blobs (deleted unless dry_run is set)
+    pub unreferenced: usize,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
@@ -68,6 +110,13 @@ pub enum FlistState {
     Failed,
 }
 
+impl FlistState {
+    /// is_done is true if the job reached a terminal state
+    pub fn is_done(&self) -> bool {
+        matches!(self, FlistState::Created(_) | FlistState::Failed)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
 pub struct FlistStateInfo {
     msg: String,
@@ -91,6 +140,7 @@ pub async fn health_check_handler() -> ResponseResult {
     request_body = FlistBody,
     responses(
         (status = 201, description = "Flist conversion started", body = Job),
+        (status = 400, description = "Bad request"),
         (status = 401, description = "Unauthorized user"),
         (status = 403, description = "Forbidden"),
         (status = 409, description = "Conflict"),
@@ -104,6 +154,7 @@ pub async fn create_flist_handler(
     Json(body): Json<FlistBody>,
 ) -> impl IntoResponse {
     let cfg = state.config.clone();
+    let gc_guard = gc_guard(&state)?;
     let credentials = Some(DockerCredentials {
         username: body.username,
         password: body.password,
@@ -119,7 +170,15 @@ pub async fn create_flist_handler(
         docker_image.push_str(":latest");
     }
 
-    let fl_name = docker_image.replace([':', '/'], "-") + ".fl";
+    let mut fl_name = docker_image.replace([':', '/'], "-");
+    if let Some(platform) = &body.platform {
+        if let Err(err) = docker2fl::parse_platform(platform) {
+            return Err(ResponseError::BadRequest(err.to_string()));
+        }
+        fl_name = format!("{}-{}", fl_name, platform.replace('/', "-"));
+    }
+    let fl_name = fl_name + ".fl";
+    let platform = body.platform;
     let username_dir = std::path::Path::new(&cfg.flist_dir).join(&username);
     let fl_path = username_dir.join(&fl_name);
 
@@ -170,6 +229,7 @@ pub async fn create_flist_handler(
             job.id.clone(),
             FlistState::Accepted(format!("flist '{}' is accepted", &fl_name)),
         );
+    state.metrics.job("accepted");
 
     let flist_download_url = std::path::Path::new(&format!("{}:{}", cfg.host, cfg.port))
         .join(cfg.flist_dir)
@@ -177,6 +237,8 @@ pub async fn create_flist_handler(
         .join(&fl_name);
 
     tokio::spawn(async move {
+        // the garbage collection can run again once the job is done
+        let _gc_guard = gc_guard;
         state
             .jobs_state
             .lock()
@@ -185,6 +247,7 @@ pub async fn create_flist_handler(
                 job.id.clone(),
                 FlistState::Started(format!("flist '{}' is started", fl_name)),
             );
+        state.metrics.job("started");
 
         let container_name = Uuid::new_v4().to_string();
         let docker_tmp_dir =
@@ -192,79 +255,535 @@ pub async fn create_flist_handler(
 
         let (tx, rx) = mpsc::channel();
         let mut docker_to_fl =
-            docker2fl::DockerImageToFlist::new(meta, docker_image, credentials, docker_tmp_dir);
+            docker2fl::DockerImageToFlist::new(meta, docker_image, credentials, docker_tmp_dir)
+                .with_platform(platform);
 
         let res = docker_to_fl.prepare().await;
         if res.is_err() {
-            let _ = tokio::fs::remove_file(&fl_path).await;
-            state
-                .jobs_state
-                .lock()
-                .expect("failed to lock state")
-                .insert(job.id.clone(), FlistState::Failed);
+            job_failed(&state, &job.id, &fl_path).await;
             return;
         }
 
-        let files_count = docker_to_fl.files_count();
-        let st = state.clone();
-        let job_id = job.id.clone();
-        let cloned_fl_path = fl_path.clone();
-        tokio::spawn(async move {
-            let mut progress: f32 = 0.0;
-
-            for _ in 0..files_count - 1 {
-                let step = rx.recv().expect("failed to receive progress") as f32;
-                progress += step;
-                let progress_percentage = progress / files_count as f32 * 100.0;
-                st.jobs_state.lock().expect("failed to lock state").insert(
-                    job_id.clone(),
-                    FlistState::InProgress(FlistStateInfo {
-                        msg: "flist is in progress".to_string(),
-                        progress: progress_percentage,
-                    }),
-                );
-                st.flists_progress
-                    .lock()
-                    .expect("failed to lock state")
-                    .insert(cloned_fl_path.clone(), progress_percentage);
-            }
-        });
+        track_progress(
+            state.clone(),
+            job.id.clone(),
+            fl_path.clone(),
+            docker_to_fl.files_count(),
+            rx,
+        );
 
         let res = docker_to_fl.pack(store, Some(tx)).await;
 
         // remove the file created with the writer if fl creation failed
         if res.is_err() {
             log::error!("failed creation failed with error {:?}", res.err());
-            let _ = tokio::fs::remove_file(&fl_path).await;
-            state
-                .jobs_state
-                .lock()
-                .expect("failed to lock state")
-                .insert(job.id.clone(), FlistState::Failed);
+            job_failed(&state, &job.id, &fl_path).await;
             return;
         }
 
+        job_created(&state, &job.id, fl_path, &flist_download_url);
+    });
+
+    Ok(ResponseResult::FlistCreated(current_job))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/api/fl/tar",
+    request_body(content = TarFlistBody, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "Flist conversion started", body = Job),
+        (status = 400, description = "Bad request"),
+        (status = 401, description = "Unauthorized user"),
+        (status = 403, description = "Forbidden"),
+        (status = 409, description = "Conflict"),
+        (status = 413, description = "Tarball is too large"),
+        (status = 500, description = "Internal server error"),
+    )
+)]
+#[debug_handler]
+pub async fn create_tar_flist_handler(
+    State(state): State<Arc<config::AppState>>,
+    Extension(username): Extension<String>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let cfg = state.config.clone();
+    let gc_guard = gc_guard(&state)?;
+    let tmp_dir = tempdir::TempDir::new("fl-tar").map_err(|err| {
+        log::error!("failed to create tmp dir for tarball with error {}", err);
+        ResponseError::InternalServerError
+    })?;
+    let tar_path = tmp_dir.path().join("rootfs.tar.gz");
+
+    // the tarball is written to disk as it's received, it can be large
+    let mut name = None;
+    let mut uploaded = false;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| ResponseError::BadRequest(err.to_string()))?
+    {
+        match field.name() {
+            Some("name") => {
+                name = Some(
+                    field
+                        .text()
+                        .await
+                        .map_err(|err| ResponseError::BadRequest(err.to_string()))?,
+                )
+            }
+            Some("file") => {
+                save_field(field, &tar_path).await?;
+                uploaded = true;
+            }
+            _ => continue,
+        }
+    }
+
+    let name = name.ok_or_else(|| ResponseError::BadRequest("missing flist name".to_string()))?;
+    if !uploaded {
+        return Err(ResponseError::BadRequest(
+            "missing tarball file".to_string(),
+        ));
+    }
+
+    if !valid_name(&name) {
+        return Err(ResponseError::BadRequest(format!(
+            "invalid flist name '{}'",
+            name
+        )));
+    }
+
+    let fl_name = match name.ends_with(".fl") {
+        true => name,
+        false => format!("{}.fl", name),
+    };
+    let username_dir = std::path::Path::new(&cfg.flist_dir).join(&username);
+    let fl_path = username_dir.join(&fl_name);
+
+    if fl_path.exists() {
+        return Err(ResponseError::Conflict("flist already exists".to_string()));
+    }
+
+    // the entries are checked before the job is accepted
+    let cloned_tar_path = tar_path.clone();
+    let entries =
+        match tokio::task::spawn_blocking(move || validate_tarball(&cloned_tar_path)).await {
+            Ok(Ok(entries)) => entries,
+            Ok(Err(err)) => return Err(ResponseError::BadRequest(err.to_string())),
+            Err(err) => {
+                log::error!("failed to validate tarball with error {}", err);
+                return Err(ResponseError::InternalServerError);
+            }
+        };
+
+    if let Err(err) = fs::create_dir_all(&username_dir) {
+        log::error!(
+            "failed to create user flist directory `{:?}` with error {:?}",
+            &username_dir,
+            err
+        );
+        return Err(ResponseError::InternalServerError);
+    }
+
+    let meta = match Writer::new(&fl_path, true).await {
+        Ok(writer) => writer,
+        Err(err) => {
+            log::error!(
+                "failed to create a new writer for flist `{:?}` with error {}",
+                fl_path,
+                err
+            );
+            return Err(ResponseError::InternalServerError);
+        }
+    };
+
+    let store = match rfs::store::parse_router(&cfg.store_url).await {
+        Ok(s) => s,
+        Err(err) => {
+            log::error!("failed to parse router for store with error {}", err);
+            return Err(ResponseError::InternalServerError);
+        }
+    };
+
+    let job: Job = Job {
+        id: Uuid::new_v4().to_string(),
+    };
+    let current_job = job.clone();
+
+    state
+        .jobs_state
+        .lock()
+        .expect("failed to lock state")
+        .insert(
+            job.id.clone(),
+            FlistState::Accepted(format!("flist '{}' is accepted", &fl_name)),
+        );
+    state.metrics.job("accepted");
+
+    let flist_download_url = std::path::Path::new(&format!("{}:{}", cfg.host, cfg.port))
+        .join(cfg.flist_dir)
+        .join(username)
+        .join(&fl_name);
+
+    tokio::spawn(async move {
+        // the garbage collection can run again once the job is done
+        let _gc_guard = gc_guard;
         state
             .jobs_state
             .lock()
             .expect("failed to lock state")
             .insert(
                 job.id.clone(),
-                FlistState::Created(format!(
-                    "flist {:?} is created successfully",
-                    flist_download_url
-                )),
+                FlistState::Started(format!("flist '{}' is started", fl_name)),
             );
-        state
-            .flists_progress
-            .lock()
-            .expect("failed to lock state")
-            .insert(fl_path, 100.0);
+        state.metrics.job("started");
+
+        // the uploaded tarball is removed once the job is done
+        let _tmp_dir = tmp_dir;
+
+        // the tarball is packed as it's read, nothing is extracted so the ownership,
+        // modes and xattrs of its entries never apply to the server itself
+        let tarball = match tokio::fs::File::open(&tar_path).await {
+            Ok(file) => file,
+            Err(err) => {
+                log::error!("failed to open tarball with error {}", err);
+                job_failed(&state, &job.id, &fl_path).await;
+                return;
+            }
+        };
+
+        let (tx, rx) = mpsc::channel();
+        track_progress(state.clone(), job.id.clone(), fl_path.clone(), entries, rx);
+
+        let res = rfs::pack_tar(
+            meta,
+            store,
+            async_compression::tokio::bufread::GzipDecoder::new(tokio::io::BufReader::new(tarball)),
+            rfs::PackOptions {
+                progress: Some(tx),
+                ..rfs::PackOptions::default()
+            },
+        )
+        .await;
+
+        // remove the file created with the writer if fl creation failed
+        if res.is_err() {
+            log::error!("failed creation failed with error {:?}", res.err());
+            job_failed(&state, &job.id, &fl_path).await;
+            return;
+        }
+
+        job_created(&state, &job.id, fl_path, &flist_download_url);
     });
 
     Ok(ResponseResult::FlistCreated(current_job))
 }
 
+async fn save_field(mut field: Field<'_>, path: &std::path::Path) -> Result<(), ResponseError> {
+    let mut file = tokio::fs::File::create(path).await.map_err(|err| {
+        log::error!("failed to create file {:?} with error {}", path, err);
+        ResponseError::InternalServerError
+    })?;
+
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|err| ResponseError::BadRequest(err.to_string()))?
+    {
+        file.write_all(&chunk).await.map_err(|err| {
+            log::error!("failed to write file {:?} with error {}", path, err);
+            ResponseError::InternalServerError
+        })?;
+    }
+
+    file.flush().await.map_err(|err| {
+        log::error!("failed to write file {:?} with error {}", path, err);
+        ResponseError::InternalServerError
+    })
+}
+
+fn open_tarball(
+    path: &std::path::Path,
+) -> Result<tar::Archive<flate2::read::GzDecoder<fs::File>>, Error> {
+    let file = fs::File::open(path)?;
+    Ok(tar::Archive::new(flate2::read::GzDecoder::new(file)))
+}
+
+/// gc_guard keeps the garbage collection from running while a flist is created, it
+/// must be held until the flist job is done
+fn gc_guard(
+    state: &config::AppState,
+) -> Result<tokio::sync::OwnedRwLockReadGuard<()>, ResponseError> {
+    state.gc_lock.clone().try_read_owned().map_err(|_| {
+        ResponseError::Conflict("garbage collection is running, try again later".to_string())
+    })
+}
+
+/// valid_name checks that a user or flist name can be used as a file name
+/// inside the flists directory
+pub fn valid_name(name: &str) -> bool {
+    static VALID: OnceLock<regex::Regex> = OnceLock::new();
+    let valid = VALID.get_or_init(|| regex::Regex::new(r"^[a-zA-Z0-9_.-]+$").unwrap());
+
+    valid.is_match(name) && !name.starts_with('.')
+}
+
+/// validate_tarball makes sure no entry of the tarball refers to a path outside
+/// of the flist root, it returns the number of entries
+fn validate_tarball(path: &std::path::Path) -> Result<usize, Error> {
+    use std::path::Component;
+
+    let escapes = |path: &std::path::Path| {
+        path.components().any(|c| {
+            matches!(
+                c,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_)
+            )
+        })
+    };
+
+    let mut entries = 0;
+    let mut archive = open_tarball(path)?;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        entries += 1;
+        let path = entry.path()?;
+        if escapes(&path) {
+            anyhow::bail!("invalid tarball entry '{}'", path.display());
+        }
+
+        if entry.header().entry_type().is_hard_link() {
+            if let Some(target) = entry.link_name()? {
+                if escapes(&target) {
+                    anyhow::bail!(
+                        "invalid tarball hard link '{}' -> '{}'",
+                        path.display(),
+                        target.display()
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// track_progress updates the job state with the progress of the flist
+/// creation, each file packed is sent on the receiver. The receiver blocks so
+/// it's read on the blocking thread pool.
+fn track_progress(
+    state: Arc<config::AppState>,
+    job_id: String,
+    fl_path: PathBuf,
+    files_count: usize,
+    rx: mpsc::Receiver<rfs::PackEvent>,
+) {
+    tokio::task::spawn_blocking(move || {
+        let mut progress: f32 = 0.0;
+
+        // the channel is closed once the pack is done
+        while let Ok(event) = rx.recv() {
+            if event != rfs::PackEvent::Entry {
+                continue;
+            }
+
+            progress += 1.0;
+            let progress_percentage = progress / files_count as f32 * 100.0;
+            state
+                .jobs_state
+                .lock()
+                .expect("failed to lock state")
+                .insert(
+                    job_id.clone(),
+                    FlistState::InProgress(FlistStateInfo {
+                        msg: "flist is in progress".to_string(),
+                        progress: progress_percentage,
+                    }),
+                );
+            state
+                .flists_progress
+                .lock()
+                .expect("failed to lock state")
+                .insert(fl_path.clone(), progress_percentage);
+        }
+    });
+}
+
+async fn job_failed(state: &config::AppState, job_id: &str, fl_path: &std::path::Path) {
+    // the flist might not be finished, so the WAL files next to it are removed too
+    if let Err(err) = rfs::fungi::meta::remove(fl_path).await {
+        log::error!("failed to remove flist `{:?}` with error {}", fl_path, err);
+    }
+    state
+        .jobs_state
+        .lock()
+        .expect("failed to lock state")
+        .insert(job_id.to_string(), FlistState::Failed);
+    state.metrics.job("failed");
+}
+
+fn job_created(
+    state: &config::AppState,
+    job_id: &str,
+    fl_path: PathBuf,
+    flist_download_url: &std::path::Path,
+) {
+    state
+        .jobs_state
+        .lock()
+        .expect("failed to lock state")
+        .insert(
+            job_id.to_string(),
+            FlistState::Created(format!(
+                "flist {:?} is created successfully",
+                flist_download_url
+            )),
+        );
+    state.metrics.job("created");
+    state
+        .flists_progress
+        .lock()
+        .expect("failed to lock state")
+        .insert(fl_path, 100.0);
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/api/fl/{username}/{flist}",
+    responses(
+        (status = 200, description = "Flist deleted"),
+        (status = 400, description = "Bad request"),
+        (status = 401, description = "Unauthorized user"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Flist not found"),
+        (status = 409, description = "Flist is still being created"),
+        (status = 500, description = "Internal server error"),
+    ),
+    params(
+        ("username" = String, Path, description = "owner of the flist"),
+        ("flist" = String, Path, description = "flist file name")
+    )
+)]
+#[debug_handler]
+pub async fn delete_flist_handler(
+    State(state): State<Arc<config::AppState>>,
+    Extension(current_user): Extension<String>,
+    Path((username, flist)): Path<(String, String)>,
+) -> impl IntoResponse {
+    if current_user != username && !state.config.admins.contains(&current_user) {
+        return Err(ResponseError::Forbidden(
+            "You are not allowed to delete flists of other users".to_string(),
+        ));
+    }
+
+    let fl_path = format!("{}/{}/{}", state.config.flist_dir, username, flist);
+    if let Err(err) = validate_flist_path(&state, &fl_path).await {
+        if !std::path::Path::new(&fl_path).is_file() {
+            return Err(ResponseError::NotFound(format!(
+                "flist '{}' doesn't exist",
+                fl_path
+            )));
+        }
+        return Err(ResponseError::BadRequest(err.to_string()));
+    }
+
+    let path = PathBuf::from(&fl_path);
+    let mut progress = state.flists_progress.lock().expect("failed to lock state");
+    if matches!(progress.get(&path), Some(p) if *p < 100.0) {
+        return Err(ResponseError::Conflict(format!(
+            "flist '{}' is still being created",
+            fl_path
+        )));
+    }
+
+    if let Err(err) = fs::remove_file(&path) {
+        log::error!("failed to delete flist '{}' with error {}", fl_path, err);
+        return Err(ResponseError::InternalServerError);
+    }
+    progress.remove(&path);
+
+    Ok(ResponseResult::FlistDeleted)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/api/gc",
+    request_body = GcBody,
+    responses(
+        (status = 200, description = "Garbage collection done", body = GcResponse),
+        (status = 401, description = "Unauthorized user"),
+        (status = 403, description = "Forbidden"),
+        (status = 409, description = "Flists are being created"),
+        (status = 500, description = "Internal server error"),
+    )
+)]
+#[debug_handler]
+pub async fn gc_handler(
+    State(state): State<Arc<config::AppState>>,
+    Extension(current_user): Extension<String>,
+    Json(body): Json<GcBody>,
+) -> impl IntoResponse {
+    if !state.config.admins.contains(&current_user) {
+        return Err(ResponseError::Forbidden(
+            "Only admins can run the garbage collection".to_string(),
+        ));
+    }
+
+    // blocks of flists that are being created are uploaded before they are
+    // added to the flist, so they would look unreferenced. No job can start
+    // until the garbage collection is done.
+    let _gc = state.gc_lock.try_write().map_err(|_| {
+        ResponseError::Conflict("flists are being created, try again later".to_string())
+    })?;
+
+    // flists are stored as <flist_dir>/<username>/<flist>.fl
+    let mut readers = Vec::new();
+    for entry in walkdir::WalkDir::new(&state.config.flist_dir)
+        .min_depth(2)
+        .max_depth(2)
+    {
+        let entry = entry.map_err(|err| {
+            log::error!("failed to list flists with error {}", err);
+            ResponseError::InternalServerError
+        })?;
+
+        if !entry.file_type().is_file() || entry.path().extension() != Some("fl".as_ref()) {
+            continue;
+        }
+
+        let reader = Reader::new(entry.path()).await.map_err(|err| {
+            log::error!(
+                "failed to open flist '{}' with error {}",
+                entry.path().display(),
+                err
+            );
+            ResponseError::InternalServerError
+        })?;
+        readers.push(reader);
+    }
+
+    let store = rfs::store::parse_router(&state.config.store_url)
+        .await
+        .map_err(|err| {
+            log::error!("failed to parse router for store with error {}", err);
+            ResponseError::InternalServerError
+        })?;
+
+    let unreferenced = rfs::gc_all(&readers, &store, body.dry_run)
+        .await
+        .map_err(|err| {
+            log::error!("failed to garbage collect the stores with error {}", err);
+            ResponseError::InternalServerError
+        })?;
+
+    Ok(ResponseResult::GarbageCollected(GcResponse {
+        dry_run: body.dry_run,
+        flists: readers.len(),
+        unreferenced: unreferenced.len(),
+    }))
+}
+
 #[utoipa::path(
     get,
     path = "/v1/api/fl/{job_id}",
@@ -326,19 +845,136 @@ pub async fn get_flist_state_handler(
     }
 }
 
+const STREAM_INTERVAL: Duration = Duration::from_millis(500);
+
+#[utoipa::path(
+    get,
+    path = "/v1/api/fl/{job_id}/stream",
+    responses(
+        (status = 200, description = "Server sent events of the flist state, each event data is the same as the flist state response. A `gone` event ends the stream if the state was fetched from the state endpoint before the job is done", content_type = "text/event-stream", body = FlistState),
+        (status = 404, description = "Flist not found"),
+        (status = 401, description = "Unauthorized user"),
+        (status = 403, description = "Forbidden"),
+    ),
+    params(
+        ("job_id" = String, Path, description = "flist job id")
+    )
+)]
+#[debug_handler]
+pub async fn stream_flist_state_handler(
+    Path(flist_job_id): Path<String>,
+    State(state): State<Arc<config::AppState>>,
+) -> impl IntoResponse {
+    if !state
+        .jobs_state
+        .lock()
+        .expect("failed to lock state")
+        .contains_key(&flist_job_id)
+    {
+        return Err(ResponseError::NotFound("flist doesn't exist".to_string()));
+    }
+
+    // an event is sent each time the state changes, the stream ends once the job is
+    // created or failed. The job is kept so its result can still be fetched with the
+    // state handler (which removes it), if that happens before the job is done a
+    // `gone` event is sent before the stream ends.
+    // The stream is dropped if the client disconnects.
+    let mut interval = tokio::time::interval(STREAM_INTERVAL);
+    let mut last: Option<FlistState> = None;
+    let mut gone = false;
+    let events = stream::poll_fn(move |cx| loop {
+        if gone || last.as_ref().is_some_and(FlistState::is_done) {
+            return std::task::Poll::Ready(None);
+        }
+        futures_util::ready!(interval.poll_tick(cx));
+
+        let jobs = state.jobs_state.lock().expect("failed to lock state");
+        let current = match jobs.get(&flist_job_id) {
+            Some(current) => current.clone(),
+            None => {
+                gone = true;
+                let event = Event::default()
+                    .event("gone")
+                    .data("flist state was already consumed by another request");
+                return std::task::Poll::Ready(Some(Ok(event)));
+            }
+        };
+
+        if last.as_ref() != Some(&current) {
+            let event = Event::default()
+                .event("state")
+                .json_data(serde_json::json!({ "flist_state": current }));
+            last = Some(current);
+            return std::task::Poll::Ready(Some(event));
+        }
+    });
+
+    Ok(ResponseResult::FlistStateStream(
+        Sse::new(events.boxed()).keep_alive(KeepAlive::default()),
+    ))
+}
+
+const DEFAULT_PAGE_LIMIT: usize = 100;
+const MAX_PAGE_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct Pagination {
+    /// page number starting from 1, defaults to 1
+    page: Option<usize>,
+    /// number of flists per page, defaults to 100 and can't exceed 1000
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct Filter {
+    /// only list the flists of this user
+    username: Option<String>,
+    /// only list the flists whose name contains this value
+    name: Option<String>,
+    /// minimum flist size in bytes
+    min_size: Option<u64>,
+    /// maximum flist size in bytes
+    max_size: Option<u64>,
+}
+
+impl Filter {
+    fn matches(&self, username: &str, file: &FileInfo) -> bool {
+        self.username.as_ref().map_or(true, |u| u == username)
+            && self
+                .name
+                .as_ref()
+                .map_or(true, |n| file.name.contains(n.as_str()))
+            && self.min_size.map_or(true, |min| file.size >= min)
+            && self.max_size.map_or(true, |max| file.size <= max)
+    }
+}
+
 #[utoipa::path(
 	get,
 	path = "/v1/api/fl",
+	params(Pagination, Filter),
 	responses(
         (status = 200, description = "Listing flists", body = HashMap<String, Vec<FileInfo>>),
         (status = 401, description = "Unauthorized user"),
         (status = 403, description = "Forbidden"),
+        (status = 400, description = "Bad request"),
         (status = 500, description = "Internal server error"),
 	)
 )]
 #[debug_handler]
-pub async fn list_flists_handler(State(state): State<Arc<config::AppState>>) -> impl IntoResponse {
-    let mut flists: HashMap<String, Vec<FileInfo>> = HashMap::new();
+pub async fn list_flists_handler(
+    State(state): State<Arc<config::AppState>>,
+    Query(pagination): Query<Pagination>,
+    Query(filter): Query<Filter>,
+) -> impl IntoResponse {
+    let page = pagination.page.unwrap_or(1);
+    let limit = pagination.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if page == 0 || limit == 0 || limit > MAX_PAGE_LIMIT {
+        return Err(ResponseError::BadRequest(format!(
+            "page must be at least 1 and limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
 
     let rs: Result<Vec<FileInfo>, std::io::Error> =
         visit_dir_one_level(&state.config.flist_dir, &state).await;
@@ -351,17 +987,37 @@ pub async fn list_flists_handler(State(state): State<Arc<config::AppState>>) ->
         }
     };
 
+    // flists are sorted by username then name so pages are stable
+    let mut matched: Vec<(String, FileInfo)> = Vec::new();
     for file in files {
-        if !file.is_file {
-            let flists_per_username = visit_dir_one_level(&file.path_uri, &state).await;
-            match flists_per_username {
-                Ok(files) => flists.insert(file.name, files),
-                Err(e) => {
-                    log::error!("failed to list flists per username with error: {}", e);
-                    return Err(ResponseError::InternalServerError);
-                }
-            };
+        if file.is_file || filter.username.as_ref().is_some_and(|u| u != &file.name) {
+            continue;
+        }
+
+        let flists_per_username = match visit_dir_one_level(&file.path_uri, &state).await {
+            Ok(flists) => flists,
+            Err(e) => {
+                log::error!("failed to list flists per username with error: {}", e);
+                return Err(ResponseError::InternalServerError);
+            }
         };
+
+        matched.extend(
+            flists_per_username
+                .into_iter()
+                .filter(|flist| filter.matches(&file.name, flist))
+                .map(|flist| (file.name.clone(), flist)),
+        );
+    }
+    matched.sort_by(|(a_user, a), (b_user, b)| (a_user, &a.name).cmp(&(b_user, &b.name)));
+
+    let mut flists: HashMap<String, Vec<FileInfo>> = HashMap::new();
+    for (username, flist) in matched
+        .into_iter()
+        .skip((page - 1).saturating_mul(limit))
+        .take(limit)
+    {
+        flists.entry(username).or_default().push(flist);
     }
 
     Ok(ResponseResult::Flists(flists))
@@ -494,7 +1150,10 @@ async fn get_flist_content(fl_path: &String) -> Result<Vec<PathBuf>, Error> {
         }
     };
 
-    match meta.walk(&mut visitor).await {
+    match meta
+        .walk_streaming(rfs::fungi::meta::WALK_BATCH, &mut visitor)
+        .await
+    {
         Ok(()) => return Ok(visitor.into_inner()),
         Err(err) => {
             log::error!(
@@ -529,3 +1188,153 @@ impl rfs::fungi::meta::WalkVisitor for ReadVisitor {
         Ok(rfs::fungi::meta::Walk::Continue)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn state() -> Arc<config::AppState> {
+        state_with_config(config::Config::default())
+    }
+
+    fn state_with_config(config: config::Config) -> Arc<config::AppState> {
+        Arc::new(config::AppState {
+            jobs_state: Mutex::new(HashMap::new()),
+            flists_progress: Mutex::new(HashMap::new()),
+            db: Arc::new(crate::db::MapDB::new(&[])),
+            config,
+            metrics: crate::metrics::Metrics::new().unwrap(),
+            gc_lock: Arc::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_gc_lock() {
+        let state = state_with_config(config::Config {
+            admins: vec!["admin".into()],
+            ..config::Config::default()
+        });
+        let admin = Extension("admin".to_string());
+
+        // a running job keeps the garbage collection from running
+        let job = gc_guard(&state).ok().unwrap();
+        let response = gc_handler(
+            State(state.clone()),
+            admin.clone(),
+            Json(GcBody { dry_run: true }),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::CONFLICT);
+        drop(job);
+
+        // and no job can start while the garbage collection runs
+        let gc = state.gc_lock.try_write().unwrap();
+        assert!(gc_guard(&state).is_err());
+        drop(gc);
+        assert!(gc_guard(&state).is_ok());
+    }
+
+    #[test]
+    fn test_valid_name() {
+        for name in ["user1", "my-image_1.0.fl", "a.b"] {
+            assert!(valid_name(name), "{}", name);
+        }
+        for name in ["", ".", "..", ".hidden", "a/b", "../a", "a b"] {
+            assert!(!valid_name(name), "{}", name);
+        }
+    }
+
+    async fn next_event(body: &mut axum::body::BodyDataStream) -> Option<String> {
+        body.next()
+            .await
+            .map(|data| String::from_utf8(data.unwrap().to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_stream_flist_state() {
+        let state = state();
+        state.jobs_state.lock().unwrap().insert(
+            "job".into(),
+            FlistState::Started("flist 'test.fl' is started".into()),
+        );
+
+        let response = stream_flist_state_handler(Path("job".into()), State(state.clone()))
+            .await
+            .into_response();
+        let mut body = response.into_body().into_data_stream();
+        assert!(next_event(&mut body)
+            .await
+            .unwrap()
+            .starts_with("event: state\n"));
+
+        state
+            .jobs_state
+            .lock()
+            .unwrap()
+            .insert("job".into(), FlistState::Created("test.fl".into()));
+        assert!(next_event(&mut body).await.unwrap().contains("Created"));
+        assert_eq!(next_event(&mut body).await, None);
+
+        // the stream doesn't consume the result of the job
+        let response = get_flist_state_handler(Path("job".into()), State(state.clone()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert!(state.jobs_state.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stream_flist_state_gone() {
+        let state = state();
+        state.jobs_state.lock().unwrap().insert(
+            "job".into(),
+            FlistState::Started("flist 'test.fl' is started".into()),
+        );
+
+        let response = stream_flist_state_handler(Path("job".into()), State(state.clone()))
+            .await
+            .into_response();
+        let mut body = response.into_body().into_data_stream();
+        assert!(next_event(&mut body)
+            .await
+            .unwrap()
+            .starts_with("event: state\n"));
+
+        // the result was fetched by another request
+        state.jobs_state.lock().unwrap().remove("job");
+        assert!(next_event(&mut body)
+            .await
+            .unwrap()
+            .starts_with("event: gone\n"));
+        assert_eq!(next_event(&mut body).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_job_failed() {
+        const PATH: &str = "/tmp/fl-server-job-failed.fl";
+        let state = state();
+
+        // a failed job leaves an unfinished flist behind
+        let meta = Writer::new(PATH, true).await.unwrap();
+        meta.inode(rfs::fungi::meta::Inode::default())
+            .await
+            .unwrap();
+        assert!(std::path::Path::new("/tmp/fl-server-job-failed.fl-wal").exists());
+
+        job_failed(&state, "job", std::path::Path::new(PATH)).await;
+
+        for path in [
+            PATH,
+            "/tmp/fl-server-job-failed.fl-wal",
+            "/tmp/fl-server-job-failed.fl-shm",
+        ] {
+            assert!(!std::path::Path::new(path).exists(), "{}", path);
+        }
+        assert!(matches!(
+            state.jobs_state.lock().unwrap().get("job"),
+            Some(FlistState::Failed)
+        ));
+    }
+}
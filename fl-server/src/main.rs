@@ -2,21 +2,23 @@ mod auth;
 mod config;
 mod db;
 mod handlers;
+mod metrics;
 mod response;
 mod serve_flists;
 
 use anyhow::{Context, Result};
 use axum::{
     error_handling::HandleErrorLayer,
+    extract::DefaultBodyLimit,
     http::StatusCode,
     middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post},
     BoxError, Router,
 };
 use clap::{ArgAction, Parser};
 use hyper::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    header::{HeaderName, ACCEPT, AUTHORIZATION, CONTENT_TYPE},
     Method,
 };
 use std::{
@@ -33,6 +35,9 @@ use tower_http::{cors::Any, trace::TraceLayer};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+// timeout of all the requests except the tarball uploads
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Parser, Debug)]
 #[clap(name ="fl-server", author, version = env!("GIT_VERSION"), about, long_about = None)]
 struct Options {
@@ -72,43 +77,59 @@ async fn app() -> Result<()> {
         .await
         .context("failed to parse config file")?;
 
-    let db = Arc::new(db::MapDB::new(&config.users.clone()));
+    // passwords of the configured users are hashed like the passwords of registered users
+    let users = config
+        .users
+        .iter()
+        .map(|user| {
+            Ok(db::User {
+                username: user.username.clone(),
+                password: auth::hash_password(&user.password)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .context("failed to hash users passwords")?;
+    let db = Arc::new(db::MapDB::new(&users));
 
     let app_state = Arc::new(config::AppState {
         jobs_state: Mutex::new(HashMap::new()),
         flists_progress: Mutex::new(HashMap::new()),
         db,
         config,
+        metrics: metrics::Metrics::new().context("failed to create metrics")?,
+        gc_lock: Arc::default(),
     });
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST])
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([
+            AUTHORIZATION,
+            ACCEPT,
+            CONTENT_TYPE,
+            HeaderName::from_static(auth::API_KEY_HEADER),
+        ]);
 
-    let v1_routes = Router::new()
-        .route("/v1/api", get(handlers::health_check_handler))
-        .route("/v1/api/signin", post(auth::sign_in_handler))
-        .route(
-            "/v1/api/fl",
-            post(handlers::create_flist_handler).layer(middleware::from_fn_with_state(
-                app_state.clone(),
-                auth::authorize,
-            )),
-        )
-        .route(
-            "/v1/api/fl/:job_id",
-            get(handlers::get_flist_state_handler).layer(middleware::from_fn_with_state(
-                app_state.clone(),
-                auth::authorize,
-            )),
-        )
-        .route(
-            "/v1/api/fl/preview/:flist_path",
-            get(handlers::preview_flist_handler),
-        )
-        .route("/v1/api/fl", get(handlers::list_flists_handler))
-        .route("/*path", get(serve_flists::serve_flists));
+    let v1_routes = v1_routes(&app_state);
+
+    let metrics_routes = Router::new().route("/metrics", get(metrics::metrics_handler));
+    let v1_routes = match &app_state.config.metrics_address {
+        Some(address) => {
+            let listener = tokio::net::TcpListener::bind(address)
+                .await
+                .context("failed to bind metrics address")?;
+            log::info!("metrics are served at {}", address);
+
+            let metrics_app = metrics_routes.with_state(Arc::clone(&app_state));
+            tokio::spawn(async move {
+                if let Err(err) = axum::serve(listener, metrics_app).await {
+                    log::error!("failed to serve metrics: {}", err);
+                }
+            });
+            v1_routes
+        }
+        None => v1_routes.merge(metrics_routes),
+    };
 
     let app = Router::new()
         .merge(
@@ -121,7 +142,6 @@ async fn app() -> Result<()> {
                 .layer(HandleErrorLayer::new(handle_error))
                 .load_shed()
                 .concurrency_limit(1024)
-                .timeout(Duration::from_secs(10))
                 .layer(TraceLayer::new_for_http()),
         )
         .with_state(Arc::clone(&app_state))
@@ -146,6 +166,100 @@ async fn app() -> Result<()> {
     Ok(())
 }
 
+/// v1_routes are the routes of the api and of the flists. Requests time out after
+/// REQUEST_TIMEOUT, except the tarball uploads which have their own timeout
+fn v1_routes(app_state: &Arc<config::AppState>) -> Router<Arc<config::AppState>> {
+    // a large tarball can take much longer to upload than the other requests
+    let upload_routes = Router::new()
+        .route(
+            "/v1/api/fl/tar",
+            post(handlers::create_tar_flist_handler)
+                .layer(DefaultBodyLimit::max(
+                    app_state.config.max_upload_size_mb * 1024 * 1024,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth::authorize,
+                )),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_error))
+                .timeout(Duration::from_secs(app_state.config.upload_timeout_secs)),
+        );
+
+    Router::new()
+        .route("/v1/api", get(handlers::health_check_handler))
+        .route("/v1/api/signin", post(auth::sign_in_handler))
+        .route("/v1/api/refresh", post(auth::refresh_handler))
+        .route("/v1/api/register", post(auth::register_handler))
+        .route(
+            "/v1/api/keys",
+            post(auth::create_api_key_handler).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth::authorize,
+            )),
+        )
+        .route(
+            "/v1/api/keys/:id",
+            delete(auth::revoke_api_key_handler).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth::authorize,
+            )),
+        )
+        .route(
+            "/v1/api/fl",
+            post(handlers::create_flist_handler).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth::authorize,
+            )),
+        )
+        .route(
+            "/v1/api/fl/:job_id",
+            get(handlers::get_flist_state_handler).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth::authorize,
+            )),
+        )
+        .route(
+            "/v1/api/fl/:job_id/stream",
+            get(handlers::stream_flist_state_handler).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth::authorize,
+            )),
+        )
+        .route(
+            "/v1/api/gc",
+            post(handlers::gc_handler).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth::authorize,
+            )),
+        )
+        .route(
+            "/v1/api/fl/:username/:flist",
+            delete(handlers::delete_flist_handler).layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth::authorize,
+            )),
+        )
+        .route(
+            "/v1/api/fl/preview/:flist_path",
+            get(handlers::preview_flist_handler),
+        )
+        .route("/v1/api/fl", get(handlers::list_flists_handler))
+        .route("/*path", get(serve_flists::serve_flists))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_error))
+                .timeout(REQUEST_TIMEOUT),
+        )
+        .merge(upload_routes)
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            metrics::track,
+        ))
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -184,3 +298,85 @@ async fn handle_error(error: BoxError) -> impl IntoResponse {
         Cow::from(format!("Unhandled internal error: {}", error)),
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::{Body, Bytes};
+    use futures_util::StreamExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_slow_tarball_upload() {
+        const ROOT: &str = "/tmp/fl-server-upload-test";
+        let _ = std::fs::remove_dir_all(ROOT);
+
+        let state = Arc::new(config::AppState {
+            jobs_state: Mutex::new(HashMap::new()),
+            flists_progress: Mutex::new(HashMap::new()),
+            db: Arc::new(db::MapDB::new(&[db::User {
+                username: "user".into(),
+                password: "password".into(),
+            }])),
+            config: config::Config {
+                store_url: vec![format!("dir://{}/store", ROOT)],
+                flist_dir: format!("{}/flists", ROOT),
+                max_upload_size_mb: 1,
+                upload_timeout_secs: 60,
+                ..config::Config::default()
+            },
+            metrics: metrics::Metrics::new().unwrap(),
+            gc_lock: Arc::default(),
+        });
+        state
+            .db
+            .create_api_key("key", "user", &sha256::digest("secret"));
+
+        let mut tarball = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tarball
+            .append_data(&mut header, "file", &b"data"[..])
+            .unwrap();
+        let tarball = tarball.into_inner().unwrap().finish().unwrap();
+
+        let head = "--boundary\r\n\
+            Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+            slow\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"rootfs.tar.gz\"\r\n\
+            Content-Type: application/gzip\r\n\r\n";
+        let parts = vec![
+            Bytes::from(head),
+            Bytes::from(tarball),
+            Bytes::from("\r\n--boundary--\r\n"),
+        ];
+
+        // the tarball takes longer to upload than the timeout of the other requests
+        let body = futures_util::stream::iter(parts.into_iter().enumerate()).then(
+            |(i, part)| async move {
+                if i == 1 {
+                    tokio::time::sleep(REQUEST_TIMEOUT + Duration::from_secs(1)).await;
+                }
+                Ok::<_, std::io::Error>(part)
+            },
+        );
+        let request = axum::http::Request::post("/v1/api/fl/tar")
+            .header(auth::API_KEY_HEADER, "secret")
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+            .body(Body::from_stream(body))
+            .unwrap();
+
+        let response = v1_routes(&state)
+            .with_state(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use crate::{config, handlers::FlistState, response::ResponseError, response::ResponseResult};
+
+/// Metrics holds the prometheus metrics of the server
+pub struct Metrics {
+    registry: Registry,
+    jobs: IntCounterVec,
+    jobs_state: IntGaugeVec,
+    requests: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("fl_server".into()), None)?;
+
+        let jobs = IntCounterVec::new(
+            Opts::new("flist_jobs_total", "number of flist jobs per reached state"),
+            &["state"],
+        )?;
+        let jobs_state = IntGaugeVec::new(
+            Opts::new("flist_jobs", "number of flist jobs currently in each state"),
+            &["state"],
+        )?;
+        let requests = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "latency of the http requests",
+            ),
+            &["method", "path", "status"],
+        )?;
+
+        registry.register(Box::new(jobs.clone()))?;
+        registry.register(Box::new(jobs_state.clone()))?;
+        registry.register(Box::new(requests.clone()))?;
+
+        Ok(Self {
+            registry,
+            jobs,
+            jobs_state,
+            requests,
+        })
+    }
+
+    /// job records that a flist job reached the given state
+    pub fn job(&self, state: &str) {
+        self.jobs.with_label_values(&[state]).inc();
+    }
+
+    /// render encodes all the metrics in the prometheus text format, the jobs
+    /// gauges are computed from the current jobs states
+    pub fn render(&self, jobs: &HashMap<String, FlistState>) -> prometheus::Result<String> {
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for state in jobs.values() {
+            *counts.entry(state_name(state)).or_default() += 1;
+        }
+
+        for state in ["accepted", "started", "in_progress", "created", "failed"] {
+            self.jobs_state
+                .with_label_values(&[state])
+                .set(counts.get(state).copied().unwrap_or_default());
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+fn state_name(state: &FlistState) -> &'static str {
+    match state {
+        FlistState::Accepted(_) => "accepted",
+        FlistState::Started(_) => "started",
+        FlistState::InProgress(_) => "in_progress",
+        FlistState::Created(_) => "created",
+        FlistState::Failed => "failed",
+    }
+}
+
+/// track is a middleware that records the latency of each request
+pub async fn track(
+    State(state): State<Arc<config::AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    // the route is used instead of the uri to keep the number of labels bounded
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unknown".into());
+    let method = req.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+
+    state
+        .metrics
+        .requests
+        .with_label_values(&[&method, &path, response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
+
+pub async fn metrics_handler(State(state): State<Arc<config::AppState>>) -> impl IntoResponse {
+    let jobs = state
+        .jobs_state
+        .lock()
+        .expect("failed to lock state")
+        .clone();
+
+    match state.metrics.render(&jobs) {
+        Ok(metrics) => Ok(ResponseResult::Metrics(metrics)),
+        Err(err) => {
+            log::error!("failed to render metrics: {}", err);
+            Err(ResponseError::InternalServerError)
+        }
+    }
+}
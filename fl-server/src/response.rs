@@ -3,17 +3,21 @@ use std::collections::HashMap;
 use askama::Template;
 use axum::{
     body::Body,
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
+    http::{header, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        Html, IntoResponse, Response,
+    },
     Json,
 };
+use futures_util::stream::BoxStream;
 use serde::Serialize;
 use utoipa::ToSchema;
 
 use crate::{
-    auth::SignInResponse,
+    auth::{ApiKeyResponse, SignInResponse},
     config::Job,
-    handlers::{FlistState, PreviewResponse},
+    handlers::{FlistState, GcResponse, PreviewResponse},
 };
 
 #[derive(Serialize, ToSchema)]
@@ -73,16 +77,26 @@ impl IntoResponse for ResponseError {
     }
 }
 
+pub type FlistStateStream = Sse<BoxStream<'static, Result<Event, axum::Error>>>;
+
 #[derive(ToSchema)]
 pub enum ResponseResult {
     Health,
     FlistCreated(Job),
     FlistState(FlistState),
+    FlistStateStream(FlistStateStream),
+    FlistDeleted,
+    Metrics(String),
+    GarbageCollected(GcResponse),
     Flists(HashMap<String, Vec<FileInfo>>),
     PreviewFlist(PreviewResponse),
     SignedIn(SignInResponse),
+    Registered,
+    ApiKeyCreated(ApiKeyResponse),
+    ApiKeyRevoked,
     DirTemplate(DirListTemplate),
     Res(hyper::Response<tower_http::services::fs::ServeFileSystemResponseBody>),
+    NotModified(String),
 }
 
 impl IntoResponse for ResponseResult {
@@ -94,7 +108,33 @@ impl IntoResponse for ResponseResult {
             )
                 .into_response(),
             ResponseResult::SignedIn(token) => (StatusCode::CREATED, Json(token)).into_response(),
+            ResponseResult::ApiKeyCreated(key) => (StatusCode::CREATED, Json(key)).into_response(),
+            ResponseResult::ApiKeyRevoked => (
+                StatusCode::OK,
+                Json(serde_json::json!({"msg": "api key revoked successfully"})),
+            )
+                .into_response(),
+            ResponseResult::Registered => (
+                StatusCode::CREATED,
+                Json(serde_json::json!({"msg": "user registered successfully"})),
+            )
+                .into_response(),
             ResponseResult::FlistCreated(job) => (StatusCode::CREATED, Json(job)).into_response(),
+            ResponseResult::GarbageCollected(report) => {
+                (StatusCode::OK, Json(report)).into_response()
+            }
+            ResponseResult::Metrics(metrics) => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                metrics,
+            )
+                .into_response(),
+            ResponseResult::FlistStateStream(stream) => stream.into_response(),
+            ResponseResult::FlistDeleted => (
+                StatusCode::OK,
+                Json(serde_json::json!({"msg": "flist deleted successfully"})),
+            )
+                .into_response(),
             ResponseResult::FlistState(flist_state) => (
                 StatusCode::OK,
                 Json(serde_json::json!({
@@ -118,6 +158,9 @@ impl IntoResponse for ResponseResult {
                 }
             },
             ResponseResult::Res(res) => res.map(axum::body::Body::new),
+            ResponseResult::NotModified(etag) => {
+                (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response()
+            }
         }
     }
 }
@@ -6,7 +6,7 @@ use tower_http::services::ServeDir;
 
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    http::{header, HeaderValue, Request, StatusCode},
     response::IntoResponse,
 };
 use axum_macros::debug_handler;
@@ -27,9 +27,33 @@ pub async fn serve_flists(
 ) -> impl IntoResponse {
     let path = req.uri().path().to_string();
 
+    // flists only change when they are re-created, clients (and CDNs) can
+    // revalidate their copy instead of downloading it again
+    let etag = match validate_path(&path) {
+        Ok(full_path) => flist_etag(&full_path).await,
+        Err(_) => None,
+    };
+    if let Some(etag) = &etag {
+        let matched = req
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| etag_matches(value, etag))
+            .unwrap_or(false);
+        if matched {
+            return Ok(ResponseResult::NotModified(etag.clone()));
+        }
+    }
+
     return match ServeDir::new("").oneshot(req).await {
-        Ok(res) => {
+        Ok(mut res) => {
             let status = res.status();
+            if let (true, Some(etag)) = (status.is_success(), &etag) {
+                if let Ok(value) = HeaderValue::from_str(etag) {
+                    res.headers_mut().insert(header::ETAG, value);
+                }
+            }
+
             match status {
                 StatusCode::NOT_FOUND => {
                     let full_path = match validate_path(&path) {
@@ -78,6 +102,36 @@ pub async fn serve_flists(
     };
 }
 
+/// flist_etag returns the entity tag of the file at the given path, derived from its
+/// size and modification time. Directories don't have an entity tag.
+async fn flist_etag(path: &std::path::Path) -> Option<String> {
+    let meta = tokio::fs::metadata(path).await.ok()?;
+    if !meta.is_file() {
+        return None;
+    }
+
+    let modified = meta
+        .modified()
+        .ok()?
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .ok()?;
+
+    Some(format!(
+        "\"{:x}-{:x}-{:x}\"",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        meta.len()
+    ))
+}
+
+/// etag_matches checks an If-None-Match header value against the entity tag, with
+/// the weak comparison required for If-None-Match
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+    })
+}
+
 fn validate_path(path: &str) -> io::Result<PathBuf> {
     let path = path.trim_start_matches('/');
     let path = percent_decode(path.as_ref()).decode_utf8_lossy();
@@ -149,3 +203,27 @@ pub async fn visit_dir_one_level<P: AsRef<std::path::Path>>(
 
     Ok(files)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_etag_matches() {
+        const ETAG: &str = "\"5f-1a-400\"";
+
+        assert!(etag_matches("\"5f-1a-400\"", ETAG));
+        assert!(!etag_matches("\"5f-1a-401\"", ETAG));
+        // any entity
+        assert!(etag_matches("*", ETAG));
+        // weak comparison
+        assert!(etag_matches("W/\"5f-1a-400\"", ETAG));
+        assert!(etag_matches("\"5f-1a-400\"", "W/\"5f-1a-400\""));
+        assert!(!etag_matches("W/\"5f-1a-401\"", ETAG));
+        // lists of entity tags
+        assert!(etag_matches("\"a\", W/\"5f-1a-400\"", ETAG));
+        assert!(etag_matches("\"a\",\"5f-1a-400\",\"b\"", ETAG));
+        assert!(!etag_matches("\"a\", \"b\"", ETAG));
+        assert!(!etag_matches("", ETAG));
+    }
+}
@@ -21,15 +21,39 @@ CREATE TABLE IF NOT EXISTS extra (
     data VARCHAR(4096)
 );
 
+-- extended attributes of inodes
+CREATE TABLE IF NOT EXISTS xattr (
+    ino INTEGER,
+    name VARCHAR(255),
+    value BLOB
+);
+
+CREATE INDEX IF NOT EXISTS xattr_ino ON xattr (ino);
+
+-- hardlinks, all inodes with the same target are links to the same file. The target
+-- is the inode of the first link
+CREATE TABLE IF NOT EXISTS hardlink (
+    ino INTEGER PRIMARY KEY,
+    target INTEGER
+);
+
 -- blocks per file, order of insertion is important
 CREATE TABLE IF NOT EXISTS block (
     ino INTEGER,
     id VARCHAR(32),
-    key VARCHAR(32)
+    key VARCHAR(32),
+    size INTEGER -- size of the plain block data
 );
 
 CREATE INDEX IF NOT EXISTS block_ino ON block (ino);
 
+-- digest of the content of regular files (see `file_hash`), files with the same hash have
+-- the same content. FLs created before file hashes don't have it
+CREATE TABLE IF NOT EXISTS file_hash (
+    ino INTEGER PRIMARY KEY,
+    hash VARCHAR(32)
+);
+
 -- global flist tags, this can include values like `version`, `description`, `block-size`, etc..
 -- it can also hold extra user-defined tags for extensions
 CREATE TABLE IF NOT EXISTS tag (
@@ -37,6 +61,14 @@ CREATE TABLE IF NOT EXISTS tag (
     value VARCHAR(255)
 );
 
+-- free form metadata of the flist, like its build provenance (git commit, build date,
+-- source url). Unlike tags it's never interpreted by rfs. FLs created before metadata
+-- support don't have it
+CREATE TABLE IF NOT EXISTS metadata (
+    key VARCHAR(255) PRIMARY KEY,
+    value TEXT
+);
+
 -- routing table define ranges where blobs can be found. This allows "sharding" by be able to retrieve
 -- blobs from different partitions using the prefix range (hashes that are )
 CREATE TABLE IF NOT EXISTS route (
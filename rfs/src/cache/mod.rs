@@ -3,15 +3,92 @@ use crate::store::{BlockStore, Store};
 use anyhow::{Context, Result};
 
 use std::os::unix::io::AsRawFd;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs::{self, File, OpenOptions};
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
 
-/// Cache implements a caching layer on top of a block store
-//#[derive(Clone)]
+type BlockId = [u8; 32];
+
+/// Cache implements a caching layer on top of a block store. Clones share the
+/// same store and cache directory.
 pub struct Cache<S: Store> {
-    store: BlockStore<S>,
+    store: Arc<BlockStore<S>>,
     root: PathBuf,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    // cached blocks by access order, only tracked if the cache size is limited.
+    // It's loaded from the cache directory on first use.
+    index: Arc<Mutex<Option<Index>>>,
+}
+
+impl<S> Clone for Cache<S>
+where
+    S: Store,
+{
+    fn clone(&self) -> Self {
+        Self {
+            store: Arc::clone(&self.store),
+            root: self.root.clone(),
+            max_size: self.max_size,
+            max_age: self.max_age,
+            index: Arc::clone(&self.index),
+        }
+    }
+}
+
+/// Index tracks the size and access order of the cached blocks
+struct Index {
+    blocks: lru::LruCache<BlockId, u64>,
+    size: u64,
+}
+
+impl Index {
+    /// load indexes the blocks already in the cache directory, ordered by their
+    /// last access time
+    fn load(root: &Path) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        fn children(dir: &Path) -> impl Iterator<Item = std::fs::DirEntry> {
+            std::fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok())
+        }
+
+        // blocks are stored under <root>/<xx>/<xx>/<id>
+        let mut blocks: Vec<(i64, BlockId, u64)> = children(root)
+            .flat_map(|level1| children(&level1.path()))
+            .flat_map(|level2| children(&level2.path()))
+            .filter_map(|entry| {
+                let meta = entry.metadata().ok()?;
+                let mut id = BlockId::default();
+                hex::decode_to_slice(entry.file_name().to_str()?, &mut id).ok()?;
+                Some((meta.atime(), id, meta.len()))
+            })
+            .collect();
+        blocks.sort_unstable_by_key(|(atime, _, _)| *atime);
+
+        let mut index = Index {
+            blocks: lru::LruCache::unbounded(),
+            size: 0,
+        };
+        for (_, id, size) in blocks {
+            index.touch(id, size);
+        }
+
+        index
+    }
+
+    /// touch marks the block as the most recently used
+    fn touch(&mut self, id: BlockId, size: u64) {
+        if let Some(old) = self.blocks.put(id, size) {
+            self.size -= old;
+        }
+        self.size += size;
+    }
 }
 
 impl<S> Cache<S>
@@ -23,14 +100,45 @@ where
         P: Into<PathBuf>,
     {
         Cache {
-            store: store.into(),
+            store: Arc::new(store.into()),
             root: root.into(),
+            max_size: None,
+            max_age: None,
+            index: Arc::default(),
         }
     }
 
-    // download given an open file, writes the content of the chunk to the file
+    /// with_max_size limits the size of the cached blocks, the least recently used
+    /// blocks are removed from the cache directory once it grows bigger than max_size
+    /// bytes. Blocks that are being downloaded are never removed, blocks that are still
+    /// open keep their content until closed.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// with_max_age makes cached blocks older than max_age be checked to still exist
+    /// in the store before they are used, blocks that don't exist anymore are
+    /// downloaded again. This is useful if the same cache directory is used with
+    /// stores that can change.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(&name[0..2]).join(&name[2..4]).join(name)
+    }
+
+    // download given an open file, writes the content of the chunk to the file. The
+    // content is only written if it matches the block.
     async fn download(&self, file: &mut File, block: &Block) -> Result<u64> {
         let data = self.store.get(block).await?;
+        // the block key is the hash of the plain content
+        if crate::store::hash(&data).as_slice() != block.key {
+            return Err(crate::store::Error::InvalidBlob)
+                .with_context(|| format!("block {} content mismatch", block.id.as_slice().hex()));
+        }
         file.write_all(&data).await?;
 
         Ok(data.len() as u64)
@@ -41,9 +149,10 @@ where
         if name.len() < 4 {
             anyhow::bail!("invalid chunk hash");
         }
-        let path = self.root.join(&name[0..2]).join(&name[2..4]);
-        fs::create_dir_all(&path).await?;
-        let path = path.join(name);
+        let path = self.path(&name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
 
         let file = OpenOptions::new()
             .create(true)
@@ -63,8 +172,9 @@ where
             .prepare(&block.id)
             .await
             .context("failed to prepare cache block")?;
-        // TODO: locking must happen here so no
-        // other processes start downloading the same chunk
+        // the block file is locked before its size is checked, and until the download
+        // is complete. Other callers (in this or other processes) wait for the lock
+        // then find the block already cached instead of downloading it again.
         let locker = Locker::new(&file);
         locker.lock().await?;
 
@@ -73,41 +183,240 @@ where
             .await
             .context("failed to get block metadata")?;
         if meta.len() > 0 {
-            // chunk is already downloaded
-            debug!("block cache hit: {}", block.id.as_slice().hex());
-            locker.unlock().await?;
-            return Ok((meta.len(), file));
+            if self.fresh(&file, &meta, block).await {
+                // chunk is already downloaded
+                debug!("block cache hit: {}", block.id.as_slice().hex());
+                locker.unlock().await?;
+                self.touch(block.id, meta.len()).await;
+                return Ok((meta.len(), file));
+            }
+
+            debug!("cached block expired: {}", block.id.as_slice().hex());
+            file.set_len(0)
+                .await
+                .context("failed to truncate expired block")?;
         }
 
         debug!("downloading block with key: {}", block.id.as_slice().hex());
-        let size = self
-            .download(&mut file, block)
-            .await
-            .context("failed to download block")?;
+        let size = match self.download(&mut file, block).await {
+            Ok(size) => size,
+            Err(err) => {
+                // a partially written block must not be served as cached
+                file.set_len(0)
+                    .await
+                    .context("failed to truncate partial block")?;
+                return Err(err.context("failed to download block"));
+            }
+        };
 
         // if file is just downloaded, we need
         // to seek to beginning of the file.
         file.rewind().await?;
 
         locker.unlock().await?;
+        self.touch(block.id, size).await;
         Ok((size, file))
     }
 
-    /// direct downloads all the file blocks from remote and write it to output
-    #[allow(dead_code)]
-    pub async fn direct(&self, blocks: &[Block], out: &mut File) -> Result<()> {
+    /// fresh checks if the cached block can be used. Blocks older than max_age are
+    /// checked to still exist in the store, then their age is reset.
+    async fn fresh(&self, file: &File, meta: &std::fs::Metadata, block: &Block) -> bool {
+        let max_age = match self.max_age {
+            Some(max_age) => max_age,
+            None => return true,
+        };
+
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or_default();
+        if age <= max_age {
+            return true;
+        }
+
+        match self.store.exists(block).await {
+            Ok(true) => {
+                if let Err(err) = reset_age(file) {
+                    warn!("failed to reset cached block age: {:#}", err);
+                }
+                true
+            }
+            Ok(false) => false,
+            Err(err) => {
+                // the cached block is still used if the store can't be reached
+                warn!(
+                    "failed to check block {} in store: {:#}",
+                    block.id.as_slice().hex(),
+                    err
+                );
+                true
+            }
+        }
+    }
+
+    /// touch records the access to the cached block, then evicts the least recently
+    /// used blocks if the cache is too big
+    async fn touch(&self, id: BlockId, size: u64) {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return,
+        };
+
+        let mut index = self.index.lock().await;
+        let index = match &mut *index {
+            Some(index) => index,
+            None => {
+                let root = self.root.clone();
+                let loaded = tokio::task::spawn_blocking(move || Index::load(&root))
+                    .await
+                    .unwrap_or_else(|_| Index {
+                        blocks: lru::LruCache::unbounded(),
+                        size: 0,
+                    });
+                index.insert(loaded)
+            }
+        };
+
+        index.touch(id, size);
+
+        // blocks that can't be evicted now are kept as the most recently used
+        let mut busy = Vec::new();
+        while index.size > max_size {
+            let (id, size) = match index.blocks.pop_lru() {
+                Some(block) => block,
+                None => break,
+            };
+
+            match self.evict(&id).await {
+                Ok(true) => index.size -= size,
+                Ok(false) => busy.push((id, size)),
+                Err(err) => {
+                    // the block is dropped from the index so it's not retried forever
+                    warn!(
+                        "failed to evict cached block {}: {:#}",
+                        id.as_slice().hex(),
+                        err
+                    );
+                    index.size -= size;
+                }
+            }
+        }
+
+        for (id, size) in busy {
+            index.blocks.put(id, size);
+        }
+    }
+
+    /// evict removes the cached block file, it returns false if the block is locked
+    /// because it's being downloaded
+    async fn evict(&self, id: &BlockId) -> Result<bool> {
+        let path = self.path(&id.as_slice().hex());
+        tokio::task::spawn_blocking(move || {
+            use nix::fcntl::{flock, FlockArg};
+
+            let file = match std::fs::File::open(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+                Err(err) => return Err(err.into()),
+            };
+
+            // the lock is released when the file is closed
+            if flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err() {
+                return Ok(false);
+            }
+
+            debug!("evicting cached block {}", path.display());
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(true),
+            }
+        })
+        .await
+        .context("failed to spawn block eviction")?
+    }
+
+    /// put uploads the given data as a new block to the remote store
+    pub async fn put(&self, data: &[u8]) -> Result<Block> {
+        let mut block = self
+            .store
+            .set(data)
+            .await
+            .context("failed to upload block")?;
+        block.size = Some(data.len() as u64);
+
+        Ok(block)
+    }
+
+    /// raw downloads the block blob as it is stored (compressed and encrypted) from the
+    /// remote store, the local cache is not used.
+    pub async fn raw(&self, block: &Block) -> crate::store::Result<Vec<u8>> {
+        self.store.raw(block).await
+    }
+
+    /// verify checks that the block can be downloaded from the remote store
+    /// and that its content is valid. The local cache is not used.
+    pub async fn verify(&self, block: &Block) -> crate::store::Result<()> {
+        self.store.verify(block).await
+    }
+
+    /// direct downloads all the file blocks from remote and write it to output. Up to
+    /// PARALLEL_DOWNLOAD blocks are downloaded in parallel, but they are written to
+    /// the output in order
+    pub async fn direct<W>(&self, blocks: &[Block], out: &mut W) -> Result<()>
+    where
+        W: tokio::io::AsyncWrite + Unpin + ?Sized,
+    {
+        use futures::stream::{FuturesOrdered, StreamExt};
         use tokio::io::copy;
-        for (index, block) in blocks.iter().enumerate() {
-            let (_, mut chunk) = self.get(block).await?;
+
+        // the downloads are queued explicitly (instead of mapping a stream of the
+        // blocks) so the returned future stays Send and can be spawned
+        let mut pending = blocks.iter();
+        let mut chunks = FuturesOrdered::new();
+        for block in pending.by_ref().take(crate::PARALLEL_DOWNLOAD) {
+            chunks.push_back(self.get(block));
+        }
+
+        let mut index = 0;
+        while let Some(chunk) = chunks.next().await {
+            if let Some(block) = pending.next() {
+                chunks.push_back(self.get(block));
+            }
+
+            let (_, mut chunk) = chunk?;
             copy(&mut chunk, out)
                 .await
                 .with_context(|| format!("failed to copy block {}", index))?;
+            index += 1;
         }
 
         Ok(())
     }
 }
 
+/// reset_age sets the modification time of the file to now
+fn reset_age(file: &File) -> std::io::Result<()> {
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_NOW,
+        },
+    ];
+
+    // SAFETY: the file descriptor is valid while the file is borrowed and times
+    // points to two timespecs as required by futimens
+    if unsafe { libc::futimens(file.as_raw_fd(), times.as_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
 pub struct Locker {
     fd: std::os::unix::io::RawFd,
 }
@@ -149,3 +458,153 @@ impl Hex for &[u8] {
         hex::encode(self)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::{mem::MemStore, Compression, Route, Store};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_cache_eviction() {
+        const ROOT: &str = "/tmp/cache-eviction-test";
+        let _ = fs::remove_dir_all(ROOT).await;
+
+        let cache = Cache::new(ROOT, MemStore::new()).with_max_size(2 * 1024);
+
+        let mut blocks = Vec::new();
+        for i in 0..3u8 {
+            blocks.push(cache.put(&[i; 1024]).await.unwrap());
+        }
+
+        for block in blocks.iter() {
+            let (size, _) = cache.get(block).await.unwrap();
+            assert_eq!(size, 1024);
+        }
+
+        // the least recently used block is evicted
+        let cached = |block: &Block| cache.path(&block.id.as_slice().hex()).exists();
+        assert!(!cached(&blocks[0]));
+        assert!(cached(&blocks[1]));
+        assert!(cached(&blocks[2]));
+
+        // an evicted block is downloaded again, evicting the next one
+        cache.get(&blocks[0]).await.unwrap();
+        assert!(cached(&blocks[0]));
+        assert!(!cached(&blocks[1]));
+        assert!(cached(&blocks[2]));
+    }
+
+    /// CountingStore counts the blocks downloaded from the inner store
+    #[derive(Clone, Default)]
+    struct CountingStore {
+        inner: MemStore,
+        gets: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Store for CountingStore {
+        async fn get(&self, key: &[u8]) -> crate::store::Result<Vec<u8>> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            // make the download slow enough for the callers to overlap
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            self.inner.get(key).await
+        }
+
+        async fn set(&self, key: &[u8], blob: &[u8]) -> crate::store::Result<()> {
+            self.inner.set(key, blob).await
+        }
+
+        async fn exists(&self, key: &[u8]) -> crate::store::Result<bool> {
+            self.inner.exists(key).await
+        }
+
+        async fn delete(&self, key: &[u8]) -> crate::store::Result<()> {
+            self.inner.delete(key).await
+        }
+
+        fn routes(&self) -> Vec<Route> {
+            self.inner.routes()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cache_concurrent_get() {
+        const ROOT: &str = "/tmp/cache-concurrent-test";
+        let _ = fs::remove_dir_all(ROOT).await;
+
+        let store = CountingStore::default();
+        let cache = Arc::new(Cache::new(ROOT, store.clone()));
+        let block = cache.put(&[1; 1024]).await.unwrap();
+
+        let gets: Vec<_> = (0..20)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let block = block.clone();
+                tokio::spawn(async move { cache.get(&block).await.unwrap().0 })
+            })
+            .collect();
+
+        for get in gets {
+            assert_eq!(get.await.unwrap(), 1024);
+        }
+
+        assert_eq!(store.gets.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_max_age() {
+        const ROOT: &str = "/tmp/cache-max-age-test";
+        let _ = fs::remove_dir_all(ROOT).await;
+
+        let store = CountingStore::default();
+        let cache = Cache::new(ROOT, store.clone()).with_max_age(Duration::from_millis(10));
+        let block = cache.put(&[1; 1024]).await.unwrap();
+
+        cache.get(&block).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // the expired block is still in the store, so it's not downloaded again
+        cache.get(&block).await.unwrap();
+        assert_eq!(store.gets.load(Ordering::SeqCst), 1);
+
+        // the block is removed from the store, the expired cached block is not used
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        store.inner.delete(&block.id).await.unwrap();
+        assert!(cache.get(&block).await.is_err());
+        let path = cache.path(&block.id.as_slice().hex());
+        assert_eq!(fs::metadata(path).await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_tampered() {
+        use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+
+        const ROOT: &str = "/tmp/cache-tampered-test";
+        let _ = fs::remove_dir_all(ROOT).await;
+
+        let store = MemStore::new();
+        let cache = Cache::new(ROOT, store.clone());
+        let block = cache.put(b"original content").await.unwrap();
+
+        // the store serves other content that is valid for the block key, so the
+        // blob decrypts fine but doesn't match the block
+        let cipher = Aes256Gcm::new_from_slice(&block.key).unwrap();
+        let compressed = Compression::None.compress(b"tampered content").unwrap();
+        let tampered = cipher
+            .encrypt(Nonce::from_slice(&block.key[..12]), compressed.as_slice())
+            .unwrap();
+        store.set(&block.id, &tampered).await.unwrap();
+
+        let err = cache.get(&block).await.unwrap_err();
+        assert!(matches!(
+            err.root_cause().downcast_ref::<crate::store::Error>(),
+            Some(crate::store::Error::InvalidBlob)
+        ));
+
+        // nothing is cached
+        let path = cache.path(&block.id.as_slice().hex());
+        assert_eq!(fs::metadata(path).await.unwrap().len(), 0);
+    }
+}
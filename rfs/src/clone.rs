@@ -6,15 +6,56 @@ use crate::{
 use anyhow::Error;
 use futures::lock::Mutex;
 use hex::ToHex;
-use std::sync::Arc;
-use tokio::io::AsyncReadExt;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// CloneProgress is reported each time a block is processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CloneProgress {
+    /// number of processed blocks, including the skipped ones
+    pub done: u64,
+    /// total number of blocks of the FL
+    pub total: u64,
+    /// number of blocks skipped because they already exist in the destination
+    pub skipped: u64,
+    /// number of bytes copied to the destination
+    pub bytes: u64,
+}
 
-const WORKERS: usize = 10;
+/// Progress is called with the clone progress after each processed block
+pub type Progress = Arc<dyn Fn(CloneProgress) + Send + Sync>;
 
-pub async fn clone<S: Store>(reader: Reader, store: S, cache: Cache<S>) -> Result<()> {
+/// clone copies all the blocks of the FL to the given store. Blocks are copied as they
+/// are stored (compressed and encrypted) so the FL can be read from the new store as is.
+///
+/// If resume is set, blocks that already exist in the destination store are skipped, so
+/// an interrupted clone can be restarted without copying everything again.
+///
+/// At most `parallel` blocks are copied at the same time.
+pub async fn clone<S: Store>(
+    reader: Reader,
+    store: S,
+    cache: Cache<S>,
+    resume: bool,
+    parallel: usize,
+    progress: Option<Progress>,
+) -> Result<()> {
     let failures = Arc::new(Mutex::new(Vec::new()));
-    let cloner = BlobCloner::new(cache, store.into(), failures.clone());
-    let mut workers = workers::WorkerPool::new(cloner, WORKERS);
+    let total = reader.blocks_count().await?;
+    let cloner = BlobCloner::new(
+        cache,
+        store.into(),
+        failures.clone(),
+        resume,
+        Arc::new(Counter {
+            total,
+            progress,
+            ..Counter::default()
+        }),
+    );
+    let mut workers = workers::WorkerPool::new(cloner, parallel.max(1));
 
     let mut offset = 0;
     loop {
@@ -50,6 +91,42 @@ pub async fn clone<S: Store>(reader: Reader, store: S, cache: Cache<S>) -> Resul
     )))
 }
 
+#[derive(Default)]
+struct Counter {
+    done: AtomicU64,
+    skipped: AtomicU64,
+    bytes: AtomicU64,
+    total: u64,
+    progress: Option<Progress>,
+}
+
+impl Counter {
+    /// inc records a processed block, copied is the size of the copied data
+    /// or None if the block was skipped
+    fn inc(&self, copied: Option<u64>) {
+        let (skipped, bytes) = match copied {
+            Some(size) => (
+                self.skipped.load(Ordering::Relaxed),
+                self.bytes.fetch_add(size, Ordering::Relaxed) + size,
+            ),
+            None => (
+                self.skipped.fetch_add(1, Ordering::Relaxed) + 1,
+                self.bytes.load(Ordering::Relaxed),
+            ),
+        };
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(progress) = &self.progress {
+            progress(CloneProgress {
+                done,
+                total: self.total,
+                skipped,
+                bytes,
+            });
+        }
+    }
+}
+
 struct BlobCloner<S>
 where
     S: Store,
@@ -57,6 +134,8 @@ where
     cache: Arc<Cache<S>>,
     store: Arc<BlockStore<S>>,
     failures: Arc<Mutex<Vec<(String, Error)>>>,
+    resume: bool,
+    counter: Arc<Counter>,
 }
 
 impl<S> Clone for BlobCloner<S>
@@ -68,6 +147,8 @@ where
             cache: self.cache.clone(),
             store: self.store.clone(),
             failures: self.failures.clone(),
+            resume: self.resume,
+            counter: self.counter.clone(),
         }
     }
 }
@@ -80,12 +161,30 @@ where
         cache: Cache<S>,
         store: BlockStore<S>,
         failures: Arc<Mutex<Vec<(String, Error)>>>,
+        resume: bool,
+        counter: Arc<Counter>,
     ) -> Self {
         Self {
             cache: Arc::new(cache),
             store: Arc::new(store),
             failures,
+            resume,
+            counter,
+        }
+    }
+
+    /// copy copies the block to the destination store, it returns the size of the
+    /// copied data or None if the block was skipped
+    async fn copy(&self, block: &Block) -> std::result::Result<Option<u64>, Error> {
+        // blocks that are already in the destination store are not downloaded
+        if self.resume && self.store.exists(block).await? {
+            return Ok(None);
         }
+
+        let data = self.cache.raw(block).await?;
+        self.store.put(block, &data).await?;
+
+        Ok(Some(data.len() as u64))
     }
 }
 
@@ -98,31 +197,86 @@ where
     type Output = ();
 
     async fn run(&mut self, block: Self::Input) -> Self::Output {
-        let mut file = match self.cache.get(&block).await {
-            Ok((_, f)) => f,
-            Err(err) => {
-                self.failures
-                    .lock()
-                    .await
-                    .push((block.id.as_slice().encode_hex(), err));
-                return;
-            }
-        };
-
-        let mut data = Vec::new();
-        if let Err(err) = file.read_to_end(&mut data).await {
-            self.failures
+        match self.copy(&block).await {
+            Ok(copied) => self.counter.inc(copied),
+            Err(err) => self
+                .failures
                 .lock()
                 .await
-                .push((block.id.as_slice().encode_hex(), err.into()));
-            return;
+                .push((block.id.as_slice().encode_hex(), err)),
         }
-        if let Err(err) = self.store.set(&data).await {
-            self.failures
-                .lock()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fungi::{meta::Inode, Writer},
+        store::mem::MemStore,
+    };
+
+    #[tokio::test]
+    async fn test_clone() {
+        const PATH: &str = "/tmp/clone-test.fl";
+        let writer = Writer::new(PATH, true).await.unwrap();
+        let ino = writer.inode(Inode::default()).await.unwrap();
+
+        let source = MemStore::new();
+        let blocks = BlockStore::from(source.clone());
+        for i in 0..5u8 {
+            let block = blocks.set(&[i; 100]).await.unwrap();
+            writer
+                .block(ino, &block.id, &block.key, block.size)
                 .await
-                .push((block.id.as_slice().encode_hex(), err.into()));
-            return;
+                .unwrap();
+        }
+
+        let reader = Reader::new(PATH).await.unwrap();
+        let destination = MemStore::new();
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let progress_calls = Arc::clone(&calls);
+        let progress: Progress = Arc::new(move |progress| {
+            progress_calls.lock().unwrap().push(progress);
+        });
+
+        // one block is already in the destination so it's skipped
+        let first = reader.all_blocks(1, 0).await.unwrap().remove(0);
+        BlockStore::from(destination.clone())
+            .put(
+                &first,
+                &BlockStore::from(source.clone()).raw(&first).await.unwrap(),
+            )
+            .await
+            .unwrap();
+
+        clone(
+            reader.clone(),
+            destination.clone(),
+            Cache::new("/tmp/clone-test-cache", source.clone()),
+            true,
+            2,
+            Some(progress),
+        )
+        .await
+        .unwrap();
+
+        let calls = calls.lock().unwrap().clone();
+        let last = calls.iter().max_by_key(|progress| progress.done).unwrap();
+        assert_eq!((last.done, last.total), (5, 5));
+        assert_eq!(calls.iter().map(|p| p.skipped).max(), Some(1));
+        assert!(calls.iter().map(|p| p.bytes).max().unwrap() > 0);
+
+        // blobs are copied as is
+        let mut source_keys = source.keys().await.unwrap();
+        let mut destination_keys = destination.keys().await.unwrap();
+        source_keys.sort();
+        destination_keys.sort();
+        assert_eq!(source_keys, destination_keys);
+
+        let destination = BlockStore::from(destination);
+        for block in reader.blocks(ino).await.unwrap() {
+            destination.verify(&block).await.unwrap();
         }
     }
 }
@@ -14,6 +14,15 @@ pub async fn tag_list(reader: Reader) -> Result<()> {
     Ok(())
 }
 
+pub async fn tag_get(reader: Reader, key: String) -> Result<()> {
+    let value = reader
+        .tag(Tag::Custom(key.as_str()))
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("tag '{}' is not set", key))?;
+    println!("{}", value);
+    Ok(())
+}
+
 pub async fn tag_add(writer: Writer, tags: Vec<(String, String)>) -> Result<()> {
     for (key, value) in tags {
         writer.tag(Tag::Custom(key.as_str()), value).await?;
@@ -0,0 +1,191 @@
+use crate::fungi::{
+    meta::{FileType, Inode, Walk, WalkVisitor},
+    Reader, Result,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Change is a path that exists in both FLs but with different attributes or content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: PathBuf,
+    /// number of blocks of the new file that are not in the old file
+    pub blocks_added: usize,
+    /// number of blocks of the old file that are not in the new file
+    pub blocks_removed: usize,
+}
+
+/// DiffReport holds the paths that were added, removed or changed between two FLs,
+/// sorted by path
+#[derive(Debug, Default)]
+pub struct DiffReport {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<Change>,
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// diff walks both FLs and compares their entries by path. An entry is changed if its
+/// type, size, mtime, mode, ownership, link target or blocks are different. The blocks
+/// of files are only compared if the FLs don't have the same file hash for them.
+pub async fn diff(old: &Reader, new: &Reader) -> Result<DiffReport> {
+    let mut old_entries = Collector::default();
+    old.walk(&mut old_entries).await?;
+    let mut new_entries = Collector::default();
+    new.walk(&mut new_entries).await?;
+
+    let mut report = DiffReport::default();
+    for (path, old_node) in old_entries.entries.iter() {
+        let new_node = match new_entries.entries.get(path) {
+            Some(node) => node,
+            None => {
+                report.removed.push(path.clone());
+                continue;
+            }
+        };
+
+        let both_regular =
+            old_node.mode.is(FileType::Regular) && new_node.mode.is(FileType::Regular);
+        // files with the same hash have the same blocks, they don't need to be compared
+        let same_content = both_regular && {
+            let old_hash = old.file_hash(old_node.ino).await?;
+            old_hash.is_some() && old_hash == new.file_hash(new_node.ino).await?
+        };
+
+        let (blocks_added, blocks_removed) = if both_regular && !same_content {
+            let old_blocks: HashSet<_> = old
+                .blocks(old_node.ino)
+                .await?
+                .into_iter()
+                .map(|b| b.id)
+                .collect();
+            let new_blocks: HashSet<_> = new
+                .blocks(new_node.ino)
+                .await?
+                .into_iter()
+                .map(|b| b.id)
+                .collect();
+            (
+                new_blocks.difference(&old_blocks).count(),
+                old_blocks.difference(&new_blocks).count(),
+            )
+        } else {
+            (0, 0)
+        };
+
+        if blocks_added > 0 || blocks_removed > 0 || attrs_changed(old_node, new_node) {
+            report.changed.push(Change {
+                path: path.clone(),
+                blocks_added,
+                blocks_removed,
+            });
+        }
+    }
+
+    report.added = new_entries
+        .entries
+        .into_keys()
+        .filter(|path| !old_entries.entries.contains_key(path))
+        .collect();
+
+    Ok(report)
+}
+
+fn attrs_changed(old: &Inode, new: &Inode) -> bool {
+    old.mode.mode() != new.mode.mode()
+        || old.size != new.size
+        || old.mtime != new.mtime
+        || old.uid != new.uid
+        || old.gid != new.gid
+        || old.rdev != new.rdev
+        || old.data != new.data
+}
+
+#[derive(Default)]
+struct Collector {
+    entries: BTreeMap<PathBuf, Inode>,
+}
+
+#[async_trait::async_trait]
+impl WalkVisitor for Collector {
+    async fn visit(&mut self, path: &Path, node: &Inode) -> Result<Walk> {
+        self.entries.insert(path.into(), node.clone());
+        Ok(Walk::Continue)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fungi::{
+        meta::fixture::{child, root},
+        Writer,
+    };
+
+    async fn make(path: &str, files: &[(&str, i64, &[u8])]) -> Reader {
+        let writer = Writer::new(path, true).await.unwrap();
+        let root = root(&writer).await;
+
+        for (name, mtime, blocks) in files {
+            let ino = child(
+                &writer,
+                root,
+                name,
+                FileType::Regular,
+                Inode {
+                    mtime: *mtime,
+                    ..Inode::default()
+                },
+            )
+            .await;
+
+            for id in blocks.iter() {
+                writer.block(ino, &[*id; 32], &[0; 32], None).await.unwrap();
+            }
+        }
+
+        Reader::new(path).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_diff() {
+        let old = make(
+            "/tmp/diff-old.fl",
+            &[
+                ("same", 1, &[1]),
+                ("removed", 1, &[2]),
+                ("changed", 1, &[3, 4]),
+            ],
+        )
+        .await;
+        let new = make(
+            "/tmp/diff-new.fl",
+            &[
+                ("same", 1, &[1]),
+                ("added", 1, &[5]),
+                ("changed", 2, &[3, 6, 7]),
+            ],
+        )
+        .await;
+
+        let report = diff(&old, &new).await.unwrap();
+        assert_eq!(report.added, vec![PathBuf::from("/added")]);
+        assert_eq!(report.removed, vec![PathBuf::from("/removed")]);
+        assert_eq!(
+            report.changed,
+            vec![Change {
+                path: "/changed".into(),
+                blocks_added: 2,
+                blocks_removed: 1,
+            }]
+        );
+
+        let report = diff(&old, &old).await.unwrap();
+        assert!(report.is_empty());
+    }
+}
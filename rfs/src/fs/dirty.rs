@@ -0,0 +1,187 @@
+use crate::fungi::meta::Block;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Dirty is a file opened for writing. Blocks of the file are split at chunk_size
+/// boundaries, modified blocks are kept in memory until the file is flushed to the
+/// store, the rest of the blocks are still served from the FL blocks (base).
+pub struct Dirty {
+    /// blocks of the file as stored in the FL
+    pub base: Vec<Block>,
+    /// base data after this offset was truncated, and must read as zeros
+    valid: u64,
+    /// current size of the file
+    pub size: u64,
+    chunk_size: usize,
+    blocks: HashMap<usize, Vec<u8>>,
+}
+
+impl Dirty {
+    pub fn new(base: Vec<Block>, size: u64, chunk_size: usize) -> Self {
+        Self {
+            base,
+            valid: size,
+            size,
+            chunk_size,
+            blocks: HashMap::default(),
+        }
+    }
+
+    /// count of blocks of the file at its current size
+    pub fn count(&self) -> usize {
+        self.indices(0, self.size as usize).end
+    }
+
+    /// indices of the blocks that holds the range [offset, offset + len)
+    pub fn indices(&self, offset: usize, len: usize) -> Range<usize> {
+        if len == 0 {
+            return offset / self.chunk_size..offset / self.chunk_size;
+        }
+
+        offset / self.chunk_size..(offset + len - 1) / self.chunk_size + 1
+    }
+
+    fn len_at(&self, size: u64, index: usize) -> usize {
+        let start = (index * self.chunk_size) as u64;
+        std::cmp::min(size.saturating_sub(start), self.chunk_size as u64) as usize
+    }
+
+    /// block_len is the length of the block at index for the current file size
+    pub fn block_len(&self, index: usize) -> usize {
+        self.len_at(self.size, index)
+    }
+
+    /// base_len is the length of the valid data of the base block at index
+    pub fn base_len(&self, index: usize) -> usize {
+        if index >= self.base.len() {
+            return 0;
+        }
+
+        self.len_at(self.valid, index)
+    }
+
+    /// get returns the buffered data of the block at index if it was modified
+    pub fn get(&self, index: usize) -> Option<&Vec<u8>> {
+        self.blocks.get(&index)
+    }
+
+    /// insert buffers the data of the block at index, this must be called with
+    /// the current data of a block before writing to it
+    pub fn insert(&mut self, index: usize, data: Vec<u8>) {
+        self.blocks.insert(index, data);
+    }
+
+    /// write data at offset, all blocks touched by the write must be
+    /// inserted first unless they are new blocks.
+    pub fn write(&mut self, offset: usize, mut data: &[u8]) {
+        let chunk_size = self.chunk_size;
+        let end = (offset + data.len()) as u64;
+
+        let mut offset = offset;
+        while !data.is_empty() {
+            let index = offset / chunk_size;
+            let start = offset % chunk_size;
+            let len = std::cmp::min(chunk_size - start, data.len());
+
+            let block = self.blocks.entry(index).or_default();
+            if block.len() < start + len {
+                block.resize(start + len, 0);
+            }
+
+            block[start..start + len].copy_from_slice(&data[..len]);
+            data = &data[len..];
+            offset += len;
+        }
+
+        if end > self.size {
+            self.size = end;
+        }
+    }
+
+    /// truncate changes the size of the file
+    pub fn truncate(&mut self, size: u64) {
+        self.size = size;
+        if size < self.valid {
+            self.valid = size;
+        }
+
+        let count = self.count();
+        self.blocks.retain(|index, _| *index < count);
+        if count > 0 {
+            let len = self.block_len(count - 1);
+            if let Some(block) = self.blocks.get_mut(&(count - 1)) {
+                block.truncate(len);
+            }
+        }
+    }
+
+    /// is_clean returns true if the base block at index can be used as is
+    pub fn is_clean(&self, index: usize) -> bool {
+        !self.blocks.contains_key(&index)
+            && index < self.base.len()
+            && self.base_len(index) == self.block_len(index)
+            && self.base[index]
+                .size
+                .map(|size| size as usize == self.block_len(index))
+                .unwrap_or(true)
+    }
+
+    /// reset marks the file as clean after a flush with the new list of blocks
+    pub fn reset(&mut self, base: Vec<Block>) {
+        self.base = base;
+        self.valid = self.size;
+        self.blocks.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block(size: u64) -> Block {
+        Block {
+            size: Some(size),
+            ..Block::default()
+        }
+    }
+
+    #[test]
+    fn test_append_across_blocks() {
+        // a 10 bytes file with 4 bytes blocks
+        let mut file = Dirty::new(vec![block(4), block(4), block(2)], 10, 4);
+        assert_eq!(file.count(), 3);
+        assert_eq!(file.indices(9, 3), 2..3);
+
+        file.insert(2, vec![8, 9]);
+        file.write(10, &[10, 11, 12, 13]);
+
+        assert_eq!(file.size, 14);
+        assert_eq!(file.count(), 4);
+        assert_eq!(file.get(2).unwrap(), &vec![8, 9, 10, 11]);
+        assert_eq!(file.get(3).unwrap(), &vec![12, 13]);
+
+        assert!(file.is_clean(0));
+        assert!(file.is_clean(1));
+        assert!(!file.is_clean(2));
+        assert!(!file.is_clean(3));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut file = Dirty::new(vec![block(4), block(4), block(2)], 10, 4);
+        file.truncate(5);
+
+        assert_eq!(file.count(), 2);
+        assert!(file.is_clean(0));
+        // block 1 is cut short
+        assert!(!file.is_clean(1));
+        assert_eq!(file.base_len(1), 1);
+
+        // extending the file again must not bring back the truncated data
+        file.truncate(12);
+        assert_eq!(file.count(), 3);
+        assert_eq!(file.base_len(1), 1);
+        assert_eq!(file.base_len(2), 0);
+        assert_eq!(file.block_len(2), 4);
+    }
+}
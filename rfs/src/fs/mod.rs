@@ -1,22 +1,28 @@
 #![allow(clippy::unnecessary_mut_passed)]
 #![deny(clippy::unimplemented, clippy::todo)]
 
+mod dirty;
+
 use crate::cache;
 use crate::fungi::{
-    meta::{FileType, Inode},
-    Reader,
+    meta::{Block, FileType, Ino, Inode, Mode, Tag},
+    Reader, Writer,
 };
 use crate::store::Store;
+use dirty::Dirty;
 
 use anyhow::{ensure, Context, Result};
 use polyfuse::reply::FileAttr;
 use polyfuse::{
     op,
-    reply::{AttrOut, EntryOut, ReaddirOut, StatfsOut},
+    reply::{AttrOut, EntryOut, OpenOut, ReaddirOut, StatfsOut, WriteOut},
     KernelConfig, Operation, Request, Session,
 };
-use std::io::SeekFrom;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
+use std::time::SystemTime;
 use std::{io, path::PathBuf, time::Duration};
 use tokio::fs::File;
 use tokio::sync::Mutex;
@@ -25,10 +31,14 @@ use tokio::{
     task::{self, JoinHandle},
 };
 
-const CHUNK_SIZE: usize = 512 * 1024; // 512k and is hardcoded in the hub. the block_size value is not used
+const CHUNK_SIZE: usize = 512 * 1024; // 512k default block size if the FL has no block-size tag
 const TTL: Duration = Duration::from_secs(60 * 60 * 24 * 365);
-const LRU_CAP: usize = 5; // Least Recently Used File Capacity
 const FS_BLOCK_SIZE: u32 = 4 * 1024;
+const NAME_MAX: u32 = 255;
+const WRITE_FH: u64 = 1; // file handle of files opened for writing
+const READ_FH: u64 = 2; // file handle of files and directories opened for reading
+pub const MAX_PREFETCH: usize = 8; // max number of blocks to prefetch ahead of a sequential read
+const READERS_CAP: usize = 1024; // max number of files tracked for sequential reads
 
 type FHash = [u8; 32];
 type BlockSize = u64;
@@ -40,6 +50,14 @@ where
     meta: Reader,
     cache: Arc<cache::Cache<S>>,
     lru: Arc<Mutex<lru::LruCache<FHash, (File, BlockSize)>>>,
+    chunk_size: usize,
+    writer: Option<Writer>,
+    dirty: Arc<Mutex<HashMap<Ino, Arc<Mutex<Dirty>>>>>,
+    prefetch: usize,
+    // end offset of the last read per file, used to detect sequential reads
+    readers: Arc<Mutex<lru::LruCache<Ino, u64>>>,
+    // blocks that are currently being prefetched
+    prefetching: Arc<Mutex<HashSet<FHash>>>,
 }
 
 impl<S> Clone for Filesystem<S>
@@ -51,6 +69,12 @@ where
             meta: self.meta.clone(),
             cache: Arc::clone(&self.cache),
             lru: Arc::clone(&self.lru),
+            chunk_size: self.chunk_size,
+            writer: self.writer.clone(),
+            dirty: Arc::clone(&self.dirty),
+            prefetch: self.prefetch,
+            readers: Arc::clone(&self.readers),
+            prefetching: Arc::clone(&self.prefetching),
         }
     }
 }
@@ -59,12 +83,44 @@ impl<S> Filesystem<S>
 where
     S: Store,
 {
-    pub fn new(meta: Reader, cache: cache::Cache<S>) -> Self {
-        Filesystem {
+    /// new creates a new filesystem, lru_cap is the number of open block files
+    /// kept around for sequential reads
+    pub async fn new(meta: Reader, cache: cache::Cache<S>, lru_cap: NonZeroUsize) -> Result<Self> {
+        let chunk_size = match meta.tag(Tag::BlockSize).await? {
+            Some(size) => size
+                .parse()
+                .with_context(|| format!("invalid block-size tag '{}'", size))?,
+            None => CHUNK_SIZE,
+        };
+
+        Ok(Filesystem {
             meta,
             cache: Arc::new(cache),
-            lru: Arc::new(Mutex::new(lru::LruCache::new(LRU_CAP))),
-        }
+            lru: Arc::new(Mutex::new(lru::LruCache::new(lru_cap))),
+            chunk_size,
+            writer: None,
+            dirty: Arc::default(),
+            prefetch: 0,
+            readers: Arc::new(Mutex::new(lru::LruCache::new(
+                NonZeroUsize::new(READERS_CAP).unwrap(),
+            ))),
+            prefetching: Arc::default(),
+        })
+    }
+
+    /// with_prefetch enables downloading up to depth blocks (bounded by MAX_PREFETCH)
+    /// ahead in the background when a file is read sequentially
+    pub fn with_prefetch(mut self, depth: usize) -> Self {
+        self.prefetch = std::cmp::min(depth, MAX_PREFETCH);
+        self
+    }
+
+    /// with_writer enables the (experimental) write support of the filesystem. Written
+    /// blocks are buffered in memory and uploaded to the FL stores when the file is
+    /// closed or synced, the metadata changes are written to the FL with the writer.
+    pub fn with_writer(mut self, writer: Writer) -> Self {
+        self.writer = Some(writer);
+        self
     }
 
     pub async fn mount<P>(&self, mnt: P) -> Result<()>
@@ -75,7 +131,8 @@ where
         ensure!(mountpoint.is_dir(), "mountpoint must be a directory");
         let mut options = KernelConfig::default();
         options.mount_option(&format!(
-            "ro,allow_other,fsname={},subtype=g8ufs,default_permissions",
+            "{},allow_other,fsname={},subtype=g8ufs,default_permissions",
+            if self.writer.is_some() { "rw" } else { "ro" },
             std::process::id()
         ));
 
@@ -98,6 +155,17 @@ where
                     Operation::Readdir(op) => fs.readdir(&req, op).await,
                     Operation::Readlink(op) => fs.readlink(&req, op).await,
                     Operation::Statfs(op) => fs.statfs(&req, op).await,
+                    Operation::Access(op) => fs.access(&req, op).await,
+                    Operation::Open(op) => fs.open(&req, op).await,
+                    Operation::Write(op, data) => fs.write(&req, op, data).await,
+                    Operation::Release(op) => fs.release(&req, op).await,
+                    Operation::Opendir(op) => fs.opendir(&req, op).await,
+                    Operation::Releasedir(_) => Ok(req.reply(())?),
+                    Operation::Fsync(op) => fs.fsync(&req, op).await,
+                    Operation::Setattr(op) => fs.setattr(&req, op).await,
+                    Operation::Create(op) => fs.create(&req, op).await,
+                    Operation::Mkdir(op) => fs.mkdir(&req, op).await,
+                    Operation::Unlink(op) => fs.unlink(&req, op).await,
                     op => {
                         debug!("function is not implemented: {:?}", op);
                         Ok(req.reply_error(libc::ENOSYS)?)
@@ -118,13 +186,52 @@ where
     }
 
     async fn statfs(&self, req: &Request, _op: op::Statfs<'_>) -> Result<()> {
+        let flist = self.meta.stats().await?;
+        let block_size = FS_BLOCK_SIZE as u64;
+
         let mut out = StatfsOut::default();
         let stats = out.statfs();
         stats.bsize(FS_BLOCK_SIZE);
+        stats.frsize(FS_BLOCK_SIZE);
+        stats.blocks((flist.total_size + block_size - 1) / block_size);
+        stats.files(flist.total_inodes);
+        stats.namelen(NAME_MAX);
+        // the filesystem content is fixed by the FL, there is no free space to report
+        stats.bfree(0);
+        stats.bavail(0);
+        stats.ffree(0);
         req.reply(out)?;
         Ok(())
     }
 
+    /// access checks the permissions of the caller the same way the kernel does with
+    /// `default_permissions`, only the primary group of the caller is known
+    async fn access(&self, req: &Request, op: op::Access<'_>) -> Result<()> {
+        let inode = self.meta.inode(op.ino()).await?;
+        let mask = op.mask() as i32;
+
+        if mask & libc::W_OK != 0 && self.writer.is_none() {
+            return Ok(req.reply_error(libc::EROFS)?);
+        }
+
+        if !permitted(&inode, req.uid(), req.gid(), mask) {
+            return Ok(req.reply_error(libc::EACCES)?);
+        }
+
+        Ok(req.reply(())?)
+    }
+
+    async fn opendir(&self, req: &Request, op: op::Opendir<'_>) -> Result<()> {
+        let inode = self.meta.inode(op.ino()).await?;
+        if !inode.mode.is(FileType::Dir) {
+            return Ok(req.reply_error(libc::ENOTDIR)?);
+        }
+
+        let mut out = OpenOut::default();
+        out.fh(READ_FH);
+        Ok(req.reply(out)?)
+    }
+
     async fn readlink(&self, req: &Request, op: op::Readlink<'_>) -> Result<()> {
         let link = self.meta.inode(op.ino()).await?;
         if !link.mode.is(FileType::Link) {
@@ -146,12 +253,15 @@ where
             return Ok(req.reply_error(libc::EISDIR)?);
         };
 
+        if let Some(file) = self.opened(op.ino()).await {
+            return self.read_dirty(req, op, &*file.lock().await).await;
+        }
+
         let offset = op.offset() as usize;
         let size = op.size() as usize;
-        let chunk_size = CHUNK_SIZE; // file.block_size as usize;
-        let chunk_index = offset / chunk_size;
-
         let blocks = self.meta.blocks(op.ino()).await?;
+        // offset is now relative to the first block to read
+        let (chunk_index, offset) = locate(&blocks, offset, self.chunk_size);
 
         if chunk_index >= blocks.len() || op.size() == 0 {
             // reading after the end of the file
@@ -159,72 +269,39 @@ where
             return Ok(req.reply(data)?);
         }
 
-        // offset inside the file
-        let mut offset = offset - (chunk_index * chunk_size);
-        let mut buf: Vec<u8> = vec![0; size];
-        let mut total = 0;
-
-        'blocks: for block in blocks.iter().skip(chunk_index) {
-            // hash works as a key inside the LRU
-            let hash = block.id;
-
-            // getting the file descriptor from the LRU or from the cache if not found in the LRU
-            let lru = self.lru.lock().await.pop(&hash);
-
-            let (mut fd, block_size) = match lru {
-                Some((descriptor, bsize)) => {
-                    debug!("lru hit");
-                    (descriptor, bsize)
-                }
-                None => {
-                    let (bsize, descriptor) = match self.cache.get(block).await {
-                        Ok(out) => out,
-                        Err(err) => {
-                            error!("io cache error: {:#}", err);
-                            return Ok(req.reply_error(libc::EIO)?);
-                        }
-                    };
-                    (descriptor, bsize)
-                }
-            };
-
-            // seek to the position <offset>
-            fd.seek(SeekFrom::Start(offset as u64)).await?;
-
-            let mut chunk_offset = offset as u64;
-
-            loop {
-                // read the file bytes into buf
-                let read = match fd.read(&mut buf[total..]).await {
-                    Ok(n) => n,
-                    Err(err) => {
-                        error!("read error: {:#}", err);
-                        return Ok(req.reply_error(libc::EIO)?);
-                    }
-                };
-
-                chunk_offset += read as u64;
-
-                // calculate the total size and break if the required bytes (=size) downloaded
-                total += read;
-
-                if total >= size {
-                    // if only part of the block read -> store it in the lruf
-                    if chunk_offset < block_size {
-                        let mut lruf = self.lru.lock().await;
-                        lruf.put(hash, (fd, block_size));
-                    }
+        if self.sequential(op.ino(), op.offset(), op.size()).await {
+            self.prefetch(&blocks[chunk_index + 1..]).await;
+        }
 
-                    break 'blocks;
-                }
+        // all the blocks of the request are opened first (downloading the missing ones)
+        // then read on a single blocking task with one positioned read per block
+        let segments = match self.segments(&blocks[chunk_index..], offset, size).await {
+            Ok(segments) => segments,
+            Err(err) => {
+                error!("io cache error: {:#}", err);
+                return Ok(req.reply_error(libc::EIO)?);
+            }
+        };
 
-                // read = 0 means the EOF (end of the block)
-                if read == 0 {
-                    break;
-                }
+        let mut buf: Vec<u8> = vec![0; size];
+        let (mut segments, buf) = match task::spawn_blocking(move || {
+            read_segments(&segments, &mut buf).map(|_| (segments, buf))
+        })
+        .await?
+        {
+            Ok(out) => out,
+            Err(err) => {
+                error!("read error: {:#}", err);
+                return Ok(req.reply_error(libc::EIO)?);
             }
+        };
 
-            offset = 0;
+        // if only part of the last block is read -> store it in the lru
+        if let Some(last) = segments.pop() {
+            if last.offset + (last.len as u64) < last.block_size {
+                let mut lruf = self.lru.lock().await;
+                lruf.put(last.hash, (File::from_std(last.fd), last.block_size));
+            }
         }
 
         Ok(req.reply(&buf[..size])?)
@@ -233,7 +310,10 @@ where
     async fn getattr(&self, req: &Request, op: op::Getattr<'_>) -> Result<()> {
         log::debug!("getattr({})", op.ino());
 
-        let entry = self.meta.inode(op.ino()).await?;
+        let mut entry = self.meta.inode(op.ino()).await?;
+        if let Some(file) = self.opened(op.ino()).await {
+            entry.size = file.lock().await.size;
+        }
 
         let mut attr = AttrOut::default();
 
@@ -317,13 +397,16 @@ where
 
         let node = self.meta.lookup(op.parent(), name).await?;
 
-        let node = match node {
+        let mut node = match node {
             Some(node) => node,
             None => {
                 req.reply_error(libc::ENOENT)?;
                 return Ok(());
             }
         };
+        if let Some(file) = self.opened(node.ino).await {
+            node.size = file.lock().await.size;
+        }
         let mut out = EntryOut::default();
 
         node.fill(out.attr());
@@ -335,6 +418,547 @@ where
     }
 }
 
+// ==== read ====
+
+impl<S> Filesystem<S>
+where
+    S: Store,
+{
+    /// segments opens the blocks (downloading the missing ones) that cover size bytes
+    /// starting at offset inside the first block, and returns the part of each
+    /// block to read
+    async fn segments(
+        &self,
+        blocks: &[Block],
+        mut offset: usize,
+        size: usize,
+    ) -> Result<Vec<Segment>> {
+        let mut segments = Vec::new();
+        let mut total = 0;
+        for block in blocks {
+            if total >= size {
+                break;
+            }
+
+            // hash works as a key inside the LRU
+            let hash = block.id;
+
+            // getting the file descriptor from the LRU or from the cache if not found in the LRU
+            let lru = self.lru.lock().await.pop(&hash);
+
+            let (fd, block_size) = match lru {
+                Some((descriptor, bsize)) => {
+                    debug!("lru hit");
+                    (descriptor, bsize)
+                }
+                None => {
+                    let (bsize, descriptor) = self.cache.get(block).await?;
+                    (descriptor, bsize)
+                }
+            };
+
+            let len = (block_size.saturating_sub(offset as u64) as usize).min(size - total);
+            segments.push(Segment {
+                hash,
+                fd: fd.into_std().await,
+                block_size,
+                offset: offset as u64,
+                len,
+            });
+
+            total += len;
+            offset = 0;
+        }
+
+        Ok(segments)
+    }
+}
+
+// ==== prefetch ====
+
+impl<S> Filesystem<S>
+where
+    S: Store,
+{
+    /// sequential records the read and returns true if it starts where the
+    /// previous read of the same file ended
+    async fn sequential(&self, ino: Ino, offset: u64, size: u32) -> bool {
+        if self.prefetch == 0 {
+            return false;
+        }
+
+        let mut readers = self.readers.lock().await;
+        let sequential = matches!(readers.get(&ino), Some(end) if *end == offset);
+        readers.put(ino, offset + size as u64);
+
+        sequential
+    }
+
+    /// prefetch downloads the first blocks (up to the prefetch depth) in
+    /// the background to warm up the cache
+    async fn prefetch(&self, blocks: &[Block]) {
+        let mut prefetching = self.prefetching.lock().await;
+        for block in blocks.iter().take(self.prefetch) {
+            if !prefetching.insert(block.id) {
+                // already in progress
+                continue;
+            }
+
+            let cache = Arc::clone(&self.cache);
+            let inflight = Arc::clone(&self.prefetching);
+            let block = block.clone();
+            task::spawn(async move {
+                if let Err(err) = cache.get(&block).await {
+                    debug!("failed to prefetch block: {:#}", err);
+                }
+                inflight.lock().await.remove(&block.id);
+            });
+        }
+    }
+}
+
+// ==== write support ====
+
+impl<S> Filesystem<S>
+where
+    S: Store,
+{
+    /// opened returns the file if it is opened for writing
+    async fn opened(&self, ino: Ino) -> Option<Arc<Mutex<Dirty>>> {
+        self.dirty.lock().await.get(&ino).cloned()
+    }
+
+    /// prepare the file for writing, fails with EBUSY if the file is already
+    /// opened for writing
+    async fn prepare(&self, ino: Ino) -> std::result::Result<Arc<Mutex<Dirty>>, i32> {
+        let mut dirty = self.dirty.lock().await;
+        if dirty.contains_key(&ino) {
+            return Err(libc::EBUSY);
+        }
+
+        let inode = self.meta.inode(ino).await.map_err(|_| libc::ENOENT)?;
+        if !inode.mode.is(FileType::Regular) {
+            return Err(libc::EISDIR);
+        }
+
+        // writing to one link would leave the other links with the old content
+        if matches!(self.meta.hardlink(ino).await, Ok(Some(_)) | Err(_)) {
+            return Err(libc::ENOTSUP);
+        }
+
+        let blocks = self.meta.blocks(ino).await.map_err(|_| libc::EIO)?;
+        // only files split in blocks of chunk_size (fixed chunking) can be modified
+        let count = blocks.len();
+        let fixed = blocks.iter().enumerate().all(|(index, block)| {
+            index + 1 == count
+                || block
+                    .size
+                    .map(|size| size as usize == self.chunk_size)
+                    .unwrap_or(true)
+        });
+        if !fixed {
+            return Err(libc::ENOTSUP);
+        }
+
+        let file = Arc::new(Mutex::new(Dirty::new(blocks, inode.size, self.chunk_size)));
+        dirty.insert(ino, Arc::clone(&file));
+
+        Ok(file)
+    }
+
+    /// load the data of the block at index, the data is always of the block
+    /// length at the current file size
+    async fn load(&self, file: &Dirty, index: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::default();
+        if let Some(buffered) = file.get(index) {
+            data.extend_from_slice(buffered);
+        } else if file.base_len(index) > 0 {
+            let (_, mut fd) = self.cache.get(&file.base[index]).await?;
+            fd.rewind().await?;
+            fd.read_to_end(&mut data).await?;
+            data.truncate(file.base_len(index));
+        }
+
+        data.resize(file.block_len(index), 0);
+        Ok(data)
+    }
+
+    /// flush uploads all modified blocks of the file and updates the FL
+    async fn flush(&self, ino: Ino, file: &mut Dirty) -> Result<()> {
+        let writer = self.writer.as_ref().context("filesystem is read-only")?;
+
+        let mut blocks = Vec::with_capacity(file.count());
+        for index in 0..file.count() {
+            if file.is_clean(index) {
+                blocks.push(file.base[index].clone());
+                continue;
+            }
+
+            let data = self.load(file, index).await?;
+            blocks.push(self.cache.put(&data).await?);
+        }
+
+        writer.delete_blocks(ino).await?;
+        writer.blocks(ino, &blocks).await?;
+
+        let mut inode = self.meta.inode(ino).await?;
+        inode.size = file.size;
+        inode.mtime = now();
+        writer.update_inode(&inode).await?;
+
+        file.reset(blocks);
+        Ok(())
+    }
+
+    async fn open(&self, req: &Request, op: op::Open<'_>) -> Result<()> {
+        let mut out = OpenOut::default();
+        if op.flags() as i32 & libc::O_ACCMODE == libc::O_RDONLY {
+            out.fh(READ_FH);
+            return Ok(req.reply(out)?);
+        }
+
+        if self.writer.is_none() {
+            return Ok(req.reply_error(libc::EROFS)?);
+        }
+
+        let file = match self.prepare(op.ino()).await {
+            Ok(file) => file,
+            Err(errno) => return Ok(req.reply_error(errno)?),
+        };
+
+        if op.flags() as i32 & libc::O_TRUNC != 0 {
+            file.lock().await.truncate(0);
+        }
+
+        out.fh(WRITE_FH);
+        Ok(req.reply(out)?)
+    }
+
+    async fn write<T: Read>(&self, req: &Request, op: op::Write<'_>, mut data: T) -> Result<()> {
+        let file = match self.opened(op.ino()).await {
+            Some(file) => file,
+            None => return Ok(req.reply_error(libc::EBADF)?),
+        };
+
+        let mut content = vec![0; op.size() as usize];
+        data.read_exact(&mut content)?;
+
+        let mut file = file.lock().await;
+        let offset = op.offset() as usize;
+        for index in file.indices(offset, content.len()) {
+            if file.get(index).is_none() && file.base_len(index) > 0 {
+                let block = match self.load(&file, index).await {
+                    Ok(block) => block,
+                    Err(err) => {
+                        error!("failed to load block: {:#}", err);
+                        return Ok(req.reply_error(libc::EIO)?);
+                    }
+                };
+                file.insert(index, block);
+            }
+        }
+
+        file.write(offset, &content);
+
+        let mut out = WriteOut::default();
+        out.size(op.size());
+        Ok(req.reply(out)?)
+    }
+
+    async fn release(&self, req: &Request, op: op::Release<'_>) -> Result<()> {
+        if op.fh() != WRITE_FH {
+            return Ok(req.reply(())?);
+        }
+
+        let file = match self.dirty.lock().await.remove(&op.ino()) {
+            Some(file) => file,
+            None => return Ok(req.reply(())?),
+        };
+
+        if let Err(err) = self.flush(op.ino(), &mut *file.lock().await).await {
+            error!("failed to flush file: {:#}", err);
+            return Ok(req.reply_error(libc::EIO)?);
+        }
+
+        Ok(req.reply(())?)
+    }
+
+    async fn fsync(&self, req: &Request, op: op::Fsync<'_>) -> Result<()> {
+        if let Some(file) = self.opened(op.ino()).await {
+            if let Err(err) = self.flush(op.ino(), &mut *file.lock().await).await {
+                error!("failed to flush file: {:#}", err);
+                return Ok(req.reply_error(libc::EIO)?);
+            }
+        }
+
+        Ok(req.reply(())?)
+    }
+
+    async fn setattr(&self, req: &Request, op: op::Setattr<'_>) -> Result<()> {
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return Ok(req.reply_error(libc::EROFS)?),
+        };
+
+        if let Some(size) = op.size() {
+            match self.opened(op.ino()).await {
+                Some(file) => file.lock().await.truncate(size),
+                None => {
+                    // truncate of a file that is not open
+                    let file = match self.prepare(op.ino()).await {
+                        Ok(file) => file,
+                        Err(errno) => return Ok(req.reply_error(errno)?),
+                    };
+
+                    let result = {
+                        let mut file = file.lock().await;
+                        file.truncate(size);
+                        self.flush(op.ino(), &mut file).await
+                    };
+
+                    self.dirty.lock().await.remove(&op.ino());
+                    if let Err(err) = result {
+                        error!("failed to truncate file: {:#}", err);
+                        return Ok(req.reply_error(libc::EIO)?);
+                    }
+                }
+            }
+        }
+
+        let mut inode = self.meta.inode(op.ino()).await?;
+        if let Some(mode) = op.mode() {
+            inode.mode = Mode::new(inode.mode.file_type(), mode);
+        }
+        if let Some(uid) = op.uid() {
+            inode.uid = uid;
+        }
+        if let Some(gid) = op.gid() {
+            inode.gid = gid;
+        }
+        inode.ctime = now();
+        writer.update_inode(&inode).await?;
+
+        if let Some(file) = self.opened(op.ino()).await {
+            inode.size = file.lock().await.size;
+        }
+
+        let mut out = AttrOut::default();
+        inode.fill(out.attr());
+        Ok(req.reply(out)?)
+    }
+
+    /// new_inode adds a new inode under parent, it fails with EEXIST if the
+    /// name is already used
+    async fn new_inode(
+        &self,
+        req: &Request,
+        parent: Ino,
+        name: &std::ffi::OsStr,
+        mode: Mode,
+    ) -> std::result::Result<Inode, i32> {
+        let writer = self.writer.as_ref().ok_or(libc::EROFS)?;
+        let name = name.to_str().ok_or(libc::EINVAL)?;
+
+        let dir = self.meta.inode(parent).await.map_err(|_| libc::ENOENT)?;
+        if !dir.mode.is(FileType::Dir) {
+            return Err(libc::ENOTDIR);
+        }
+
+        if let Ok(Some(_)) = self.meta.lookup(parent, name).await {
+            return Err(libc::EEXIST);
+        }
+
+        let mut inode = Inode {
+            parent,
+            name: name.into(),
+            uid: req.uid(),
+            gid: req.gid(),
+            mode,
+            ctime: now(),
+            mtime: now(),
+            ..Inode::default()
+        };
+
+        inode.ino = writer.inode(inode.clone()).await.map_err(|err| {
+            error!("failed to create inode: {:#}", err);
+            libc::EIO
+        })?;
+
+        Ok(inode)
+    }
+
+    async fn create(&self, req: &Request, op: op::Create<'_>) -> Result<()> {
+        let mode = Mode::new(FileType::Regular, op.mode() & !op.umask());
+        let inode = match self.new_inode(req, op.parent(), op.name(), mode).await {
+            Ok(inode) => inode,
+            Err(errno) => return Ok(req.reply_error(errno)?),
+        };
+
+        if let Err(errno) = self.prepare(inode.ino).await {
+            return Ok(req.reply_error(errno)?);
+        }
+
+        let mut entry = EntryOut::default();
+        inode.fill(entry.attr());
+        entry.ino(inode.ino);
+        entry.ttl_attr(TTL);
+        entry.ttl_entry(TTL);
+
+        let mut open = OpenOut::default();
+        open.fh(WRITE_FH);
+
+        Ok(req.reply((entry, open))?)
+    }
+
+    async fn mkdir(&self, req: &Request, op: op::Mkdir<'_>) -> Result<()> {
+        let mode = Mode::new(FileType::Dir, op.mode() & !op.umask());
+        let inode = match self.new_inode(req, op.parent(), op.name(), mode).await {
+            Ok(inode) => inode,
+            Err(errno) => return Ok(req.reply_error(errno)?),
+        };
+
+        let mut entry = EntryOut::default();
+        inode.fill(entry.attr());
+        entry.ino(inode.ino);
+        entry.ttl_attr(TTL);
+        entry.ttl_entry(TTL);
+
+        Ok(req.reply(entry)?)
+    }
+
+    async fn unlink(&self, req: &Request, op: op::Unlink<'_>) -> Result<()> {
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return Ok(req.reply_error(libc::EROFS)?),
+        };
+
+        let name = match op.name().to_str() {
+            Some(name) => name,
+            None => return Ok(req.reply_error(libc::ENOENT)?),
+        };
+
+        let node = match self.meta.lookup(op.parent(), name).await? {
+            Some(node) => node,
+            None => return Ok(req.reply_error(libc::ENOENT)?),
+        };
+
+        if node.mode.is(FileType::Dir) {
+            return Ok(req.reply_error(libc::EISDIR)?);
+        }
+
+        if self.opened(node.ino).await.is_some() {
+            return Ok(req.reply_error(libc::EBUSY)?);
+        }
+
+        writer.delete_inode(node.ino).await?;
+        Ok(req.reply(())?)
+    }
+
+    async fn read_dirty(&self, req: &Request, op: op::Read<'_>, file: &Dirty) -> Result<()> {
+        let offset = op.offset() as usize;
+        if offset as u64 >= file.size || op.size() == 0 {
+            let data: &[u8] = &[];
+            return Ok(req.reply(data)?);
+        }
+
+        let size = std::cmp::min(op.size() as u64, file.size - offset as u64) as usize;
+        let mut buf = Vec::with_capacity(size);
+        for index in file.indices(offset, size) {
+            let block = match self.load(file, index).await {
+                Ok(block) => block,
+                Err(err) => {
+                    error!("io cache error: {:#}", err);
+                    return Ok(req.reply_error(libc::EIO)?);
+                }
+            };
+
+            let start = (offset + buf.len()) - index * self.chunk_size;
+            let end = std::cmp::min(block.len(), start + size - buf.len());
+            buf.extend_from_slice(&block[start..end]);
+        }
+
+        Ok(req.reply(&buf[..])?)
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// Segment is the part of a cached block that is read to reply to a read request
+struct Segment {
+    hash: FHash,
+    fd: std::fs::File,
+    block_size: BlockSize,
+    offset: u64,
+    len: usize,
+}
+
+/// read_segments fills buf with the content of the segments, one after the other. Each
+/// segment is read with a single positioned read, so the block files don't need to be
+/// seeked and a request spanning multiple blocks doesn't go back to the runtime between
+/// blocks.
+fn read_segments(segments: &[Segment], buf: &mut [u8]) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    let mut total = 0;
+    for segment in segments {
+        segment
+            .fd
+            .read_exact_at(&mut buf[total..total + segment.len], segment.offset)?;
+        total += segment.len;
+    }
+
+    Ok(())
+}
+
+/// locate finds the index of the block that holds the given file offset, and the
+/// offset inside that block. If the size of every block is known (variable sized blocks)
+/// the blocks are walked, otherwise all blocks are assumed to be of chunk_size.
+fn locate(blocks: &[Block], offset: usize, chunk_size: usize) -> (usize, usize) {
+    if blocks.iter().all(|block| block.size.is_some()) {
+        let mut start = 0;
+        for (index, block) in blocks.iter().enumerate() {
+            let end = start + block.size.unwrap_or_default() as usize;
+            if offset < end {
+                return (index, offset - start);
+            }
+            start = end;
+        }
+
+        return (blocks.len(), 0);
+    }
+
+    let index = offset / chunk_size;
+    (index, offset - (index * chunk_size))
+}
+
+/// permitted checks if the user is allowed to access the inode with the given access
+/// mask (R_OK, W_OK, X_OK), following the same rules as the kernel permission checks
+fn permitted(inode: &Inode, uid: u32, gid: u32, mask: i32) -> bool {
+    let mask = (mask & (libc::R_OK | libc::W_OK | libc::X_OK)) as u32;
+    let perm = inode.mode.permissions();
+
+    // root can read and write anything, but can only execute files with at
+    // least one execute bit set
+    if uid == 0 {
+        return mask & libc::X_OK as u32 == 0 || inode.mode.is(FileType::Dir) || perm & 0o111 != 0;
+    }
+
+    let granted = if uid == inode.uid {
+        (perm >> 6) & 0o7
+    } else if gid == inode.gid {
+        (perm >> 3) & 0o7
+    } else {
+        perm & 0o7
+    };
+
+    granted & mask == mask
+}
+
 // ==== AsyncSession ====
 
 struct AsyncSession {
@@ -405,3 +1029,163 @@ impl AttributeFiller for Inode {
         };
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_permitted() {
+        let inode = Inode {
+            uid: 1000,
+            gid: 100,
+            mode: Mode::new(FileType::Regular, 0o640),
+            ..Inode::default()
+        };
+
+        // owner
+        assert!(permitted(&inode, 1000, 1000, libc::R_OK | libc::W_OK));
+        assert!(!permitted(&inode, 1000, 1000, libc::X_OK));
+        // group
+        assert!(permitted(&inode, 1001, 100, libc::R_OK));
+        assert!(!permitted(&inode, 1001, 100, libc::W_OK));
+        // others
+        assert!(!permitted(&inode, 1001, 1001, libc::R_OK));
+        assert!(permitted(&inode, 1001, 1001, libc::F_OK));
+        // root can't execute a file without execute bits
+        assert!(permitted(&inode, 0, 0, libc::R_OK | libc::W_OK));
+        assert!(!permitted(&inode, 0, 0, libc::X_OK));
+
+        let dir = Inode {
+            mode: Mode::new(FileType::Dir, 0o700),
+            ..inode
+        };
+        assert!(permitted(&dir, 0, 0, libc::X_OK));
+        assert!(permitted(&dir, 1000, 100, libc::X_OK));
+        assert!(!permitted(&dir, 1001, 100, libc::X_OK));
+    }
+
+    #[test]
+    fn test_read_segments() {
+        use std::io::Write;
+
+        let mut blocks = Vec::new();
+        for content in [b"0123456789", b"abcdefghij", b"ABCDEFGHIJ"].iter() {
+            let mut file = tempfile::tempfile().unwrap();
+            file.write_all(*content).unwrap();
+            blocks.push(file);
+        }
+
+        let mut blocks = blocks.into_iter();
+        let segments = vec![
+            // tail of the first block, all of the second, head of the third
+            Segment {
+                hash: [0; 32],
+                fd: blocks.next().unwrap(),
+                block_size: 10,
+                offset: 6,
+                len: 4,
+            },
+            Segment {
+                hash: [1; 32],
+                fd: blocks.next().unwrap(),
+                block_size: 10,
+                offset: 0,
+                len: 10,
+            },
+            Segment {
+                hash: [2; 32],
+                fd: blocks.next().unwrap(),
+                block_size: 10,
+                offset: 0,
+                len: 3,
+            },
+        ];
+
+        let mut buf = vec![0; 17];
+        read_segments(&segments, &mut buf).unwrap();
+        assert_eq!(buf, b"6789abcdefghijABC");
+
+        // reading past the end of a block fails
+        let mut buf = vec![0; 5];
+        let segments = vec![Segment {
+            offset: 8,
+            len: 5,
+            ..segments.into_iter().next().unwrap()
+        }];
+        assert!(read_segments(&segments, &mut buf).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_chunk_size() {
+        use rfs::{store::mem::MemStore, ChunkingStrategy, PackOptions};
+
+        const ROOT: &str = "/tmp/fs-chunk-size-test";
+        let root = std::path::Path::new(ROOT);
+        let _ = tokio::fs::remove_dir_all(root).await;
+        tokio::fs::create_dir_all(root.join("source"))
+            .await
+            .unwrap();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        tokio::fs::write(root.join("source/file"), &data)
+            .await
+            .unwrap();
+
+        let store = MemStore::new();
+        let writer = Writer::new(root.join("test.fl"), true).await.unwrap();
+        rfs::pack(
+            writer,
+            store.clone(),
+            root.join("source"),
+            PackOptions {
+                strip_password: false,
+                chunking: ChunkingStrategy::Fixed(1000),
+                ..PackOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let reader = Reader::new(root.join("test.fl")).await.unwrap();
+        let cache = cache::Cache::new(root.join("cache"), store);
+        let filesystem = Filesystem::new(reader.clone(), cache, NonZeroUsize::new(1).unwrap())
+            .await
+            .unwrap();
+        // the block size is read from the FL tags
+        assert_eq!(filesystem.chunk_size, 1000);
+
+        let node = reader.lookup_path("/file").await.unwrap().unwrap();
+        let blocks = reader.blocks(node.ino).await.unwrap();
+        assert_eq!(blocks.len(), 10);
+        // FLs created before the block sizes were recorded only have the block-size tag
+        let without_sizes: Vec<Block> = blocks
+            .iter()
+            .map(|block| Block {
+                size: None,
+                ..block.clone()
+            })
+            .collect();
+
+        for (offset, size) in [
+            (0, 10),
+            (995, 10),
+            (999, 1),
+            (1000, 1),
+            (2500, 2000),
+            (9990, 10),
+        ] {
+            for blocks in [&blocks, &without_sizes] {
+                let (index, block_offset) = locate(blocks, offset, filesystem.chunk_size);
+                assert_eq!((index, block_offset), (offset / 1000, offset % 1000));
+
+                let segments = filesystem
+                    .segments(&blocks[index..], block_offset, size)
+                    .await
+                    .unwrap();
+                let mut buf = vec![0; size];
+                read_segments(&segments, &mut buf).unwrap();
+                assert_eq!(buf, &data[offset..offset + size], "offset {}", offset);
+            }
+        }
+    }
+}
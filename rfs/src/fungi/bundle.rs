@@ -0,0 +1,299 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use super::{Error, Reader, Result};
+
+// magic number of a zstd frame, all bundles start with it
+const MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+// magic number of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// header of a plain FL (an sqlite database)
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+// max number of compression layers of an FL, a .tar.gz has 2
+const MAX_LAYERS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zstd,
+    Gzip,
+    Tar,
+}
+
+/// is_bundle checks if the file at the given path is a bundle, missing and
+/// short files are not bundles
+pub fn is_bundle<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut magic = [0; 4];
+    match file.read_exact(&mut magic) {
+        Ok(_) => Ok(magic == MAGIC),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// export writes the FL (plain or bundle) at `fl` as a bundle: the FL compressed with
+/// the given zstd level into a single portable file. The FL is copied with `VACUUM INTO`
+/// first, so the bundle also holds the content of the journal. Bundles are opened
+/// transparently by [`Reader::new`].
+pub async fn export<P: AsRef<Path>, Q: AsRef<Path>>(fl: P, out: Q, level: i32) -> Result<()> {
+    let out = out.as_ref();
+    let dir = match out.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    // sqlite refuses to vacuum into an existing file
+    let db = tempfile::Builder::new()
+        .prefix(".fl-export")
+        .tempfile_in(dir)?
+        .into_temp_path();
+    std::fs::remove_file(&db)?;
+
+    let reader = Reader::new(fl).await?;
+    reader.vacuum_into(&db).await?;
+
+    compress(db.to_path_buf(), out.into(), level).await
+}
+
+/// import decompresses the bundle at `bundle` into a plain FL at `out`
+pub async fn import<P: AsRef<Path>, Q: AsRef<Path>>(bundle: P, out: Q) -> Result<()> {
+    let bundle = bundle.as_ref();
+    if !is_bundle(bundle)? {
+        return Err(Error::Anyhow(anyhow::anyhow!(
+            "'{}' is not an fl bundle",
+            bundle.display()
+        )));
+    }
+
+    decompress(bundle.into(), out.as_ref().into()).await
+}
+
+/// extract decompresses the FL at the given path if it's a bundle, a gzip compressed FL
+/// or a tar archive holding the FL, and any combination of those (like the .tar.gz FLs
+/// downloaded from the hub). It returns the path of the decompressed FL, which is removed
+/// once dropped, or None if the FL is not compressed.
+pub(crate) async fn extract(path: PathBuf) -> Result<Option<tempfile::TempPath>> {
+    tokio::task::spawn_blocking(move || -> Result<Option<tempfile::TempPath>> {
+        let mut plain: Option<tempfile::TempPath> = None;
+        let mut layers = 0;
+        loop {
+            let src: &Path = match &plain {
+                Some(plain) => plain.as_ref(),
+                None => path.as_path(),
+            };
+
+            let format = match format(src)? {
+                Some(format) => format,
+                None => return Ok(plain),
+            };
+
+            if layers == MAX_LAYERS {
+                return Err(Error::Anyhow(anyhow::anyhow!(
+                    "'{}' has too many compression layers",
+                    path.display()
+                )));
+            }
+            layers += 1;
+
+            let out = tempfile::NamedTempFile::new()?.into_temp_path();
+            let input = File::open(src)?;
+            let mut output = File::create(&out)?;
+            match format {
+                Format::Zstd => zstd::stream::copy_decode(input, output)?,
+                Format::Gzip => {
+                    std::io::copy(&mut flate2::read::GzDecoder::new(input), &mut output)?;
+                }
+                Format::Tar => untar(input, &mut output)?,
+            }
+
+            plain = Some(out);
+        }
+    })
+    .await
+    .context("fl decompression panicked")?
+}
+
+// format detects the compression of the file from its header, None means
+// the file is not compressed
+fn format(path: &Path) -> Result<Option<Format>> {
+    let mut header = Vec::with_capacity(512);
+    File::open(path)?.take(512).read_to_end(&mut header)?;
+
+    let format = if header.starts_with(&MAGIC) {
+        Some(Format::Zstd)
+    } else if header.starts_with(&GZIP_MAGIC) {
+        Some(Format::Gzip)
+    } else if header.get(257..262) == Some(&b"ustar"[..]) {
+        Some(Format::Tar)
+    } else {
+        None
+    };
+
+    Ok(format)
+}
+
+// untar copies the first sqlite database found in the tar archive to output
+fn untar<R: Read, W: Write>(input: R, output: &mut W) -> Result<()> {
+    let mut archive = tar::Archive::new(input);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let mut magic = Vec::with_capacity(SQLITE_MAGIC.len());
+        (&mut entry)
+            .take(SQLITE_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        if magic != SQLITE_MAGIC {
+            continue;
+        }
+
+        output.write_all(&magic)?;
+        std::io::copy(&mut entry, output)?;
+        return Ok(());
+    }
+
+    Err(Error::Anyhow(anyhow::anyhow!(
+        "archive doesn't contain an fl"
+    )))
+}
+
+pub(crate) async fn compress(src: PathBuf, dst: PathBuf, level: i32) -> Result<()> {
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let input = File::open(&src)?;
+        let output = File::create(&dst)?;
+        zstd::stream::copy_encode(input, output, level)?;
+        Ok(())
+    })
+    .await
+    .context("bundle compression panicked")?
+}
+
+pub(crate) async fn decompress(src: PathBuf, dst: PathBuf) -> Result<()> {
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let input = File::open(&src)?;
+        let output = File::create(&dst)?;
+        zstd::stream::copy_decode(input, output)?;
+        Ok(())
+    })
+    .await
+    .context("bundle decompression panicked")?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fungi::meta::{FileType, Inode, Mode};
+    use crate::fungi::Writer;
+
+    #[tokio::test]
+    async fn test_export_import() {
+        const ROOT: &str = "/tmp/fl-bundle-test";
+        let _ = tokio::fs::remove_dir_all(ROOT).await;
+        tokio::fs::create_dir_all(ROOT).await.unwrap();
+        let root = Path::new(ROOT);
+
+        let writer = Writer::new(root.join("plain.fl"), true).await.unwrap();
+        writer
+            .inode(Inode {
+                name: "/".into(),
+                mode: Mode::new(FileType::Dir, 0o755),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+        writer.finish().await.unwrap();
+
+        assert!(!is_bundle(root.join("plain.fl")).unwrap());
+        assert!(!is_bundle(root.join("missing.fl")).unwrap());
+
+        export(root.join("plain.fl"), root.join("bundle.fl"), 3)
+            .await
+            .unwrap();
+        assert!(is_bundle(root.join("bundle.fl")).unwrap());
+
+        // bundles are opened transparently
+        let reader = Reader::new(root.join("bundle.fl")).await.unwrap();
+        assert_eq!(reader.inode(1).await.unwrap().name, "/");
+
+        import(root.join("bundle.fl"), root.join("imported.fl"))
+            .await
+            .unwrap();
+        assert!(!is_bundle(root.join("imported.fl")).unwrap());
+        let reader = Reader::new(root.join("imported.fl")).await.unwrap();
+        assert_eq!(reader.inode(1).await.unwrap().name, "/");
+
+        assert!(import(root.join("plain.fl"), root.join("other.fl"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_extract() {
+        use flate2::{write::GzEncoder, Compression};
+
+        const ROOT: &str = "/tmp/fl-extract-test";
+        let _ = tokio::fs::remove_dir_all(ROOT).await;
+        tokio::fs::create_dir_all(ROOT).await.unwrap();
+        let root = Path::new(ROOT);
+
+        let writer = Writer::new(root.join("plain.fl"), true).await.unwrap();
+        writer
+            .inode(Inode {
+                name: "/".into(),
+                mode: Mode::new(FileType::Dir, 0o755),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+        writer.finish().await.unwrap();
+        assert!(extract(root.join("plain.fl")).await.unwrap().is_none());
+
+        // gzip
+        let mut encoder = GzEncoder::new(
+            File::create(root.join("fl.gz")).unwrap(),
+            Compression::default(),
+        );
+        std::io::copy(
+            &mut File::open(root.join("plain.fl")).unwrap(),
+            &mut encoder,
+        )
+        .unwrap();
+        encoder.finish().unwrap();
+
+        // tar.gz with another file before the fl
+        std::fs::write(root.join("README"), b"not an fl").unwrap();
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(root.join("fl.tar.gz")).unwrap(),
+            Compression::default(),
+        ));
+        builder
+            .append_path_with_name(root.join("README"), "README")
+            .unwrap();
+        builder
+            .append_path_with_name(root.join("plain.fl"), "flistdb.sqlite3")
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        for name in ["fl.gz", "fl.tar.gz"].iter() {
+            let reader = Reader::new(root.join(name)).await.unwrap();
+            assert_eq!(reader.inode(1).await.unwrap().name, "/", "{}", name);
+        }
+
+        // an archive without an fl
+        let mut builder = tar::Builder::new(File::create(root.join("other.tar")).unwrap());
+        builder
+            .append_path_with_name(root.join("README"), "README")
+            .unwrap();
+        builder.finish().unwrap();
+        assert!(Reader::new(root.join("other.tar")).await.is_err());
+    }
+}
@@ -1,18 +1,23 @@
 use std::{
-    collections::LinkedList,
+    collections::{HashMap, LinkedList, VecDeque},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use sqlx::{
-    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteRow},
-    FromRow, Row, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+    FromRow, Row, SqliteConnection, SqlitePool,
 };
 
+pub use sqlx::sqlite::{SqliteJournalMode as JournalMode, SqliteSynchronous as Synchronous};
+
 use crate::store;
 
 const ID_LEN: usize = 32;
 const KEY_LEN: usize = 32;
 const TYPE_MASK: u32 = nix::libc::S_IFMT;
+/// number of children fetched from the FL at once while walking it
+pub const WALK_BATCH: u32 = 1000;
 
 #[repr(u32)]
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -132,12 +137,15 @@ impl FromRow<'_, SqliteRow> for Inode {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Block {
     /// id of the block
     pub id: [u8; ID_LEN],
     /// encryption key of the block
     pub key: [u8; KEY_LEN],
+    /// size of the (plain) block data, not known for FLs created
+    /// before block sizes were recorded
+    pub size: Option<u64>,
 }
 
 impl FromRow<'_, SqliteRow> for Block {
@@ -149,13 +157,19 @@ impl FromRow<'_, SqliteRow> for Block {
 
         let key: &[u8] = row.get("key");
 
-        if hash.len() != KEY_LEN {
+        if key.len() != KEY_LEN {
             return Err(sqlx::Error::Decode(Box::new(Error::InvalidKey)));
         }
 
         let mut block = Self::default();
         block.id.copy_from_slice(hash);
         block.key.copy_from_slice(key);
+        // the size column doesn't exist in older FLs
+        block.size = row
+            .try_get::<Option<i64>, &str>("size")
+            .ok()
+            .flatten()
+            .map(|size| size as u64);
 
         Ok(block)
     }
@@ -183,6 +197,10 @@ pub enum Tag<'a> {
     Version,
     Description,
     Author,
+    BlockSize,
+    Replicas,
+    Compression,
+    Startup,
     Custom(&'a str),
 }
 
@@ -192,11 +210,74 @@ impl<'a> Tag<'a> {
             Self::Version => "version",
             Self::Description => "description",
             Self::Author => "author",
+            Self::BlockSize => "block-size",
+            Self::Replicas => "replicas",
+            Self::Compression => "compression",
+            Self::Startup => "startup",
             Self::Custom(a) => a,
         }
     }
 }
 
+/// Problem is a referential integrity issue found by [`Reader::fsck`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// there is no root inode (ino 1)
+    MissingRoot,
+    /// the parent of the inode doesn't exist
+    OrphanInode { ino: Ino, parent: Ino },
+    /// the block row references an inode that doesn't exist
+    OrphanBlock { rowid: i64, ino: Ino },
+    /// the extra row references an inode that doesn't exist
+    OrphanExtra { rowid: i64, ino: Ino },
+    /// the xattr row references an inode that doesn't exist
+    OrphanXattr { rowid: i64, ino: Ino },
+    /// the hardlink row references an inode (link or target) that doesn't exist
+    OrphanHardlink { rowid: i64, ino: Ino },
+    /// the file_hash row references an inode that doesn't exist
+    OrphanFileHash { rowid: i64, ino: Ino },
+    /// the route row has its start after its end
+    InvalidRoute { rowid: i64, start: u8, end: u8 },
+    /// no route covers the blocks with ids starting with a byte in this range
+    RouteGap { start: u8, end: u8 },
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingRoot => write!(f, "missing root inode"),
+            Self::OrphanInode { ino, parent } => {
+                write!(f, "inode {}: parent inode {} doesn't exist", ino, parent)
+            }
+            Self::OrphanBlock { rowid, ino } => {
+                write!(f, "block {}: inode {} doesn't exist", rowid, ino)
+            }
+            Self::OrphanExtra { rowid, ino } => {
+                write!(f, "extra {}: inode {} doesn't exist", rowid, ino)
+            }
+            Self::OrphanXattr { rowid, ino } => {
+                write!(f, "xattr {}: inode {} doesn't exist", rowid, ino)
+            }
+            Self::OrphanHardlink { rowid, ino } => {
+                write!(f, "hardlink {}: inode {} doesn't exist", rowid, ino)
+            }
+            Self::OrphanFileHash { rowid, ino } => {
+                write!(f, "file_hash {}: inode {} doesn't exist", rowid, ino)
+            }
+            Self::InvalidRoute { rowid, start, end } => {
+                write!(
+                    f,
+                    "route {}: invalid range {:02x}-{:02x}",
+                    rowid, start, end
+                )
+            }
+            Self::RouteGap { start, end } => {
+                write!(f, "no route for range {:02x}-{:02x}", start, end)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Walk {
     Continue,
@@ -209,20 +290,67 @@ pub trait WalkVisitor {
 
 struct WalkItem(PathBuf, Inode);
 
+// directory being walked by walk_streaming with the next children to visit
+struct WalkFrame {
+    path: PathBuf,
+    ino: Ino,
+    // ino of the last child fetched from the FL
+    last: Ino,
+    children: VecDeque<Inode>,
+}
+
+/// FlistStats is a summary of the content of an FL
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlistStats {
+    pub total_inodes: u64,
+    /// total size of the regular files
+    pub total_size: u64,
+    pub regular: u64,
+    pub dir: u64,
+    pub link: u64,
+    pub block_count: u64,
+}
+
 #[derive(Clone)]
 pub struct Reader {
     pool: SqlitePool,
+    // decompressed copy of a compressed FL, removed when the last clone is dropped
+    _plain: Option<Arc<tempfile::TempPath>>,
 }
 
 impl Reader {
+    /// new opens the FL at the given path, which is either a plain FL, a bundle
+    /// (see [`super::bundle::export`]) or an FL compressed with gzip or archived
+    /// with tar (like the .tar.gz FLs of the hub)
     pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let opts = SqliteConnectOptions::new()
-            .journal_mode(SqliteJournalMode::Delete)
-            .filename(path);
+        let path = path.as_ref();
+        let plain = super::bundle::extract(path.into()).await?.map(Arc::new);
+
+        let db: &Path = match &plain {
+            Some(db) => db,
+            None => path,
+        };
+
+        // the journal mode is left as is, changing it while a writer
+        // is using the FL requires an exclusive lock
+        let opts = SqliteConnectOptions::new().filename(db);
 
         let pool = SqlitePool::connect_with(opts).await?;
 
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            _plain: plain,
+        })
+    }
+
+    /// vacuum_into writes a compacted copy of the FL to the given path
+    pub(crate) async fn vacuum_into<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        sqlx::query("vacuum into ?;")
+            .bind(path.as_ref().to_string_lossy().into_owned())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
     }
 
     pub async fn inode(&self, ino: Ino) -> Result<Inode> {
@@ -260,7 +388,7 @@ impl Reader {
     }
 
     pub async fn blocks(&self, ino: Ino) -> Result<Vec<Block>> {
-        let results: Vec<Block> = sqlx::query_as("select id, key from block where ino = ?;")
+        let results: Vec<Block> = sqlx::query_as("select * from block where ino = ?;")
             .bind(ino as i64)
             .fetch_all(&self.pool)
             .await?;
@@ -268,8 +396,198 @@ impl Reader {
         Ok(results)
     }
 
+    /// file_hash returns the digest of the content of the file (see [`file_hash`]), it's
+    /// None if the file has no hash, for example in FLs created before file hashes
+    pub async fn file_hash(&self, ino: Ino) -> Result<Option<[u8; 32]>> {
+        let result: std::result::Result<Option<(Vec<u8>,)>, _> =
+            sqlx::query_as("select hash from file_hash where ino = ?;")
+                .bind(ino as i64)
+                .fetch_optional(&self.pool)
+                .await;
+
+        match result {
+            Ok(Some((hash,))) if hash.len() == ID_LEN => {
+                let mut digest = [0; ID_LEN];
+                digest.copy_from_slice(&hash);
+                Ok(Some(digest))
+            }
+            Ok(Some(_)) => Err(Error::InvalidHash),
+            Ok(None) => Ok(None),
+            // FLs created before file hashes don't have the table
+            Err(err) if is_missing_table(&err) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// blocks_count returns the total number of blocks in the FL
+    pub async fn blocks_count(&self) -> Result<u64> {
+        let count: (i64,) = sqlx::query_as("select count(*) from block;")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count.0 as u64)
+    }
+
+    /// fsck checks the referential integrity of the FL tables, it returns all the
+    /// problems found. An FL is healthy if the list is empty.
+    pub async fn fsck(&self) -> Result<Vec<Problem>> {
+        let mut problems = Vec::new();
+
+        let root: Option<(i64,)> = sqlx::query_as("select ino from inode where ino = 1;")
+            .fetch_optional(&self.pool)
+            .await?;
+        if root.is_none() {
+            problems.push(Problem::MissingRoot);
+        }
+
+        let inodes: Vec<(i64, i64)> = sqlx::query_as(
+            r#"select ino, parent from inode
+                    where ino != 1 and parent not in (select ino from inode);"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        problems.extend(
+            inodes
+                .into_iter()
+                .map(|(ino, parent)| Problem::OrphanInode {
+                    ino: ino as Ino,
+                    parent: parent as Ino,
+                }),
+        );
+
+        let blocks: Vec<(i64, i64)> = sqlx::query_as(
+            "select rowid, ino from block where ino not in (select ino from inode);",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        problems.extend(blocks.into_iter().map(|(rowid, ino)| Problem::OrphanBlock {
+            rowid,
+            ino: ino as Ino,
+        }));
+
+        let extras: Vec<(i64, i64)> = sqlx::query_as(
+            "select rowid, ino from extra where ino not in (select ino from inode);",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        problems.extend(extras.into_iter().map(|(rowid, ino)| Problem::OrphanExtra {
+            rowid,
+            ino: ino as Ino,
+        }));
+
+        let xattrs = self
+            .orphans("select rowid, ino from xattr where ino not in (select ino from inode);")
+            .await?;
+        problems.extend(xattrs.into_iter().map(|(rowid, ino)| Problem::OrphanXattr {
+            rowid,
+            ino: ino as Ino,
+        }));
+
+        // both the link and the target of the group must exist
+        let hardlinks = self
+            .orphans(
+                r#"select rowid, ino from hardlink where ino not in (select ino from inode)
+                    union all
+                    select rowid, target from hardlink where target not in (select ino from inode)
+                    order by rowid;"#,
+            )
+            .await?;
+        problems.extend(
+            hardlinks
+                .into_iter()
+                .map(|(rowid, ino)| Problem::OrphanHardlink {
+                    rowid,
+                    ino: ino as Ino,
+                }),
+        );
+
+        let hashes = self
+            .orphans("select rowid, ino from file_hash where ino not in (select ino from inode);")
+            .await?;
+        problems.extend(
+            hashes
+                .into_iter()
+                .map(|(rowid, ino)| Problem::OrphanFileHash {
+                    rowid,
+                    ino: ino as Ino,
+                }),
+        );
+
+        let routes: Vec<(i64, u8, u8)> = sqlx::query_as("select rowid, start, end from route;")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut covered = [false; 256];
+        for (rowid, start, end) in routes {
+            if start > end {
+                problems.push(Problem::InvalidRoute { rowid, start, end });
+                continue;
+            }
+            covered[start as usize..=end as usize].fill(true);
+        }
+
+        let mut gap: Option<usize> = None;
+        for (i, covered) in covered.iter().chain(std::iter::once(&true)).enumerate() {
+            match (gap, covered) {
+                (None, false) => gap = Some(i),
+                (Some(start), true) => {
+                    problems.push(Problem::RouteGap {
+                        start: start as u8,
+                        end: (i - 1) as u8,
+                    });
+                    gap = None;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(problems)
+    }
+
+    // orphans returns the (rowid, ino) rows of the query, FLs created before the
+    // table was added have no rows
+    async fn orphans(&self, query: &str) -> Result<Vec<(i64, i64)>> {
+        match sqlx::query_as(query).fetch_all(&self.pool).await {
+            Ok(rows) => Ok(rows),
+            Err(err) if is_missing_table(&err) => Ok(Vec::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// stats computes the FL summary with aggregate queries, without walking the inodes
+    pub async fn stats(&self) -> Result<FlistStats> {
+        let types: Vec<(i64, i64, i64)> = sqlx::query_as(
+            r#"select mode & ? as typ, count(*), coalesce(sum(size), 0)
+                                from inode group by typ;"#,
+        )
+        .bind(nix::libc::S_IFMT as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut stats = FlistStats {
+            block_count: self.blocks_count().await?,
+            ..FlistStats::default()
+        };
+
+        for (typ, count, size) in types {
+            let count = count as u64;
+            stats.total_inodes += count;
+            match FileType::from(typ as u32) {
+                FileType::Regular => {
+                    stats.regular = count;
+                    stats.total_size = size as u64;
+                }
+                FileType::Dir => stats.dir = count,
+                FileType::Link => stats.link = count,
+                _ => {}
+            }
+        }
+
+        Ok(stats)
+    }
+
     pub async fn all_blocks(&self, limit: u32, offset: u64) -> Result<Vec<Block>> {
-        let results: Vec<Block> = sqlx::query_as("select id, key from block limit ? offset ?;")
+        let results: Vec<Block> = sqlx::query_as("select * from block limit ? offset ?;")
             .bind(limit)
             .bind(offset as i64)
             .fetch_all(&self.pool)
@@ -278,6 +596,39 @@ impl Reader {
         Ok(results)
     }
 
+    /// xattrs returns the extended attributes (name, value) of the given inode
+    pub async fn xattrs(&self, ino: Ino) -> Result<Vec<(String, Vec<u8>)>> {
+        let result = sqlx::query_as("select name, value from xattr where ino = ?;")
+            .bind(ino as i64)
+            .fetch_all(&self.pool)
+            .await;
+
+        match result {
+            Ok(xattrs) => Ok(xattrs),
+            // FLs created before xattrs support don't have the table
+            Err(err) if is_missing_table(&err) => Ok(Vec::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// hardlink returns the hardlink group of the inode if the inode has multiple links. All
+    /// inodes of the same group are links to the same file, the group is the inode of the
+    /// first packed link.
+    pub async fn hardlink(&self, ino: Ino) -> Result<Option<Ino>> {
+        let result: std::result::Result<Option<(i64,)>, _> =
+            sqlx::query_as("select target from hardlink where ino = ?;")
+                .bind(ino as i64)
+                .fetch_optional(&self.pool)
+                .await;
+
+        match result {
+            Ok(target) => Ok(target.map(|t| t.0 as Ino)),
+            // FLs created before hardlinks support don't have the table
+            Err(err) if is_missing_table(&err) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     pub async fn tag(&self, tag: Tag<'_>) -> Result<Option<String>> {
         let value: Option<(String,)> = sqlx::query_as("select value from tag where key = ?;")
             .bind(tag.key())
@@ -295,6 +646,42 @@ impl Reader {
         Ok(tags)
     }
 
+    /// all_tags returns all the tags of the FL, standard and custom, by key
+    pub async fn all_tags(&self) -> Result<HashMap<String, String>> {
+        Ok(self.tags().await?.into_iter().collect())
+    }
+
+    /// meta returns the value of the given metadata key (see [`Writer::meta`])
+    pub async fn meta<K: AsRef<str>>(&self, key: K) -> Result<Option<String>> {
+        let result: std::result::Result<Option<(String,)>, _> =
+            sqlx::query_as("select value from metadata where key = ?;")
+                .bind(key.as_ref())
+                .fetch_optional(&self.pool)
+                .await;
+
+        match result {
+            Ok(value) => Ok(value.map(|v| v.0)),
+            // FLs created before metadata support don't have the table
+            Err(err) if is_missing_table(&err) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// metadata returns all the metadata of the FL sorted by key
+    pub async fn metadata(&self) -> Result<Vec<(String, String)>> {
+        let result: std::result::Result<Vec<(String, String)>, _> =
+            sqlx::query_as("select key, value from metadata order by key;")
+                .fetch_all(&self.pool)
+                .await;
+
+        match result {
+            Ok(metadata) => Ok(metadata),
+            // FLs created before metadata support don't have the table
+            Err(err) if is_missing_table(&err) => Ok(Vec::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     pub async fn routes(&self) -> Result<Vec<Route>> {
         let results: Vec<Route> = sqlx::query_as("select start, end, url from route;")
             .fetch_all(&self.pool)
@@ -303,11 +690,48 @@ impl Reader {
         Ok(results)
     }
 
+    /// lookup_path finds the inode of the given absolute path by
+    /// walking the path components from the root inode
+    pub async fn lookup_path<P: AsRef<Path>>(&self, path: P) -> Result<Option<Inode>> {
+        use std::path::Component;
+
+        let mut node = self.inode(1).await?;
+        for component in path.as_ref().components() {
+            let name = match component {
+                Component::RootDir | Component::CurDir => continue,
+                Component::Normal(name) => name.to_string_lossy(),
+                _ => {
+                    return Err(Error::Anyhow(anyhow::anyhow!(
+                        "invalid path component {:?}",
+                        component
+                    )))
+                }
+            };
+
+            node = match self.lookup(node.ino, name).await? {
+                Some(node) => node,
+                None => return Ok(None),
+            };
+        }
+
+        Ok(Some(node))
+    }
+
     pub async fn walk<W: WalkVisitor + Send>(&self, visitor: &mut W) -> Result<()> {
         let node = self.inode(1).await?;
+        self.walk_from(node, "/", visitor).await
+    }
+
+    /// walk_from walks the sub tree of the given node, the node itself is visited
+    /// with the given path.
+    pub async fn walk_from<P: Into<PathBuf>, W: WalkVisitor + Send>(
+        &self,
+        node: Inode,
+        path: P,
+        visitor: &mut W,
+    ) -> Result<()> {
         let mut list = LinkedList::default();
-        let path: PathBuf = "/".into();
-        list.push_back(WalkItem(path, node));
+        list.push_back(WalkItem(path.into(), node));
         while !list.is_empty() {
             let item = list.pop_back().unwrap();
             self.walk_node(&mut list, &item, visitor).await?;
@@ -316,6 +740,83 @@ impl Reader {
         Ok(())
     }
 
+    /// walk_streaming walks the FL depth first (each directory is visited before its
+    /// content). Unlike [`Reader::walk`] it never queues the subdirectories of a
+    /// directory, only the next `batch` children of each directory of the current path
+    /// are held in memory, so memory is bounded by `depth * batch` inodes however wide
+    /// the FL is. Children are visited in inode order.
+    ///
+    /// Returning [`Walk::Break`] for a directory skips its content, for any other entry
+    /// it skips the remaining entries of its directory.
+    pub async fn walk_streaming<W: WalkVisitor + Send>(
+        &self,
+        batch: u32,
+        visitor: &mut W,
+    ) -> Result<()> {
+        let root = self.inode(1).await?;
+        let path = PathBuf::from("/");
+        if visitor.visit(&path, &root).await? == Walk::Break {
+            return Ok(());
+        }
+
+        let batch = batch.max(1);
+        let mut stack = vec![WalkFrame {
+            path,
+            ino: root.ino,
+            last: 0,
+            children: VecDeque::default(),
+        }];
+
+        while let Some(frame) = stack.last_mut() {
+            if frame.children.is_empty() {
+                frame.children = self.children_after(frame.ino, frame.last, batch).await?;
+                match frame.children.back() {
+                    Some(last) => frame.last = last.ino,
+                    None => {
+                        stack.pop();
+                        continue;
+                    }
+                }
+            }
+
+            let child = frame.children.pop_front().unwrap();
+            let child_path = frame.path.join(&child.name);
+            let walk = visitor.visit(&child_path, &child).await?;
+            match (child.mode.is(FileType::Dir), walk) {
+                (true, Walk::Continue) => stack.push(WalkFrame {
+                    path: child_path,
+                    ino: child.ino,
+                    last: 0,
+                    children: VecDeque::default(),
+                }),
+                (false, Walk::Break) => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// children_after returns up to limit children of the parent with an ino greater
+    /// than after, in ino order. Unlike an offset, the cost doesn't grow with the
+    /// number of children already returned
+    async fn children_after(&self, parent: Ino, after: Ino, limit: u32) -> Result<VecDeque<Inode>> {
+        let results: Vec<Inode> = sqlx::query_as(
+            r#"select inode.*, extra.data
+                                from inode left join extra on inode.ino = extra.ino
+                                where inode.parent = ? and inode.ino > ? order by inode.ino limit ?;"#,
+        )
+        .bind(parent as i64)
+        .bind(after as i64)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(results.into())
+    }
+
     async fn walk_node<W: WalkVisitor + Send>(
         &self,
         list: &mut LinkedList<WalkItem>,
@@ -328,7 +829,7 @@ impl Reader {
 
         let mut offset = 0;
         loop {
-            let children = self.children(node.ino, 1000, offset).await?;
+            let children = self.children(node.ino, WALK_BATCH, offset).await?;
             if children.is_empty() {
                 break;
             }
@@ -351,21 +852,76 @@ impl Reader {
     }
 }
 
+fn is_missing_table(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(err) if err.message().contains("no such table"))
+}
+
+/// remove deletes the FL at path together with the `-wal` and `-shm` files the
+/// WAL journal leaves next to it until the writer is finished. Missing files are
+/// ignored.
+pub async fn remove<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let sidecar = |suffix: &str| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+
+    for path in [path.to_path_buf(), sidecar("-wal"), sidecar("-shm")] {
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// WriterOptions tunes how the FL database is written. The default (WAL journal
+/// with normal synchronous) syncs far less than the delete journal, which makes
+/// packing much faster on slow disks.
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Normal,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Writer {
     pool: SqlitePool,
 }
 
 impl Writer {
-    /// create a new mkondo writer
+    /// create a new mkondo writer with the default options
     pub async fn new<P: AsRef<Path>>(path: P, remove: bool) -> Result<Self> {
+        Self::with_options(path, remove, WriterOptions::default()).await
+    }
+
+    /// create a new mkondo writer. With the WAL journal mode, [`Writer::finish`]
+    /// must be called once all writes are done to get a self contained FL.
+    pub async fn with_options<P: AsRef<Path>>(
+        path: P,
+        remove: bool,
+        options: WriterOptions,
+    ) -> Result<Self> {
         if remove {
-            let _ = tokio::fs::remove_file(&path).await;
+            // a WAL left over from an older FL at the same path must not be replayed
+            let _ = self::remove(&path).await;
         }
 
         let opts = SqliteConnectOptions::new()
             .create_if_missing(true)
-            .journal_mode(SqliteJournalMode::Delete)
+            .journal_mode(options.journal_mode)
+            .synchronous(options.synchronous)
             .filename(path);
 
         let pool = SqlitePoolOptions::new()
@@ -374,45 +930,169 @@ impl Writer {
             .await?;
 
         sqlx::query(SCHEMA).execute(&pool).await?;
+        migrate(&pool).await?;
 
         Ok(Self { pool })
     }
 
+    /// finish moves all the content of the WAL (if any) into the FL file and switches
+    /// it back to the delete journal mode, so the FL can be shipped as a single file
+    /// and opened from read-only locations. It fails if the FL is open elsewhere.
+    pub async fn finish(&self) -> Result<()> {
+        sqlx::query("pragma wal_checkpoint(truncate);")
+            .execute(&self.pool)
+            .await?;
+        let (mode,): (String,) = sqlx::query_as("pragma journal_mode = delete;")
+            .fetch_one(&self.pool)
+            .await?;
+        if !mode.eq_ignore_ascii_case("delete") {
+            return Err(
+                anyhow::anyhow!("failed to switch journal mode, FL is in '{}' mode", mode).into(),
+            );
+        }
+
+        Ok(())
+    }
+
     /// inode add an inode to the flist
     pub async fn inode(&self, inode: Inode) -> Result<Ino> {
-        let result = sqlx::query(
-            r#"insert into inode (parent, name, size, uid, gid, mode, rdev, ctime, mtime)
-                                       values (?, ?, ?, ?, ?, ?, ?, ?, ?);"#,
+        let mut con = self.pool.acquire().await?;
+        insert_inode(&mut con, &inode).await
+    }
+
+    /// inodes adds all the inodes in a single transaction, the returned inos are in
+    /// the same order as the given inodes
+    pub async fn inodes(&self, inodes: Vec<Inode>) -> Result<Vec<Ino>> {
+        let mut tx = self.pool.begin().await?;
+        let mut inos = Vec::with_capacity(inodes.len());
+        for inode in inodes.iter() {
+            inos.push(insert_inode(&mut tx, inode).await?);
+        }
+        tx.commit().await?;
+
+        Ok(inos)
+    }
+
+    pub async fn block(
+        &self,
+        ino: Ino,
+        id: &[u8; ID_LEN],
+        key: &[u8; KEY_LEN],
+        size: Option<u64>,
+    ) -> Result<()> {
+        let mut con = self.pool.acquire().await?;
+        insert_block(&mut con, ino, id, key, size).await
+    }
+
+    /// blocks adds all the blocks of the inode (in order) in a single transaction
+    pub async fn blocks(&self, ino: Ino, blocks: &[Block]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for block in blocks {
+            insert_block(&mut tx, ino, &block.id, &block.key, block.size).await?;
+        }
+
+        // the blocks are all the blocks of the file, so the file hash is known
+        sqlx::query("insert or replace into file_hash (ino, hash) values (?, ?);")
+            .bind(ino as i64)
+            .bind(&file_hash(blocks)[..])
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// update_inode updates the attributes (size, uid, gid, mode and times) of an
+    /// existing inode
+    pub async fn update_inode(&self, inode: &Inode) -> Result<()> {
+        sqlx::query(
+            r#"update inode set size = ?, uid = ?, gid = ?, mode = ?, ctime = ?, mtime = ?
+                                       where ino = ?;"#,
         )
-        .bind(inode.parent as i64)
-        .bind(inode.name)
         .bind(inode.size as i64)
         .bind(inode.uid)
         .bind(inode.gid)
         .bind(inode.mode.0)
-        .bind(inode.rdev as i64)
         .bind(inode.ctime)
         .bind(inode.mtime)
+        .bind(inode.ino as i64)
         .execute(&self.pool)
         .await?;
+        Ok(())
+    }
 
-        let ino = result.last_insert_rowid() as Ino;
-        if let Some(data) = &inode.data {
-            sqlx::query("insert into extra(ino, data) values (?, ?)")
+    /// delete_inode removes the inode and all its associated data (blocks, xattrs, etc..)
+    pub async fn delete_inode(&self, ino: Ino) -> Result<()> {
+        for query in [
+            "delete from inode where ino = ?;",
+            "delete from extra where ino = ?;",
+            "delete from block where ino = ?;",
+            "delete from xattr where ino = ?;",
+            "delete from hardlink where ino = ?;",
+            "delete from file_hash where ino = ?;",
+        ] {
+            sqlx::query(query)
                 .bind(ino as i64)
-                .bind(data)
                 .execute(&self.pool)
                 .await?;
         }
+        Ok(())
+    }
 
-        Ok(ino)
+    /// delete_blocks removes all the blocks of an inode
+    pub async fn delete_blocks(&self, ino: Ino) -> Result<()> {
+        sqlx::query("delete from block where ino = ?;")
+            .bind(ino as i64)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("delete from file_hash where ino = ?;")
+            .bind(ino as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
     }
 
-    pub async fn block(&self, ino: Ino, id: &[u8; ID_LEN], key: &[u8; KEY_LEN]) -> Result<()> {
-        sqlx::query("insert into block (ino, id, key) values (?, ?, ?)")
+    /// hardlink adds the inode to the hardlink group target, the target must be the
+    /// inode of the first link and should be added to its own group as well.
+    pub async fn hardlink(&self, ino: Ino, target: Ino) -> Result<()> {
+        sqlx::query("insert or ignore into hardlink (ino, target) values (?, ?)")
+            .bind(ino as i64)
+            .bind(target as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// link_blocks copies the blocks of each hardlink group target to the other members
+    /// of the group, so every link can be read on its own. It must be called once all
+    /// the blocks of the targets are written.
+    pub async fn link_blocks(&self) -> Result<()> {
+        sqlx::query(
+            r#"insert into block (ino, id, key, size)
+                    select hardlink.ino, block.id, block.key, block.size
+                    from hardlink join block on block.ino = hardlink.target
+                    where hardlink.ino != hardlink.target
+                    order by hardlink.ino, block.rowid;"#,
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(
+            r#"insert or replace into file_hash (ino, hash)
+                    select hardlink.ino, file_hash.hash
+                    from hardlink join file_hash on file_hash.ino = hardlink.target
+                    where hardlink.ino != hardlink.target;"#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// xattr adds an extended attribute to an inode
+    pub async fn xattr<N: AsRef<str>>(&self, ino: Ino, name: N, value: Vec<u8>) -> Result<()> {
+        sqlx::query("insert into xattr (ino, name, value) values (?, ?, ?)")
             .bind(ino as i64)
-            .bind(&id[..])
-            .bind(&key[..])
+            .bind(name.as_ref())
+            .bind(value)
             .execute(&self.pool)
             .await?;
         Ok(())
@@ -436,6 +1116,19 @@ impl Writer {
             .await?;
         Ok(())
     }
+
+    /// meta sets free form metadata of the FL (for example the git commit it was built
+    /// from), unlike tags the metadata is not interpreted by rfs. Setting a key again
+    /// replaces its value
+    pub async fn meta<K: AsRef<str>, V: AsRef<str>>(&self, key: K, value: V) -> Result<()> {
+        sqlx::query("insert or replace into metadata (key, value) values (?, ?);")
+            .bind(key.as_ref())
+            .bind(value.as_ref())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn delete_tag(&self, tag: Tag<'_>) -> Result<()> {
         sqlx::query("delete from tag where key = ?;")
             .bind(tag.key())
@@ -464,10 +1157,477 @@ impl Writer {
     }
 }
 
+async fn insert_inode(con: &mut SqliteConnection, inode: &Inode) -> Result<Ino> {
+    let result = sqlx::query(
+        r#"insert into inode (parent, name, size, uid, gid, mode, rdev, ctime, mtime)
+                                   values (?, ?, ?, ?, ?, ?, ?, ?, ?);"#,
+    )
+    .bind(inode.parent as i64)
+    .bind(&inode.name)
+    .bind(inode.size as i64)
+    .bind(inode.uid)
+    .bind(inode.gid)
+    .bind(inode.mode.0)
+    .bind(inode.rdev as i64)
+    .bind(inode.ctime)
+    .bind(inode.mtime)
+    .execute(&mut *con)
+    .await?;
+
+    let ino = result.last_insert_rowid() as Ino;
+    if let Some(data) = &inode.data {
+        sqlx::query("insert into extra(ino, data) values (?, ?)")
+            .bind(ino as i64)
+            .bind(data)
+            .execute(&mut *con)
+            .await?;
+    }
+
+    Ok(ino)
+}
+
+/// file_hash is the digest of the content of a file made of the given blocks: the
+/// blake2b hash of the ordered block keys. Keys are the hash of the plain block data,
+/// so the digest doesn't depend on the compression of the blocks, but files split into
+/// blocks differently (for example with another chunking) have different digests.
+pub fn file_hash(blocks: &[Block]) -> [u8; 32] {
+    let mut state = blake2b_simd::Params::new().hash_length(32).to_state();
+    for block in blocks {
+        state.update(&block.key);
+    }
+
+    let mut hash = [0; 32];
+    hash.copy_from_slice(state.finalize().as_bytes());
+    hash
+}
+
+async fn insert_block(
+    con: &mut SqliteConnection,
+    ino: Ino,
+    id: &[u8; ID_LEN],
+    key: &[u8; KEY_LEN],
+    size: Option<u64>,
+) -> Result<()> {
+    sqlx::query("insert into block (ino, id, key, size) values (?, ?, ?, ?)")
+        .bind(ino as i64)
+        .bind(&id[..])
+        .bind(&key[..])
+        .bind(size.map(|size| size as i64))
+        .execute(con)
+        .await?;
+    Ok(())
+}
+
+/// migrate brings the tables of FLs created by older versions up to date with the
+/// schema, `create table if not exists` doesn't add the columns added since then
+async fn migrate(pool: &SqlitePool) -> Result<()> {
+    let (has_size,): (bool,) =
+        sqlx::query_as("select count(*) > 0 from pragma_table_info('block') where name = 'size'")
+            .fetch_one(pool)
+            .await?;
+    if !has_size {
+        sqlx::query("alter table block add column size integer;")
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// helpers to build small FLs in tests
+#[cfg(test)]
+pub(crate) mod fixture {
+    use super::*;
+
+    /// root adds the root directory of the FL and returns its inode
+    pub(crate) async fn root(writer: &Writer) -> Ino {
+        writer
+            .inode(Inode {
+                name: "/".into(),
+                mode: Mode::new(FileType::Dir, 0o755),
+                ..Inode::default()
+            })
+            .await
+            .unwrap()
+    }
+
+    /// child adds an entry of the given type (with the usual permissions for that
+    /// type) under parent. Other attributes are taken from inode.
+    pub(crate) async fn child(
+        writer: &Writer,
+        parent: Ino,
+        name: &str,
+        kind: FileType,
+        inode: Inode,
+    ) -> Ino {
+        let permissions = match kind {
+            FileType::Dir => 0o755,
+            FileType::Link => 0o777,
+            _ => 0o644,
+        };
+
+        writer
+            .inode(Inode {
+                parent,
+                name: name.into(),
+                mode: Mode::new(kind, permissions),
+                ..inode
+            })
+            .await
+            .unwrap()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[tokio::test]
+    async fn test_fsck() {
+        const PATH: &str = "/tmp/fsck.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+
+        let root = meta
+            .inode(Inode {
+                name: "/".into(),
+                mode: Mode::new(FileType::Dir, 0o755),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+
+        let file = meta
+            .inode(Inode {
+                parent: root,
+                name: "file".into(),
+                mode: Mode::new(FileType::Regular, 0o644),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+        meta.block(file, &[1; ID_LEN], &[1; KEY_LEN], None)
+            .await
+            .unwrap();
+        meta.route(0x00, 0xff, "dir:///tmp/store").await.unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        assert_eq!(reader.fsck().await.unwrap(), vec![]);
+
+        let orphan = meta
+            .inode(Inode {
+                parent: 100,
+                name: "orphan".into(),
+                data: Some("data".into()),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+        meta.block(200, &[2; ID_LEN], &[2; KEY_LEN], None)
+            .await
+            .unwrap();
+        sqlx::query("delete from inode where ino = ?;")
+            .bind(orphan as i64)
+            .execute(&meta.pool)
+            .await
+            .unwrap();
+        meta.inode(Inode {
+            parent: 100,
+            name: "orphan".into(),
+            ..Inode::default()
+        })
+        .await
+        .unwrap();
+        meta.delete_routes().await.unwrap();
+        meta.route(0x00, 0x0f, "dir:///tmp/store").await.unwrap();
+        meta.route(0x80, 0xff, "dir:///tmp/store").await.unwrap();
+
+        let problems = reader.fsck().await.unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                Problem::OrphanInode {
+                    ino: orphan + 1,
+                    parent: 100
+                },
+                Problem::OrphanBlock { rowid: 2, ino: 200 },
+                Problem::OrphanExtra {
+                    rowid: orphan as i64,
+                    ino: orphan
+                },
+                Problem::RouteGap {
+                    start: 0x10,
+                    end: 0x7f
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fsck_xattr() {
+        const PATH: &str = "/tmp/fsck-xattr.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+        let root = fixture::root(&meta).await;
+        meta.route(0x00, 0xff, "dir:///tmp/store").await.unwrap();
+        meta.xattr(root, "user.a", b"a".to_vec()).await.unwrap();
+        meta.xattr(100, "user.b", b"b".to_vec()).await.unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        assert_eq!(
+            reader.fsck().await.unwrap(),
+            vec![Problem::OrphanXattr { rowid: 2, ino: 100 }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fsck_hardlink() {
+        const PATH: &str = "/tmp/fsck-hardlink.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+        let root = fixture::root(&meta).await;
+        meta.route(0x00, 0xff, "dir:///tmp/store").await.unwrap();
+        let target = fixture::child(&meta, root, "a", FileType::Regular, Inode::default()).await;
+        let link = fixture::child(&meta, root, "b", FileType::Regular, Inode::default()).await;
+        meta.hardlink(target, target).await.unwrap();
+        meta.hardlink(link, target).await.unwrap();
+        // a link to a missing target and a missing link
+        meta.hardlink(root, 100).await.unwrap();
+        meta.hardlink(200, target).await.unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        assert_eq!(
+            reader.fsck().await.unwrap(),
+            vec![
+                Problem::OrphanHardlink {
+                    rowid: root as i64,
+                    ino: 100
+                },
+                Problem::OrphanHardlink {
+                    rowid: 200,
+                    ino: 200
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fsck_file_hash() {
+        const PATH: &str = "/tmp/fsck-file-hash.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+        let root = fixture::root(&meta).await;
+        meta.route(0x00, 0xff, "dir:///tmp/store").await.unwrap();
+        let file = fixture::child(&meta, root, "file", FileType::Regular, Inode::default()).await;
+        let block = Block {
+            id: [1; ID_LEN],
+            key: [1; KEY_LEN],
+            size: Some(10),
+        };
+        meta.blocks(file, &[block.clone()]).await.unwrap();
+        meta.blocks(100, &[block]).await.unwrap();
+        sqlx::query("delete from block where ino = 100;")
+            .execute(&meta.pool)
+            .await
+            .unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        assert_eq!(
+            reader.fsck().await.unwrap(),
+            vec![Problem::OrphanFileHash {
+                rowid: 100,
+                ino: 100
+            }]
+        );
+
+        // deleting an inode removes its hash
+        meta.delete_inode(file).await.unwrap();
+        sqlx::query("delete from file_hash where ino = 100;")
+            .execute(&meta.pool)
+            .await
+            .unwrap();
+        assert_eq!(reader.fsck().await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_writer_finish() {
+        const PATH: &str = "/tmp/finish.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+
+        let root = meta
+            .inode(Inode {
+                name: "/".into(),
+                mode: Mode::new(FileType::Dir, 0o755),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+
+        for i in 0..100 {
+            meta.inode(Inode {
+                parent: root,
+                name: format!("file-{}", i),
+                mode: Mode::new(FileType::Regular, 0o644),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+        }
+
+        // content is in the WAL until the writer is finished
+        assert!(Path::new("/tmp/finish.fl-wal").exists());
+        meta.finish().await.unwrap();
+        assert!(!Path::new("/tmp/finish.fl-wal").exists());
+
+        let reader = Reader::new(PATH).await.unwrap();
+        let (mode,): (String,) = sqlx::query_as("pragma journal_mode;")
+            .fetch_one(&reader.pool)
+            .await
+            .unwrap();
+        assert_eq!(mode, "delete");
+        assert_eq!(reader.children(root, 1000, 0).await.unwrap().len(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        const PATH: &str = "/tmp/remove.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+        meta.inode(Inode {
+            name: "/".into(),
+            mode: Mode::new(FileType::Dir, 0o755),
+            ..Inode::default()
+        })
+        .await
+        .unwrap();
+
+        // an unfinished FL, for example of a failed pack
+        assert!(Path::new("/tmp/remove.fl-wal").exists());
+        remove(PATH).await.unwrap();
+        for path in ["/tmp/remove.fl", "/tmp/remove.fl-wal", "/tmp/remove.fl-shm"] {
+            assert!(!Path::new(path).exists(), "{}", path);
+        }
+
+        // nothing left to remove
+        remove(PATH).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_inodes_blocks_batch() {
+        const PATH: &str = "/tmp/batch.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+
+        let root = meta
+            .inode(Inode {
+                name: "/".into(),
+                mode: Mode::new(FileType::Dir, 0o755),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+
+        let inos = meta
+            .inodes(
+                (0..10)
+                    .map(|i| Inode {
+                        parent: root,
+                        name: format!("file-{}", i),
+                        mode: Mode::new(FileType::Regular, 0o644),
+                        data: if i == 0 { Some("data".into()) } else { None },
+                        ..Inode::default()
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(inos, (2..12).collect::<Vec<Ino>>());
+
+        let blocks: Vec<Block> = (0..5u8)
+            .map(|i| Block {
+                id: [i; ID_LEN],
+                key: [i; KEY_LEN],
+                size: Some(i as u64),
+            })
+            .collect();
+        meta.blocks(inos[0], &blocks).await.unwrap();
+
+        let meta = Reader::new(PATH).await.unwrap();
+        let children = meta.children(root, 100, 0).await.unwrap();
+        assert_eq!(children.len(), 10);
+
+        let first = meta.lookup(root, "file-0").await.unwrap().unwrap();
+        assert_eq!(first.data.unwrap(), b"data");
+
+        let stored = meta.blocks(inos[0]).await.unwrap();
+        assert_eq!(stored.len(), 5);
+        for (i, block) in stored.iter().enumerate() {
+            assert_eq!(block.id, [i as u8; ID_LEN]);
+            assert_eq!(block.size, Some(i as u64));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        const PATH: &str = "/tmp/stats.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+
+        let root = meta
+            .inode(Inode {
+                name: "/".into(),
+                mode: Mode::new(FileType::Dir, 0o755),
+                size: 4096,
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+
+        for (name, size) in [("a", 10), ("b", 20)] {
+            let ino = meta
+                .inode(Inode {
+                    parent: root,
+                    name: name.into(),
+                    mode: Mode::new(FileType::Regular, 0o644),
+                    size,
+                    ..Inode::default()
+                })
+                .await
+                .unwrap();
+            meta.block(ino, &[ino as u8; 32], &[0; 32], None)
+                .await
+                .unwrap();
+        }
+
+        meta.inode(Inode {
+            parent: root,
+            name: "link".into(),
+            mode: Mode::new(FileType::Link, 0o777),
+            data: Some("a".into()),
+            ..Inode::default()
+        })
+        .await
+        .unwrap();
+
+        meta.inode(Inode {
+            parent: root,
+            name: "fifo".into(),
+            mode: Mode::new(FileType::FIFO, 0o644),
+            ..Inode::default()
+        })
+        .await
+        .unwrap();
+
+        let meta = Reader::new(PATH).await.unwrap();
+        let stats = meta.stats().await.unwrap();
+
+        assert_eq!(
+            stats,
+            FlistStats {
+                total_inodes: 5,
+                total_size: 30,
+                regular: 2,
+                dir: 1,
+                link: 1,
+                block_count: 2,
+            }
+        );
+    }
+
     #[tokio::test]
     async fn test_inode() {
         const PATH: &str = "/tmp/inode.fl";
@@ -540,8 +1700,8 @@ mod test {
         let key1: [u8; KEY_LEN] = [1; KEY_LEN];
         let key2: [u8; KEY_LEN] = [2; KEY_LEN];
 
-        meta.block(1, &hash, &key1).await.unwrap();
-        meta.block(1, &hash, &key2).await.unwrap();
+        meta.block(1, &hash, &key1, Some(10)).await.unwrap();
+        meta.block(1, &hash, &key2, None).await.unwrap();
 
         let meta = Reader::new(PATH).await.unwrap();
 
@@ -549,16 +1709,152 @@ mod test {
         assert_eq!(blocks.len(), 2);
         assert_eq!(blocks[0].id, hash);
         assert_eq!(blocks[0].key, key1);
+        assert_eq!(blocks[0].size, Some(10));
         assert_eq!(blocks[1].key, key2);
+        assert_eq!(blocks[1].size, None);
+    }
+
+    #[tokio::test]
+    async fn test_writer_old_schema() {
+        const PATH: &str = "/tmp/old-schema.fl";
+        let _ = remove(PATH).await;
+
+        // block table as created before block sizes were recorded
+        let opts = SqliteConnectOptions::new()
+            .create_if_missing(true)
+            .filename(PATH);
+        let pool = SqlitePoolOptions::new().connect_with(opts).await.unwrap();
+        sqlx::query("create table block (ino integer, id varchar(32), key varchar(32));")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("insert into block (ino, id, key) values (1, ?, ?)")
+            .bind([1u8; ID_LEN].as_slice())
+            .bind([1u8; KEY_LEN].as_slice())
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool.close().await;
+
+        let meta = Writer::new(PATH, false).await.unwrap();
+        meta.block(1, &[2; ID_LEN], &[2; KEY_LEN], Some(10))
+            .await
+            .unwrap();
+
+        let meta = Reader::new(PATH).await.unwrap();
+        let blocks = meta.blocks(1).await.unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].size, None);
+        assert_eq!(blocks[1].id, [2; ID_LEN]);
+        assert_eq!(blocks[1].size, Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_get_block_invalid_key() {
+        const PATH: &str = "/tmp/block-invalid-key.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+
+        sqlx::query("insert into block (ino, id, key) values (?, ?, ?)")
+            .bind(1i64)
+            .bind(&[1u8; ID_LEN][..])
+            .bind(&[2u8; 5][..])
+            .execute(&meta.pool)
+            .await
+            .unwrap();
+
+        let meta = Reader::new(PATH).await.unwrap();
+        assert!(meta.blocks(1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_delete_inode() {
+        const PATH: &str = "/tmp/update.fl";
+        let writer = Writer::new(PATH, true).await.unwrap();
+
+        let ino = writer
+            .inode(Inode {
+                name: "file".into(),
+                mode: Mode::new(FileType::Regular, 0o644),
+                ..Inode::default()
+            })
+            .await
+            .unwrap();
+        writer
+            .block(ino, &[1; ID_LEN], &[2; KEY_LEN], Some(5))
+            .await
+            .unwrap();
+
+        let meta = Reader::new(PATH).await.unwrap();
+        let mut inode = meta.inode(ino).await.unwrap();
+        inode.size = 5;
+        inode.mode = Mode::new(FileType::Regular, 0o600);
+        writer.update_inode(&inode).await.unwrap();
+
+        let inode = meta.inode(ino).await.unwrap();
+        assert_eq!(inode.size, 5);
+        assert_eq!(inode.mode.permissions(), 0o600);
+        assert!(inode.mode.is(FileType::Regular));
+
+        writer.delete_blocks(ino).await.unwrap();
+        assert!(meta.blocks(ino).await.unwrap().is_empty());
+
+        writer.delete_inode(ino).await.unwrap();
+        assert!(meta.inode(ino).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_xattrs() {
+        const PATH: &str = "/tmp/xattr.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+
+        meta.xattr(1, "user.name", "value".into()).await.unwrap();
+        meta.xattr(1, "security.selinux", vec![0, 1, 2])
+            .await
+            .unwrap();
+
+        let meta = Reader::new(PATH).await.unwrap();
+
+        let xattrs = meta.xattrs(1).await.unwrap();
+        assert_eq!(xattrs.len(), 2);
+        assert_eq!(xattrs[0], ("user.name".into(), "value".into()));
+        assert_eq!(xattrs[1], ("security.selinux".into(), vec![0, 1, 2]));
+
+        assert!(meta.xattrs(2).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_hardlinks() {
+        const PATH: &str = "/tmp/hardlink.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+        let key: [u8; KEY_LEN] = [1; KEY_LEN];
+
+        meta.block(1, &[1; ID_LEN], &key, None).await.unwrap();
+        meta.block(1, &[2; ID_LEN], &key, None).await.unwrap();
+        meta.hardlink(1, 1).await.unwrap();
+        meta.hardlink(2, 1).await.unwrap();
+        meta.link_blocks().await.unwrap();
+
+        let meta = Reader::new(PATH).await.unwrap();
+
+        assert_eq!(meta.hardlink(1).await.unwrap(), Some(1));
+        assert_eq!(meta.hardlink(2).await.unwrap(), Some(1));
+        assert_eq!(meta.hardlink(3).await.unwrap(), None);
+
+        let blocks = meta.blocks(2).await.unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].id, [1; ID_LEN]);
+        assert_eq!(blocks[1].id, [2; ID_LEN]);
     }
 
     #[tokio::test]
     async fn test_get_tag() {
         const PATH: &str = "/tmp/tag.fl";
         let meta = Writer::new(PATH, true).await.unwrap();
-        meta.tag(Tag::Version, "0.1").await.unwrap();
+        meta.tag(Tag::Version, "0.0").await.unwrap();
         meta.tag(Tag::Author, "azmy").await.unwrap();
         meta.tag(Tag::Custom("custom"), "value").await.unwrap();
+        // setting a tag again replaces its value
+        meta.tag(Tag::Version, "0.1").await.unwrap();
 
         let meta = Reader::new(PATH).await.unwrap();
 
@@ -566,6 +1862,7 @@ mod test {
             meta.tag(Tag::Version).await.unwrap().as_deref(),
             Some("0.1")
         ));
+        assert_eq!(meta.tags().await.unwrap().len(), 3);
 
         assert!(matches!(
             meta.tag(Tag::Custom("custom")).await.unwrap().as_deref(),
@@ -578,6 +1875,37 @@ mod test {
         ));
     }
 
+    #[tokio::test]
+    async fn test_metadata() {
+        const PATH: &str = "/tmp/metadata.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+        meta.meta("source", "https://example.com/repo")
+            .await
+            .unwrap();
+        meta.meta("build-date", "2024-01-01").await.unwrap();
+        meta.meta("build-date", "2024-01-02").await.unwrap();
+        meta.tag(Tag::Version, "0.1").await.unwrap();
+
+        let meta = Reader::new(PATH).await.unwrap();
+        assert_eq!(
+            meta.meta("build-date").await.unwrap().as_deref(),
+            Some("2024-01-02")
+        );
+        assert_eq!(meta.meta("unknown").await.unwrap(), None);
+        assert_eq!(
+            meta.metadata().await.unwrap(),
+            vec![
+                ("build-date".to_string(), "2024-01-02".to_string()),
+                ("source".to_string(), "https://example.com/repo".to_string()),
+            ]
+        );
+
+        // metadata are not tags
+        let tags = meta.all_tags().await.unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags.get("version").map(String::as_str), Some("0.1"));
+    }
+
     #[tokio::test]
     async fn test_get_routes() {
         const PATH: &str = "/tmp/route.fl";
@@ -630,6 +1958,100 @@ mod test {
         let meta = Reader::new(PATH).await.unwrap();
         //TODO: validate the walk
         meta.walk(&mut WalkTest).await.unwrap();
+
+        let etc = meta.lookup_path("/etc").await.unwrap();
+        assert_eq!(etc.unwrap().name, "etc");
+        assert!(meta.lookup_path("/etc/missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_walk_streaming() {
+        const PATH: &str = "/tmp/walk-streaming.fl";
+        let meta = Writer::new(PATH, true).await.unwrap();
+
+        let dir = |parent, name: &str| Inode {
+            parent,
+            name: name.into(),
+            mode: Mode::new(FileType::Dir, 0o755),
+            ..Inode::default()
+        };
+        let file = |parent, name: &str| Inode {
+            parent,
+            name: name.into(),
+            mode: Mode::new(FileType::Regular, 0o644),
+            ..Inode::default()
+        };
+
+        let root = meta.inode(dir(0, "/")).await.unwrap();
+        let a = meta.inode(dir(root, "a")).await.unwrap();
+        meta.inode(file(root, "f1")).await.unwrap();
+        let b = meta.inode(dir(a, "b")).await.unwrap();
+        for i in 0..5 {
+            meta.inode(file(b, &format!("{}", i))).await.unwrap();
+        }
+        meta.inode(file(a, "f2")).await.unwrap();
+        let skip = meta.inode(dir(root, "skip")).await.unwrap();
+        meta.inode(file(skip, "hidden")).await.unwrap();
+
+        struct Paths(Vec<String>);
+
+        #[async_trait::async_trait]
+        impl WalkVisitor for Paths {
+            async fn visit(&mut self, path: &Path, node: &Inode) -> Result<Walk> {
+                self.0.push(path.to_string_lossy().into_owned());
+                if node.name == "skip" || node.name == "2" {
+                    return Ok(Walk::Break);
+                }
+                Ok(Walk::Continue)
+            }
+        }
+
+        let reader = Reader::new(PATH).await.unwrap();
+        let mut paths = Paths(Vec::default());
+        // a small batch makes sure the children are fetched in several pages
+        reader.walk_streaming(2, &mut paths).await.unwrap();
+
+        assert_eq!(
+            paths.0,
+            vec!["/", "/a", "/a/b", "/a/b/0", "/a/b/1", "/a/b/2", "/a/f2", "/f1", "/skip"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_hash() {
+        const PATH: &str = "/tmp/file-hash.fl";
+        let writer = Writer::new(PATH, true).await.unwrap();
+        let block = |i: u8| Block {
+            id: [i; ID_LEN],
+            key: [i + 1; KEY_LEN],
+            size: Some(10),
+        };
+
+        let blocks = vec![block(1), block(2)];
+        writer.blocks(1, &blocks).await.unwrap();
+        writer.hardlink(1, 1).await.unwrap();
+        writer.hardlink(2, 1).await.unwrap();
+        writer.link_blocks().await.unwrap();
+        // blocks added one by one don't have a hash
+        writer
+            .block(3, &[1; ID_LEN], &[2; KEY_LEN], None)
+            .await
+            .unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        let hash = file_hash(&blocks);
+        assert_eq!(reader.file_hash(1).await.unwrap(), Some(hash));
+        assert_eq!(reader.file_hash(2).await.unwrap(), Some(hash));
+        assert_eq!(reader.file_hash(3).await.unwrap(), None);
+
+        // the hash only depends on the content (keys) and order of the blocks
+        let mut other = vec![block(1), block(2)];
+        other[0].id = [9; ID_LEN];
+        assert_eq!(file_hash(&other), hash);
+        assert_ne!(file_hash(&[block(2), block(1)]), hash);
+
+        writer.delete_blocks(1).await.unwrap();
+        assert_eq!(reader.file_hash(1).await.unwrap(), None);
     }
 
     struct WalkTest;
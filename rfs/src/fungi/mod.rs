@@ -1,3 +1,4 @@
+pub mod bundle;
 pub mod meta;
 
 pub use meta::{Error, Reader, Result, Writer};
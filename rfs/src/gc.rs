@@ -0,0 +1,123 @@
+use crate::{
+    fungi::{Reader, Result},
+    store::Store,
+};
+use std::collections::HashSet;
+
+/// gc lists all the blobs in the store and deletes the ones that are not referenced
+/// by any block of the FL. It returns the keys of the unreferenced blobs, if dry_run
+/// is set nothing is deleted.
+///
+/// Note: the store must only be used by this FL, otherwise blobs of other FLs will
+/// be deleted as well.
+pub async fn gc<S: Store>(reader: &Reader, store: &S, dry_run: bool) -> Result<Vec<Vec<u8>>> {
+    gc_all(std::slice::from_ref(reader), store, dry_run).await
+}
+
+/// gc_all is like [`gc`] for a store shared by multiple FLs, blobs are deleted only
+/// if they are not referenced by any of the FLs.
+pub async fn gc_all<S: Store>(
+    readers: &[Reader],
+    store: &S,
+    dry_run: bool,
+) -> Result<Vec<Vec<u8>>> {
+    let mut referenced = HashSet::new();
+    for reader in readers {
+        let mut offset = 0;
+        loop {
+            let blocks = reader.all_blocks(1000, offset).await?;
+            if blocks.is_empty() {
+                break;
+            }
+            offset += blocks.len() as u64;
+            referenced.extend(blocks.into_iter().map(|block| block.id.to_vec()));
+        }
+    }
+
+    let orphans: Vec<Vec<u8>> = store
+        .keys()
+        .await?
+        .into_iter()
+        .filter(|key| !referenced.contains(key))
+        .collect();
+
+    if dry_run {
+        return Ok(orphans);
+    }
+
+    for key in orphans.iter() {
+        log::debug!("deleting blob {}", hex::encode(key));
+        store.delete(key).await?;
+    }
+
+    Ok(orphans)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fungi::{meta::Inode, Writer},
+        store::mem::MemStore,
+    };
+    use tokio::fs;
+
+    #[tokio::test]
+    async fn test_gc() {
+        const ROOT: &str = "/tmp/gc-test";
+        let _ = fs::remove_dir_all(ROOT).await;
+        fs::create_dir_all(ROOT).await.unwrap();
+
+        let writer = Writer::new(format!("{}/meta.fl", ROOT), true)
+            .await
+            .unwrap();
+        let ino = writer.inode(Inode::default()).await.unwrap();
+        writer
+            .block(ino, &[1u8; 32], &[2u8; 32], None)
+            .await
+            .unwrap();
+        drop(writer);
+
+        let store = MemStore::new();
+        store.set(&[1u8; 32], b"referenced").await.unwrap();
+        store.set(&[3u8; 32], b"orphan").await.unwrap();
+
+        let reader = Reader::new(format!("{}/meta.fl", ROOT)).await.unwrap();
+
+        let orphans = gc(&reader, &store, true).await.unwrap();
+        assert_eq!(orphans, vec![vec![3u8; 32]]);
+        assert!(store.exists(&[3u8; 32]).await.unwrap());
+
+        let orphans = gc(&reader, &store, false).await.unwrap();
+        assert_eq!(orphans, vec![vec![3u8; 32]]);
+        assert!(!store.exists(&[3u8; 32]).await.unwrap());
+        assert!(store.exists(&[1u8; 32]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_gc_all() {
+        const ROOT: &str = "/tmp/gc-all-test";
+        let _ = fs::remove_dir_all(ROOT).await;
+        fs::create_dir_all(ROOT).await.unwrap();
+
+        let mut readers = Vec::new();
+        for i in 1..=2u8 {
+            let path = format!("{}/meta-{}.fl", ROOT, i);
+            let writer = Writer::new(&path, true).await.unwrap();
+            let ino = writer.inode(Inode::default()).await.unwrap();
+            writer.block(ino, &[i; 32], &[i; 32], None).await.unwrap();
+            drop(writer);
+            readers.push(Reader::new(&path).await.unwrap());
+        }
+
+        let store = MemStore::new();
+        for i in 1..=3u8 {
+            store.set(&[i; 32], b"blob").await.unwrap();
+        }
+
+        let orphans = gc_all(&readers, &store, false).await.unwrap();
+        assert_eq!(orphans, vec![vec![3u8; 32]]);
+        assert!(store.exists(&[1u8; 32]).await.unwrap());
+        assert!(store.exists(&[2u8; 32]).await.unwrap());
+    }
+}
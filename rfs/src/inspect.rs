@@ -0,0 +1,168 @@
+use crate::fungi::{Reader, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// FileTypes counts the entries of an FL per file type
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FileTypes {
+    pub regular: u64,
+    pub dir: u64,
+    pub link: u64,
+    /// devices, sockets and fifos
+    pub other: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteInfo {
+    pub start: u8,
+    pub end: u8,
+    pub url: String,
+}
+
+/// InspectVisitor holds the statistics of an FL, use [`inspect`] to fill it
+#[derive(Debug, Default, Serialize)]
+pub struct InspectVisitor {
+    /// number of inodes in the FL
+    pub inodes: u64,
+    /// total size of the regular files
+    pub size: u64,
+    pub types: FileTypes,
+    /// number of blocks of all files
+    pub blocks: u64,
+    pub tags: BTreeMap<String, String>,
+    /// free form metadata (see [`crate::fungi::Writer::meta`])
+    pub metadata: BTreeMap<String, String>,
+    pub routes: Vec<RouteInfo>,
+}
+
+impl InspectVisitor {
+    pub fn print_summary(&self) {
+        println!("inodes: {}", self.inodes);
+        println!("size: {}", self.size);
+        println!("blocks: {}", self.blocks);
+        println!(
+            "files: {}, directories: {}, links: {}, others: {}",
+            self.types.regular, self.types.dir, self.types.link, self.types.other
+        );
+
+        println!("tags:");
+        for (key, value) in self.tags.iter() {
+            println!("  {}: {}", key, value);
+        }
+
+        if !self.metadata.is_empty() {
+            println!("metadata:");
+            for (key, value) in self.metadata.iter() {
+                println!("  {}: {}", key, value);
+            }
+        }
+
+        println!("routes:");
+        for route in self.routes.iter() {
+            println!("  {:02x}-{:02x}: {}", route.start, route.end, route.url);
+        }
+    }
+
+    /// to_json serializes the inspection as a pretty printed JSON document
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// inspect collects the statistics, tags and routes of the FL
+pub async fn inspect(reader: &Reader) -> Result<InspectVisitor> {
+    let stats = reader.stats().await?;
+
+    Ok(InspectVisitor {
+        inodes: stats.total_inodes,
+        size: stats.total_size,
+        types: FileTypes {
+            regular: stats.regular,
+            dir: stats.dir,
+            link: stats.link,
+            other: stats.total_inodes - stats.regular - stats.dir - stats.link,
+        },
+        blocks: stats.block_count,
+        tags: reader.tags().await?.into_iter().collect(),
+        metadata: reader.metadata().await?.into_iter().collect(),
+        routes: reader
+            .routes()
+            .await?
+            .into_iter()
+            .map(|route| RouteInfo {
+                start: route.start,
+                end: route.end,
+                url: route.url,
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fungi::{
+        meta::{
+            fixture::{child, root},
+            FileType, Inode, Tag,
+        },
+        Writer,
+    };
+
+    #[tokio::test]
+    async fn test_inspect() {
+        const PATH: &str = "/tmp/inspect.fl";
+        let writer = Writer::new(PATH, true).await.unwrap();
+        let root = root(&writer).await;
+
+        let file = child(
+            &writer,
+            root,
+            "file",
+            FileType::Regular,
+            Inode {
+                size: 10,
+                ..Inode::default()
+            },
+        )
+        .await;
+        writer.block(file, &[1; 32], &[2; 32], None).await.unwrap();
+
+        child(
+            &writer,
+            root,
+            "link",
+            FileType::Link,
+            Inode {
+                data: Some(b"file".to_vec()),
+                ..Inode::default()
+            },
+        )
+        .await;
+
+        writer.tag(Tag::Version, "1").await.unwrap();
+        writer.meta("git-commit", "0030eb2").await.unwrap();
+        writer.route(0, 255, "dir:///tmp/store").await.unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        let inspection = inspect(&reader).await.unwrap();
+
+        assert_eq!(inspection.inodes, 3);
+        assert_eq!(inspection.size, 10);
+        assert_eq!(inspection.blocks, 1);
+        assert_eq!(inspection.types.regular, 1);
+        assert_eq!(inspection.types.dir, 1);
+        assert_eq!(inspection.types.link, 1);
+        assert_eq!(inspection.routes.len(), 1);
+        assert_eq!(
+            inspection.metadata.get("git-commit").map(String::as_str),
+            Some("0030eb2")
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&inspection.to_json().unwrap()).unwrap();
+        assert_eq!(json["inodes"], 3);
+        assert_eq!(json["types"]["regular"], 1);
+        assert_eq!(json["tags"]["version"], "1");
+        assert_eq!(json["routes"][0]["url"], "dir:///tmp/store");
+    }
+}
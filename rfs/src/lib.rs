@@ -6,14 +6,31 @@ pub mod fungi;
 pub mod store;
 
 mod pack;
-pub use pack::pack;
+pub use pack::{
+    pack, pack_dry_run, Base, ChunkingStrategy, DryRunReport, PackEvent, PackOptions, PackReport,
+    BLOB_SIZE,
+};
+mod pack_tar;
+pub use pack_tar::pack_tar;
 mod unpack;
-pub use unpack::unpack;
+pub use unpack::{cat, unpack, verify, UnpackOptions, VerifyReport};
 mod clone;
-pub use clone::clone;
+pub use clone::{clone, CloneProgress, Progress};
+mod gc;
+pub use gc::{gc, gc_all};
+mod diff;
+pub use diff::{diff, Change, DiffReport};
+mod inspect;
+pub use inspect::{inspect, InspectVisitor};
+mod tree;
+pub use tree::{ls, TreeFormat, TreeVisitor};
+mod startup;
+pub use startup::StartupConfig;
 pub mod config;
 
-const PARALLEL_UPLOAD: usize = 10; // number of files we can upload in parallel
+pub const PARALLEL_UPLOAD: usize = 10; // number of files we can upload in parallel
+pub const PARALLEL_BLOCKS: usize = 20; // number of blocks we can upload in parallel
+pub const PARALLEL_DOWNLOAD: usize = 20; // number of blocks (and files) we can download in parallel
 
 #[cfg(test)]
 mod test {
@@ -67,9 +84,37 @@ mod test {
         store.add(0x00, 0x7f, store0);
         store.add(0x80, 0xff, store1);
 
-        pack(writer, store, &source, false, None).await.unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let report = pack(
+            writer,
+            store,
+            &source,
+            PackOptions {
+                strip_password: false,
+                progress: Some(tx),
+                ..PackOptions::default()
+            },
+        )
+        .await
+        .unwrap();
 
         println!("packing complete");
+        let (mut entries, mut started, mut uploaded) = (0, 0, 0);
+        for event in rx.try_iter() {
+            match event {
+                PackEvent::Entry => entries += 1,
+                PackEvent::FileStarted { size, .. } => started += size,
+                PackEvent::BlockUploaded { size } => uploaded += size,
+            }
+        }
+        assert_eq!(entries, 4);
+        assert_eq!(started, 11 * 1024 * 1024 + 100 * 1024);
+        assert_eq!(uploaded, started);
+        // 100K + 1M + 10M in 512K blocks
+        assert_eq!(report.total_blocks, 1 + 2 + 20);
+        assert_eq!(report.unique_blocks, report.total_blocks);
+        assert_eq!(report.bytes_read, started);
+
         // recreate the stores for reading.
         let store0 = DirStore::new(root.join("store0")).await.unwrap();
         let store1 = DirStore::new(root.join("store1")).await.unwrap();
@@ -90,9 +135,18 @@ mod test {
         assert_eq!((routers[0].start, routers[0].end), (0x00, 0x7f));
         assert_eq!((routers[1].start, routers[1].end), (0x80, 0xff));
 
-        unpack(&reader, &cache, root.join("destination"), false)
-            .await
-            .unwrap();
+        unpack(
+            &reader,
+            &cache,
+            root.join("destination"),
+            UnpackOptions {
+                xattrs: true,
+                timestamps: true,
+                ..UnpackOptions::default()
+            },
+        )
+        .await
+        .unwrap();
 
         println!("unpacking complete");
         // compare that source directory is exactly the same as target directory
@@ -103,5 +157,29 @@ mod test {
             .unwrap();
 
         assert!(status.success());
+
+        // timestamps are restored on files and directories
+        for path in ["", "file-102400.rnd"] {
+            use std::os::unix::fs::MetadataExt;
+
+            let source = fs::metadata(root.join("source").join(path)).await.unwrap();
+            let target = fs::metadata(root.join("destination").join(path))
+                .await
+                .unwrap();
+            assert_eq!(source.mtime(), target.mtime());
+        }
+
+        // cat streams a single file out of the FL
+        let mut content = Vec::new();
+        cat(&reader, &cache, "/file-102400.rnd", &mut content)
+            .await
+            .unwrap();
+        let expected = fs::read(root.join("source/file-102400.rnd")).await.unwrap();
+        assert_eq!(content, expected);
+
+        assert!(cat(&reader, &cache, "/missing", &mut Vec::new())
+            .await
+            .is_err());
+        assert!(cat(&reader, &cache, "/", &mut Vec::new()).await.is_err());
     }
 }
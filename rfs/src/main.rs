@@ -3,10 +3,11 @@ extern crate log;
 use nix::sys::signal::{self, Signal};
 use nix::unistd::Pid;
 use std::error::Error;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
 
 use anyhow::{Context, Result};
-use clap::{ArgAction, Args, Parser, Subcommand};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
 
 use rfs::fungi;
 use rfs::store::{self};
@@ -33,10 +34,141 @@ enum Commands {
     Pack(PackOptions),
     /// unpack (downloads) content of an FL the provided location
     Unpack(UnpackOptions),
+    /// print the content of a file of an FL
+    Cat(CatOptions),
     /// clone copies the data from the stores of an FL to another stores
     Clone(CloneOptions),
+    /// verify that all blocks of an FL can be retrieved from its stores and are valid
+    Verify(VerifyOptions),
+    /// delete the blobs of a store that are not referenced by an FL
+    Gc(GcOptions),
     /// list or modify FL metadata and stores
     Config(ConfigOptions),
+    /// inspect FLs
+    #[command(subcommand)]
+    Flist(FlistCommands),
+    /// manage stores
+    #[command(subcommand)]
+    Store(StoreCommands),
+}
+
+#[derive(Subcommand, Debug)]
+enum StoreCommands {
+    /// check that the stores are reachable and writable
+    Check(StoreCheckOptions),
+}
+
+#[derive(Args, Debug)]
+struct StoreCheckOptions {
+    /// store url in the format [xx-xx=]<url>. the range xx-xx is optional and ignored.
+    /// the URL is per store type, please check docs for more information
+    #[clap(short, long, required = true, action=ArgAction::Append)]
+    store: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum FlistCommands {
+    /// show the paths that were added, removed or changed between two FLs
+    Diff(DiffOptions),
+    /// show a summary of the content, tags and stores of an FL
+    Inspect(FlistInspectionOptions),
+    /// print the entries of an FL
+    Tree(TreeOptions),
+    /// list the content of a directory of an FL
+    Ls(LsOptions),
+    /// check the integrity of the FL tables (dangling inodes, blocks and route gaps)
+    Fsck(FsckOptions),
+    /// print the startup config (entrypoint, cmd, env, ...) recorded in the FL
+    Startup(StartupOptions),
+    /// compress an FL into a single file bundle
+    Export(ExportOptions),
+    /// decompress an FL bundle into a plain FL
+    Import(ImportOptions),
+}
+
+#[derive(Args, Debug)]
+struct ExportOptions {
+    /// path to metadata file (flist)
+    meta: String,
+
+    /// path of the bundle to create
+    output: String,
+
+    /// zstd compression level of the bundle
+    #[clap(long, default_value_t = 19)]
+    level: i32,
+}
+
+#[derive(Args, Debug)]
+struct ImportOptions {
+    /// path to the bundle
+    bundle: String,
+
+    /// path of the metadata file (flist) to create
+    output: String,
+}
+
+#[derive(Args, Debug)]
+struct FsckOptions {
+    /// path to metadata file (flist)
+    meta: String,
+}
+
+#[derive(Args, Debug)]
+struct StartupOptions {
+    /// path to metadata file (flist)
+    meta: String,
+}
+
+#[derive(Args, Debug)]
+struct TreeOptions {
+    /// path to metadata file (flist)
+    meta: String,
+
+    /// output format, ndjson prints one JSON object per entry
+    #[clap(long, value_enum, default_value_t = Format::Ascii)]
+    format: Format,
+}
+
+#[derive(Args, Debug)]
+struct LsOptions {
+    /// path to metadata file (flist)
+    meta: String,
+
+    /// path of the directory inside the FL
+    #[clap(default_value_t = String::from("/"))]
+    path: String,
+
+    /// print the type and permissions, owner and size of each entry
+    #[clap(short, long)]
+    long: bool,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum Format {
+    /// indented tree of names
+    Ascii,
+    /// one JSON object ({path, type, size, mode, uid, gid}) per line
+    Ndjson,
+}
+
+#[derive(Args, Debug)]
+struct FlistInspectionOptions {
+    /// path to metadata file (flist)
+    meta: String,
+
+    /// print the summary as a JSON document
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Args, Debug)]
+struct DiffOptions {
+    /// path to the old metadata file (flist)
+    old: String,
+
+    /// path to the new metadata file (flist)
+    new: String,
 }
 
 #[derive(Args, Debug)]
@@ -49,6 +181,17 @@ struct MountOptions {
     #[clap(short, long, default_value_t = String::from("/tmp/cache"))]
     cache: String,
 
+    /// max size of the cache directory in bytes, the least recently used blocks are
+    /// removed from the cache once it's full. The cache size is not limited by default
+    #[clap(long)]
+    cache_size: Option<u64>,
+
+    /// max age in seconds of the cached blocks, older blocks are checked to still
+    /// exist in the store before they are used. Useful if the cache directory is
+    /// shared between FLs with stores that can change
+    #[clap(long)]
+    cache_max_age: Option<u64>,
+
     /// run in the background.
     #[clap(short, long)]
     daemon: bool,
@@ -57,6 +200,20 @@ struct MountOptions {
     #[clap(short, long)]
     log: Option<String>,
 
+    /// number of open file blocks kept around (least recently used) for reading
+    #[clap(long, default_value_t = NonZeroUsize::new(5).unwrap())]
+    lru_capacity: NonZeroUsize,
+
+    /// number of blocks to download ahead in the background when a file is read
+    /// sequentially, 0 disables prefetching
+    #[clap(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=fs::MAX_PREFETCH as i64))]
+    prefetch: u8,
+
+    /// (experimental) allow modifying the mounted FL, written files are uploaded
+    /// to the FL stores when they are closed
+    #[clap(long)]
+    writable: bool,
+
     /// target mountpoint
     target: String,
 }
@@ -64,20 +221,115 @@ struct MountOptions {
 #[derive(Args, Debug)]
 struct PackOptions {
     /// path to metadata file (flist)
-    #[clap(short, long)]
-    meta: String,
+    #[clap(short, long, required_unless_present = "dry_run")]
+    meta: Option<String>,
 
     /// store url in the format [xx-xx=]<url>. the range xx-xx is optional and used for
     /// sharding. the URL is per store type, please check docs for more information
     #[clap(short, long, action=ArgAction::Append)]
     store: Vec<String>,
 
-    /// no_strip_password disable automatic password stripping from store url, otherwise password will be stored in the fl.
+    /// no_strip_password disable automatic credentials (user and password) stripping from store url, otherwise credentials will be stored in the fl.
     #[clap(long, default_value_t = false)]
     no_strip_password: bool,
 
+    /// number of file blocks to upload in parallel
+    #[clap(long, default_value_t = rfs::PARALLEL_BLOCKS)]
+    parallel: usize,
+
+    /// strategy used to split files into blocks
+    #[clap(long, value_enum, default_value_t = Chunking::Fixed)]
+    chunking: Chunking,
+
+    /// size of the blocks files are split into (average block size with cdc chunking), defaults to 512K
+    #[clap(long)]
+    block_size: Option<usize>,
+
+    /// glob pattern of paths (relative to the target directory) to exclude from the FL,
+    /// can be repeated. Excluded directories are not traversed. A pattern like
+    /// `node_modules` only matches at the root, use `**/node_modules` for any depth
+    #[clap(short, long, action=ArgAction::Append)]
+    exclude: Vec<String>,
+
+    /// do not store the extended attributes of files in the FL
+    #[clap(long, default_value_t = false)]
+    no_xattrs: bool,
+
+    /// number of distinct stores (matching the block range) each block is written to,
+    /// 0 writes the blocks to all matching stores
+    #[clap(long, default_value_t = 0)]
+    replicas: usize,
+
+    /// compression of the blocks, one of none, snappy, zstd or zstd:<level>
+    #[clap(long, default_value_t = store::Compression::default())]
+    compression: store::Compression,
+
+    /// minimum number of stores a block must be written to for the upload to succeed,
+    /// failing stores are reported as degraded. 0 requires all stores to succeed
+    #[clap(long, default_value_t = 0)]
+    quorum: usize,
+
+    /// previous FL of the same target, files with the same path, size and mtime reuse
+    /// the blocks of the base instead of being uploaded again
+    #[clap(long)]
+    base: Option<String>,
+
+    /// also reuse the base blocks of files that only changed mtime, by comparing the
+    /// hash of the file blocks with the base blocks
+    #[clap(long, default_value_t = false, requires = "base")]
+    verify_content: bool,
+
+    /// path in the FL the target directory is packed under (for example /opt/app),
+    /// the missing directories of the path are created
+    #[clap(long)]
+    prefix: Option<String>,
+
+    /// journal mode of the metadata database while packing, the FL is switched to the
+    /// delete journal once the pack is complete
+    #[clap(long, value_enum, default_value_t = JournalMode::Wal)]
+    journal_mode: JournalMode,
+
+    /// how often the metadata database is synced to disk while packing
+    #[clap(long, value_enum, default_value_t = Synchronous::Normal)]
+    synchronous: Synchronous,
+
+    /// pack a tar archive of the root filesystem (for example the output of
+    /// `docker export`) instead of a directory, `-` reads the archive from stdin. The
+    /// archive is streamed, nothing is extracted to disk
+    #[clap(long, conflicts_with_all = ["target", "exclude", "base", "no_xattrs", "prefix"])]
+    tar: Option<String>,
+
+    /// walk and chunk the target directory without uploading anything, and report how
+    /// many blocks (and bytes) are new or already in the store. No FL is written
+    #[clap(long, default_value_t = false, conflicts_with_all = ["tar", "meta"])]
+    dry_run: bool,
+
     /// target directory to upload
-    target: String,
+    #[clap(required_unless_present = "tar")]
+    target: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum JournalMode {
+    Wal,
+    Delete,
+    Truncate,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum Chunking {
+    /// fixed size blocks
+    Fixed,
+    /// content defined chunking, gives better deduplication between similar files
+    Cdc,
 }
 
 #[derive(Args, Debug)]
@@ -95,10 +347,70 @@ struct UnpackOptions {
     #[clap(short, long, default_value_t = false)]
     preserve_ownership: bool,
 
+    /// do not restore the extended attributes of files stored in the FL
+    #[clap(long, default_value_t = false)]
+    no_xattrs: bool,
+
+    /// only unpack the file or directory at this path inside the FL
+    #[clap(long)]
+    subpath: Option<String>,
+
+    /// create symlinks that point outside of the target directory, by default
+    /// the unpack fails on such links
+    #[clap(long, default_value_t = false)]
+    allow_unsafe_links: bool,
+
+    /// restore the modification time of the files and directories from the FL,
+    /// otherwise the unpacked entries have the time of the unpack
+    #[clap(long, default_value_t = false)]
+    preserve_timestamps: bool,
+
     /// target directory for unpacking
     target: String,
 }
 
+#[derive(Args, Debug)]
+struct CatOptions {
+    /// path to metadata file (flist)
+    #[clap(short, long)]
+    meta: String,
+
+    /// directory used as cache for downloaded file chuncks
+    #[clap(short, long, default_value_t = String::from("/tmp/cache"))]
+    cache: String,
+
+    /// path of the file inside the FL
+    path: String,
+}
+
+#[derive(Args, Debug)]
+struct VerifyOptions {
+    /// path to metadata file (flist)
+    #[clap(short, long)]
+    meta: String,
+
+    /// directory used as cache for downloaded file chuncks
+    #[clap(short, long, default_value_t = String::from("/tmp/cache"))]
+    cache: String,
+}
+
+#[derive(Args, Debug)]
+struct GcOptions {
+    /// path to metadata file (flist)
+    #[clap(short, long)]
+    meta: String,
+
+    /// store url in the format [xx-xx=]<url>. the range xx-xx is optional and used for
+    /// sharding. the URL is per store type, please check docs for more information.
+    /// the store must only hold blobs of this FL, otherwise blobs of other FLs will be deleted
+    #[clap(short, long, action=ArgAction::Append)]
+    store: Vec<String>,
+
+    /// only list the unreferenced blobs without deleting them, use `--dry-run false` to delete
+    #[clap(long, default_value_t = true, action=ArgAction::Set)]
+    dry_run: bool,
+}
+
 #[derive(Args, Debug)]
 struct CloneOptions {
     /// path to metadata file (flist)
@@ -113,6 +425,15 @@ struct CloneOptions {
     /// directory used as cache for downloaded file chunks
     #[clap(short, long, default_value_t = String::from("/tmp/cache"))]
     cache: String,
+
+    /// skip the blocks that already exist in the destination store, this resumes
+    /// an interrupted clone
+    #[clap(long, default_value_t = false)]
+    resume: bool,
+
+    /// number of blocks to copy in parallel
+    #[clap(long, default_value_t = rfs::PARALLEL_BLOCKS)]
+    parallel: usize,
 }
 
 #[derive(Args, Debug)]
@@ -136,10 +457,20 @@ enum ConfigCommands {
 #[derive(Subcommand, Debug)]
 enum TagOperation {
     List,
+    /// print the value of a tag
+    Get(TagGetOptions),
+    /// set tags, the value of a tag that is already set is replaced
+    #[command(alias = "set")]
     Add(TagAddOptions),
     Delete(TagDeleteOptions),
 }
 
+#[derive(Args, Debug)]
+struct TagGetOptions {
+    /// key of the tag
+    key: String,
+}
+
 #[derive(Args, Debug)]
 struct TagAddOptions {
     /// pair of key-values separated with '='
@@ -217,8 +548,24 @@ fn main() -> Result<()> {
         Commands::Mount(opts) => mount(opts),
         Commands::Pack(opts) => pack(opts),
         Commands::Unpack(opts) => unpack(opts),
+        Commands::Cat(opts) => cat(opts),
         Commands::Clone(opts) => clone(opts),
+        Commands::Verify(opts) => verify(opts),
+        Commands::Gc(opts) => gc(opts),
         Commands::Config(opts) => config(opts),
+        Commands::Flist(opts) => match opts {
+            FlistCommands::Diff(opts) => diff(opts),
+            FlistCommands::Inspect(opts) => flist_inspect(opts),
+            FlistCommands::Tree(opts) => tree(opts),
+            FlistCommands::Ls(opts) => ls(opts),
+            FlistCommands::Fsck(opts) => fsck(opts),
+            FlistCommands::Startup(opts) => startup(opts),
+            FlistCommands::Export(opts) => flist_export(opts),
+            FlistCommands::Import(opts) => flist_import(opts),
+        },
+        Commands::Store(opts) => match opts {
+            StoreCommands::Check(opts) => store_check(opts),
+        },
     }
 }
 
@@ -226,14 +573,191 @@ fn pack(opts: PackOptions) -> Result<()> {
     let rt = tokio::runtime::Runtime::new()?;
 
     rt.block_on(async move {
-        let store = store::parse_router(opts.store.as_slice()).await?;
-        let meta = fungi::Writer::new(opts.meta, true).await?;
-        rfs::pack(meta, store, opts.target, !opts.no_strip_password, None).await?;
+        let mut store = store::parse_router(opts.store.as_slice()).await?;
+        store.set_replicas(opts.replicas);
+        store.set_quorum(opts.quorum);
+        let options = fungi::meta::WriterOptions {
+            journal_mode: match opts.journal_mode {
+                JournalMode::Wal => fungi::meta::JournalMode::Wal,
+                JournalMode::Delete => fungi::meta::JournalMode::Delete,
+                JournalMode::Truncate => fungi::meta::JournalMode::Truncate,
+            },
+            synchronous: match opts.synchronous {
+                Synchronous::Off => fungi::meta::Synchronous::Off,
+                Synchronous::Normal => fungi::meta::Synchronous::Normal,
+                Synchronous::Full => fungi::meta::Synchronous::Full,
+                Synchronous::Extra => fungi::meta::Synchronous::Extra,
+            },
+        };
+        let block_size = opts.block_size.unwrap_or(rfs::BLOB_SIZE);
+        let chunking = match opts.chunking {
+            Chunking::Fixed => rfs::ChunkingStrategy::Fixed(block_size),
+            Chunking::Cdc => rfs::ChunkingStrategy::FastCDC(block_size),
+        };
+        let excludes = opts
+            .exclude
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern)
+                    .with_context(|| format!("invalid exclude pattern '{}'", pattern))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let base = match opts.base {
+            Some(base) => Some(rfs::Base::new(
+                fungi::Reader::new(&base)
+                    .await
+                    .with_context(|| format!("failed to open base flist '{}'", base))?,
+                opts.verify_content,
+            )),
+            None => None,
+        };
+
+        if opts.dry_run {
+            let target = opts
+                .target
+                .ok_or_else(|| anyhow::anyhow!("a target directory is required"))?;
+            let (progress, bar) = pack_progress();
+            let result = rfs::pack_dry_run(
+                store,
+                target,
+                rfs::PackOptions {
+                    progress: Some(progress),
+                    concurrency: opts.parallel,
+                    chunking,
+                    excludes,
+                    compression: opts.compression,
+                    base,
+                    ..rfs::PackOptions::default()
+                },
+            )
+            .await;
+            bar.join()
+                .map_err(|_| anyhow::anyhow!("progress bar thread panicked"))?;
+            let report = result?;
+
+            println!(
+                "new: {} blocks ({} bytes)",
+                report.new_blocks, report.new_bytes
+            );
+            println!(
+                "existing: {} blocks ({} bytes)",
+                report.existing_blocks, report.existing_bytes
+            );
+            return Ok(());
+        }
+
+        let meta = match opts.meta {
+            Some(meta) => fungi::Writer::with_options(meta, true, options).await?,
+            None => anyhow::bail!("a metadata file is required"),
+        };
+        let (progress, bar) = pack_progress();
+        let result = match (opts.tar, opts.target) {
+            (Some(tar), _) => {
+                let reader: Box<dyn tokio::io::AsyncRead + Unpin + Send> = match tar.as_str() {
+                    "-" => Box::new(tokio::io::stdin()),
+                    path => Box::new(
+                        tokio::fs::File::open(path)
+                            .await
+                            .with_context(|| format!("failed to open archive '{}'", path))?,
+                    ),
+                };
+
+                rfs::pack_tar(
+                    meta.clone(),
+                    store,
+                    reader,
+                    rfs::PackOptions {
+                        strip_password: !opts.no_strip_password,
+                        progress: Some(progress),
+                        concurrency: opts.parallel,
+                        chunking,
+                        compression: opts.compression,
+                        ..rfs::PackOptions::default()
+                    },
+                )
+                .await
+            }
+            (None, Some(target)) => {
+                rfs::pack(
+                    meta.clone(),
+                    store,
+                    target,
+                    rfs::PackOptions {
+                        strip_password: !opts.no_strip_password,
+                        progress: Some(progress),
+                        concurrency: opts.parallel,
+                        chunking,
+                        excludes,
+                        xattrs: !opts.no_xattrs,
+                        compression: opts.compression,
+                        base,
+                        prefix: opts.prefix.as_ref().map(std::path::PathBuf::from),
+                    },
+                )
+                .await
+            }
+            (None, None) => anyhow::bail!("either a target directory or --tar is required"),
+        };
+
+        // the progress channel is closed once the pack is done
+        bar.join()
+            .map_err(|_| anyhow::anyhow!("progress bar thread panicked"))?;
+        let report = result?;
+
+        if opts.replicas > 0 {
+            meta.tag(fungi::meta::Tag::Replicas, opts.replicas.to_string())
+                .await?;
+        }
+
+        println!(
+            "blocks: {} total, {} unique",
+            report.total_blocks, report.unique_blocks
+        );
+        println!(
+            "bytes: {} read, {} uploaded",
+            report.bytes_read, report.bytes_uploaded
+        );
 
         Ok(())
     })
 }
 
+/// pack_progress renders the progress of a pack on the terminal until the returned
+/// channel is closed
+fn pack_progress() -> (
+    std::sync::mpsc::Sender<rfs::PackEvent>,
+    std::thread::JoinHandle<()>,
+) {
+    use indicatif::{ProgressBar, ProgressStyle};
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let bar = ProgressBar::new(0).with_style(
+        ProgressStyle::with_template(
+            "{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg}",
+        )
+        .expect("valid progress template")
+        .progress_chars("=> "),
+    );
+
+    let handle = std::thread::spawn(move || {
+        let (mut entries, mut files) = (0, 0);
+        for event in rx {
+            match event {
+                rfs::PackEvent::Entry => entries += 1,
+                rfs::PackEvent::FileStarted { size, .. } => {
+                    files += 1;
+                    bar.inc_length(size);
+                }
+                rfs::PackEvent::BlockUploaded { size } => bar.inc(size),
+            }
+            bar.set_message(format!("{} files, {} entries", files, entries));
+        }
+        bar.finish();
+    });
+
+    (tx, handle)
+}
+
 fn unpack(opts: UnpackOptions) -> Result<()> {
     let rt = tokio::runtime::Runtime::new()?;
 
@@ -245,7 +769,286 @@ fn unpack(opts: UnpackOptions) -> Result<()> {
         let router = store::get_router(&meta).await?;
 
         let cache = cache::Cache::new(opts.cache, router);
-        rfs::unpack(&meta, &cache, opts.target, opts.preserve_ownership).await?;
+        rfs::unpack(
+            &meta,
+            &cache,
+            opts.target,
+            rfs::UnpackOptions {
+                preserve: opts.preserve_ownership,
+                xattrs: !opts.no_xattrs,
+                subpath: opts.subpath.map(Into::into),
+                unsafe_links: opts.allow_unsafe_links,
+                timestamps: opts.preserve_timestamps,
+            },
+        )
+        .await?;
+        Ok(())
+    })
+}
+
+fn cat(opts: CatOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let meta = fungi::Reader::new(opts.meta)
+            .await
+            .context("failed to initialize metadata database")?;
+
+        let router = store::get_router(&meta).await?;
+
+        let cache = cache::Cache::new(opts.cache, router);
+        rfs::cat(&meta, &cache, &opts.path, &mut tokio::io::stdout()).await?;
+        Ok(())
+    })
+}
+
+fn verify(opts: VerifyOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let meta = fungi::Reader::new(opts.meta)
+            .await
+            .context("failed to initialize metadata database")?;
+
+        let router = store::get_router(&meta).await?;
+
+        let cache = cache::Cache::new(opts.cache, router);
+        let report = rfs::verify(&meta, &cache).await?;
+
+        for (path, block) in report.missing.iter() {
+            println!("missing block {} of {:?}", hex::encode(block.id), path);
+        }
+        for (path, block) in report.corrupt.iter() {
+            println!("corrupt block {} of {:?}", hex::encode(block.id), path);
+        }
+
+        if !report.is_ok() {
+            anyhow::bail!(
+                "{} missing and {} corrupt blocks out of {}",
+                report.missing.len(),
+                report.corrupt.len(),
+                report.blocks
+            );
+        }
+
+        println!("all {} blocks are valid", report.blocks);
+        Ok(())
+    })
+}
+
+fn gc(opts: GcOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let store = store::parse_router(opts.store.as_slice()).await?;
+        let meta = fungi::Reader::new(opts.meta)
+            .await
+            .context("failed to initialize metadata database")?;
+
+        let orphans = rfs::gc(&meta, &store, opts.dry_run).await?;
+        for key in orphans.iter() {
+            println!("{}", hex::encode(key));
+        }
+
+        if opts.dry_run {
+            println!("{} unreferenced blobs found", orphans.len());
+        } else {
+            println!("{} unreferenced blobs deleted", orphans.len());
+        }
+
+        Ok(())
+    })
+}
+
+fn diff(opts: DiffOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let old = fungi::Reader::new(&opts.old)
+            .await
+            .context("failed to initialize old metadata database")?;
+        let new = fungi::Reader::new(&opts.new)
+            .await
+            .context("failed to initialize new metadata database")?;
+
+        let report = rfs::diff(&old, &new).await?;
+        for path in report.added.iter() {
+            println!("A  {}", path.display());
+        }
+        for path in report.removed.iter() {
+            println!("D  {}", path.display());
+        }
+        for change in report.changed.iter() {
+            if change.blocks_added > 0 || change.blocks_removed > 0 {
+                println!(
+                    "M  {} (+{} -{} blocks)",
+                    change.path.display(),
+                    change.blocks_added,
+                    change.blocks_removed
+                );
+            } else {
+                println!("M  {}", change.path.display());
+            }
+        }
+
+        println!(
+            "{} added, {} removed, {} changed",
+            report.added.len(),
+            report.removed.len(),
+            report.changed.len()
+        );
+
+        Ok(())
+    })
+}
+
+fn flist_inspect(opts: FlistInspectionOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let meta = fungi::Reader::new(&opts.meta)
+            .await
+            .context("failed to initialize metadata database")?;
+
+        let inspection = rfs::inspect(&meta).await?;
+        if opts.json {
+            println!("{}", inspection.to_json()?);
+        } else {
+            inspection.print_summary();
+        }
+
+        Ok(())
+    })
+}
+
+fn flist_export(opts: ExportOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        fungi::bundle::export(&opts.meta, &opts.output, opts.level)
+            .await
+            .with_context(|| format!("failed to export '{}'", opts.meta))?;
+
+        Ok(())
+    })
+}
+
+fn flist_import(opts: ImportOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        fungi::bundle::import(&opts.bundle, &opts.output)
+            .await
+            .with_context(|| format!("failed to import '{}'", opts.bundle))?;
+
+        Ok(())
+    })
+}
+
+fn tree(opts: TreeOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let meta = fungi::Reader::new(&opts.meta)
+            .await
+            .context("failed to initialize metadata database")?;
+
+        let format = match opts.format {
+            Format::Ascii => rfs::TreeFormat::Ascii,
+            Format::Ndjson => rfs::TreeFormat::Ndjson,
+        };
+        let stdout = std::io::BufWriter::new(std::io::stdout());
+        let mut visitor = rfs::TreeVisitor::new(stdout, format);
+        meta.walk(&mut visitor).await?;
+        visitor.into_inner().flush()?;
+
+        Ok(())
+    })
+}
+
+fn ls(opts: LsOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let meta = fungi::Reader::new(&opts.meta)
+            .await
+            .context("failed to initialize metadata database")?;
+
+        let mut stdout = std::io::BufWriter::new(std::io::stdout());
+        rfs::ls(&meta, &opts.path, opts.long, &mut stdout).await?;
+        stdout.flush()?;
+
+        Ok(())
+    })
+}
+
+fn fsck(opts: FsckOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let meta = fungi::Reader::new(&opts.meta)
+            .await
+            .context("failed to initialize metadata database")?;
+
+        let problems = meta.fsck().await?;
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
+
+        if !problems.is_empty() {
+            anyhow::bail!("found {} problem(s) in '{}'", problems.len(), opts.meta);
+        }
+
+        Ok(())
+    })
+}
+
+fn startup(opts: StartupOptions) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let meta = fungi::Reader::new(&opts.meta)
+            .await
+            .context("failed to initialize metadata database")?;
+
+        let config = match rfs::StartupConfig::load(&meta).await? {
+            Some(config) => config,
+            None => anyhow::bail!("'{}' has no startup config", opts.meta),
+        };
+
+        println!("{}", serde_json::to_string_pretty(&config)?);
+
+        Ok(())
+    })
+}
+
+fn store_check(opts: StoreCheckOptions) -> Result<()> {
+    use rfs::store::Store;
+
+    let rt = tokio::runtime::Runtime::new()?;
+
+    rt.block_on(async move {
+        let mut failed = 0;
+        for spec in opts.store.iter() {
+            let started = std::time::Instant::now();
+            let result = match store::parse_router(std::slice::from_ref(spec)).await {
+                Ok(store) => store.health().await.map_err(anyhow::Error::from),
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(_) => println!("ok\t{:?}\t{}", started.elapsed(), spec),
+                Err(err) => {
+                    failed += 1;
+                    println!("failed\t{:?}\t{}: {:#}", started.elapsed(), spec, err);
+                }
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{} of {} stores failed", failed, opts.store.len());
+        }
+
         Ok(())
     })
 }
@@ -320,18 +1123,41 @@ fn wait_child(target: String, mut pid_file: tempfile::NamedTempFile) {
 }
 
 async fn fuse(opts: MountOptions) -> Result<()> {
-    let meta = fungi::Reader::new(opts.meta)
+    let meta = fungi::Reader::new(&opts.meta)
         .await
         .context("failed to initialize metadata database")?;
 
     let router = store::get_router(&meta).await?;
 
-    let cache = cache::Cache::new(opts.cache, router);
-    let filesystem = fs::Filesystem::new(meta, cache);
+    let mut cache = cache::Cache::new(opts.cache, router);
+    if let Some(size) = opts.cache_size {
+        cache = cache.with_max_size(size);
+    }
+    if let Some(age) = opts.cache_max_age {
+        cache = cache.with_max_age(std::time::Duration::from_secs(age));
+    }
+    let mut filesystem = fs::Filesystem::new(meta, cache, opts.lru_capacity)
+        .await?
+        .with_prefetch(opts.prefetch as usize);
+    if opts.writable {
+        let writer = fungi::Writer::with_options(&opts.meta, false, in_place())
+            .await
+            .context("failed to initialize metadata database")?;
+        filesystem = filesystem.with_writer(writer);
+    }
 
     filesystem.mount(opts.target).await
 }
 
+/// in_place are the writer options used to modify an existing FL, the delete
+/// journal keeps the FL a single file while it's open by readers
+fn in_place() -> fungi::meta::WriterOptions {
+    fungi::meta::WriterOptions {
+        journal_mode: fungi::meta::JournalMode::Delete,
+        ..Default::default()
+    }
+}
+
 fn clone(opts: CloneOptions) -> Result<()> {
     let rt = tokio::runtime::Runtime::new()?;
 
@@ -344,7 +1170,22 @@ fn clone(opts: CloneOptions) -> Result<()> {
         let router = store::get_router(&meta).await?;
 
         let cache = cache::Cache::new(opts.cache, router);
-        rfs::clone(meta, store, cache).await?;
+        let progress: rfs::Progress = std::sync::Arc::new(|progress| {
+            eprint!(
+                "\rcloned {}/{} blocks ({} skipped, {} bytes)",
+                progress.done, progress.total, progress.skipped, progress.bytes
+            );
+        });
+        rfs::clone(
+            meta,
+            store,
+            cache,
+            opts.resume,
+            opts.parallel,
+            Some(progress),
+        )
+        .await?;
+        eprintln!();
 
         Ok(())
     })
@@ -353,7 +1194,7 @@ fn config(opts: ConfigOptions) -> Result<()> {
     let rt = tokio::runtime::Runtime::new()?;
 
     rt.block_on(async move {
-        let writer = fungi::Writer::new(opts.meta.clone(), false)
+        let writer = fungi::Writer::with_options(opts.meta.clone(), false, in_place())
             .await
             .context("failed to initialize metadata database")?;
 
@@ -364,6 +1205,7 @@ fn config(opts: ConfigOptions) -> Result<()> {
         match opts.command {
             ConfigCommands::Tag(opts) => match opts {
                 TagOperation::List => config::tag_list(reader).await?,
+                TagOperation::Get(opts) => config::tag_get(reader, opts.key).await?,
                 TagOperation::Add(opts) => config::tag_add(writer, opts.tag).await?,
                 TagOperation::Delete(opts) => {
                     config::tag_delete(writer, opts.key, opts.all).await?
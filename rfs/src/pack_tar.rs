@@ -0,0 +1,515 @@
+use crate::fungi::meta::{FileType, Ino, Inode, Mode};
+use crate::fungi::{Error, Result, Writer};
+use crate::pack::{finish, prepare, FailuresList, PackEvent, PackOptions, PackReport, Uploader};
+use crate::store::Store;
+use anyhow::Context;
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use tokio::task::JoinSet;
+use tokio_tar::EntryType;
+
+// mode of the directories that are not in the archive but are parents of its entries
+const IMPLICIT_DIR_MODE: u32 = 0o755;
+
+/// creates an FL from a tar archive of a root filesystem (for example the output of
+/// `docker export`). The archive is read as a stream, entries are added to the FL and
+/// file blocks are uploaded as they are read, so nothing is extracted to disk.
+///
+/// Hard links, symlinks, devices and fifos are supported, as well as GNU and PAX long
+/// names. Directories missing from the archive are created with default permissions.
+///
+/// See [`crate::pack`] for the options, excludes, extended attributes, base FLs and
+/// prefixes are not supported with archives and are ignored.
+pub async fn pack_tar<R: AsyncRead + Unpin + Send, S: Store>(
+    writer: Writer,
+    store: S,
+    reader: R,
+    options: PackOptions,
+) -> Result<PackReport> {
+    let PackOptions {
+        strip_password,
+        progress,
+        concurrency,
+        chunking,
+        compression,
+        ..
+    } = options;
+    let store = prepare(&writer, store, strip_password, chunking, compression).await?;
+
+    let failures = FailuresList::default();
+    let uploader = Uploader::new(
+        store,
+        writer.clone(),
+        Arc::clone(&failures),
+        concurrency,
+        chunking,
+        progress.clone(),
+    );
+
+    let mut tree = Tree {
+        writer: &writer,
+        dirs: HashMap::default(),
+        files: HashMap::default(),
+        groups: HashSet::default(),
+    };
+    let mut completions = JoinSet::new();
+
+    // an invalid entry stops the pack, the uploads of the files read so far are
+    // aborted and the FL is left unfinished
+    let read: Result<()> = async {
+        let mut archive = tokio_tar::Archive::new(reader);
+        let mut entries = archive.entries().context("failed to read archive")?;
+        while let Some(entry) = entries.next().await {
+            // stop reading the archive as soon as one of the uploads has failed
+            if !failures.lock().await.is_empty() {
+                break;
+            }
+
+            let mut entry = entry.context("failed to read archive entry")?;
+            let path = normalize(&entry.path().context("invalid entry path")?)?;
+
+            if let Some(ref progress) = progress {
+                let _ = progress.send(PackEvent::Entry);
+            }
+
+            let header = entry.header();
+            let typ = header.entry_type();
+            let mut inode = Inode {
+                size: 0,
+                uid: header.uid().context("invalid entry uid")? as u32,
+                gid: header.gid().context("invalid entry gid")? as u32,
+                mtime: header.mtime().context("invalid entry mtime")? as i64,
+                ..Inode::default()
+            };
+            inode.ctime = inode.mtime;
+            let perm = header.mode().context("invalid entry mode")?;
+
+            let file_type = match typ {
+                EntryType::Directory => FileType::Dir,
+                EntryType::Regular | EntryType::Continuous | EntryType::Link => FileType::Regular,
+                EntryType::Symlink => FileType::Link,
+                EntryType::Char => FileType::Char,
+                EntryType::Block => FileType::Block,
+                EntryType::Fifo => FileType::FIFO,
+                _ => {
+                    log::warn!(
+                        "skipping unsupported entry '{}' of type {:?}",
+                        path.display(),
+                        typ
+                    );
+                    continue;
+                }
+            };
+            inode.mode = Mode::new(file_type.clone(), perm);
+
+            if file_type == FileType::Dir && tree.dirs.contains_key(&path) {
+                log::warn!(
+                    "directory '{}' is already added, keeping its first attributes",
+                    path.display()
+                );
+                continue;
+            }
+            if tree.files.contains_key(&path) {
+                log::warn!("skipping duplicate entry '{}'", path.display());
+                continue;
+            }
+
+            match typ {
+                EntryType::Symlink => {
+                    let target = entry
+                        .link_name()
+                        .context("invalid symlink target")?
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("symlink '{}' has no target", path.display())
+                        })?;
+                    inode.data = Some(target.as_os_str().as_bytes().into());
+                }
+                EntryType::Char | EntryType::Block => {
+                    let major = header.device_major().context("invalid device major")?;
+                    let minor = header.device_minor().context("invalid device minor")?;
+                    inode.rdev = libc::makedev(major.unwrap_or(0), minor.unwrap_or(0));
+                }
+                EntryType::Regular | EntryType::Continuous => {
+                    inode.size = header.size().context("invalid entry size")?;
+                }
+                _ => {}
+            }
+
+            // the root directory of the archive (`./`) gives the attributes of the FL root
+            if path.as_os_str().is_empty() {
+                if file_type == FileType::Dir {
+                    tree.root(inode).await?;
+                }
+                continue;
+            }
+
+            match typ {
+                EntryType::Directory => {
+                    let ino = tree.add(&path, inode).await?;
+                    tree.dirs.insert(path, ino);
+                }
+                EntryType::Link => {
+                    // tar hard links always refer to an entry that was already read
+                    let target = entry
+                        .link_name()
+                        .context("invalid hard link target")?
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("hard link '{}' has no target", path.display())
+                        })?;
+                    let target = normalize(&target)?;
+                    let (target_ino, size) = match tree.files.get(&target) {
+                        Some(file) => *file,
+                        None => {
+                            return Err(Error::Anyhow(anyhow::anyhow!(
+                                "hard link '{}' target '{}' not found",
+                                path.display(),
+                                target.display()
+                            )))
+                        }
+                    };
+
+                    inode.size = size;
+                    let ino = tree.add(&path, inode).await?;
+                    if tree.groups.insert(target_ino) {
+                        writer.hardlink(target_ino, target_ino).await?;
+                    }
+                    writer.hardlink(ino, target_ino).await?;
+                    tree.files.insert(path, (target_ino, size));
+                }
+                EntryType::Regular | EntryType::Continuous => {
+                    let size = inode.size;
+                    let ino = tree.add(&path, inode).await?;
+                    tree.files.insert(path.clone(), (ino, size));
+
+                    // the entry must be read completely before the next one, but the
+                    // uploads of its blocks can complete in the background
+                    uploader.started(&path, size);
+                    let uploads = match uploader.chunk(&mut entry).await {
+                        Ok(uploads) => uploads,
+                        Err(err) => {
+                            failures.lock().await.push((path, err));
+                            break;
+                        }
+                    };
+
+                    let uploader = uploader.clone();
+                    let failures = Arc::clone(&failures);
+                    completions.spawn(async move {
+                        if let Err(err) = uploader.complete(ino, uploads).await {
+                            log::error!("failed to upload file {}: {:#}", path.display(), err);
+                            failures.lock().await.push((path, err));
+                        }
+                    });
+                }
+                _ => {
+                    tree.add(&path, inode).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+    if let Err(err) = read {
+        completions.shutdown().await;
+        return Err(err);
+    }
+
+    while let Some(completion) = completions.join_next().await {
+        completion.context("upload task failed")?;
+    }
+
+    finish(&writer, &failures).await?;
+
+    Ok(uploader.report().await)
+}
+
+/// Tree keeps track of the inodes of the FL while the archive is read
+struct Tree<'a> {
+    writer: &'a Writer,
+    // inodes of the directories by path
+    dirs: HashMap<PathBuf, Ino>,
+    // inode and size of the regular files by path, used to resolve hard links
+    files: HashMap<PathBuf, (Ino, u64)>,
+    // hardlink targets that were already added to their own group
+    groups: HashSet<Ino>,
+}
+
+impl<'a> Tree<'a> {
+    /// root adds the root directory with the given attributes
+    async fn root(&mut self, inode: Inode) -> Result<Ino> {
+        if let Some(ino) = self.dirs.get(Path::new("")) {
+            return Ok(*ino);
+        }
+
+        let ino = self
+            .writer
+            .inode(Inode {
+                name: "/".into(),
+                parent: 0,
+                ..inode
+            })
+            .await?;
+        self.dirs.insert(PathBuf::new(), ino);
+
+        Ok(ino)
+    }
+
+    /// parent returns the inode of the directory, the directory and its parents are
+    /// added if they were not in the archive (yet)
+    async fn parent(&mut self, path: &Path) -> Result<Ino> {
+        if let Some(ino) = self.dirs.get(path) {
+            return Ok(*ino);
+        }
+
+        let implicit = Inode {
+            mode: Mode::new(FileType::Dir, IMPLICIT_DIR_MODE),
+            ..Inode::default()
+        };
+
+        // the missing directories are added from the closest existing ancestor
+        let mut missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|dir| !self.dirs.contains_key(*dir))
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect();
+
+        let mut parent = self.root(implicit.clone()).await?;
+        if let Some(ancestor) = missing.last().and_then(|dir| dir.parent()) {
+            parent = self.dirs.get(ancestor).copied().unwrap_or(parent);
+        }
+
+        while let Some(dir) = missing.pop() {
+            parent = self
+                .writer
+                .inode(Inode {
+                    parent,
+                    name: file_name(dir),
+                    ..implicit.clone()
+                })
+                .await?;
+            self.dirs.insert(dir.into(), parent);
+        }
+
+        Ok(parent)
+    }
+
+    /// add adds the inode at the given path
+    async fn add(&mut self, path: &Path, inode: Inode) -> Result<Ino> {
+        let parent = self.parent(path.parent().unwrap_or(Path::new(""))).await?;
+
+        self.writer
+            .inode(Inode {
+                parent,
+                name: file_name(path),
+                ..inode
+            })
+            .await
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| String::from_utf8_lossy(name.as_bytes()).into_owned())
+        .unwrap_or_default()
+}
+
+/// normalize returns the path relative to the archive root, entries can't refer
+/// to paths outside of the archive
+fn normalize(path: &Path) -> Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(Error::Anyhow(anyhow::anyhow!(
+                    "invalid entry path '{}'",
+                    path.display()
+                )))
+            }
+        }
+    }
+
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fungi::Reader;
+    use crate::pack::ChunkingStrategy;
+    use crate::store::{mem::MemStore, BlockStore};
+
+    fn header(path: &str, typ: EntryType, mode: u32, size: u64) -> tokio_tar::Header {
+        let mut header = tokio_tar::Header::new_gnu();
+        header.set_path(path).unwrap();
+        header.set_entry_type(typ);
+        header.set_mode(mode);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(size);
+        header.set_mtime(1000);
+        header.set_cksum();
+        header
+    }
+
+    async fn archive() -> Vec<u8> {
+        let mut builder = tokio_tar::Builder::new(Vec::new());
+
+        let data = vec![7u8; 3000];
+        builder
+            .append(&header("./", EntryType::Directory, 0o700, 0), &[][..])
+            .await
+            .unwrap();
+        // the parent directory is not in the archive
+        builder
+            .append(
+                &header("usr/bin/tool", EntryType::Regular, 0o755, data.len() as u64),
+                data.as_slice(),
+            )
+            .await
+            .unwrap();
+
+        let mut link = header("usr/bin/alias", EntryType::Link, 0o755, 0);
+        link.set_link_name("usr/bin/tool").unwrap();
+        link.set_cksum();
+        builder.append(&link, &[][..]).await.unwrap();
+
+        let mut symlink = header("usr/bin/sym", EntryType::Symlink, 0o777, 0);
+        symlink.set_link_name("tool").unwrap();
+        symlink.set_cksum();
+        builder.append(&symlink, &[][..]).await.unwrap();
+
+        // long names are written with GNU long name entries
+        let long = format!("{}/file", "d".repeat(150));
+        let mut long_header = header("placeholder", EntryType::Regular, 0o644, 5);
+        builder
+            .append_data(&mut long_header, &long, &b"hello"[..])
+            .await
+            .unwrap();
+
+        builder.into_inner().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pack_tar() {
+        const PATH: &str = "/tmp/pack-tar-test.fl";
+        let writer = Writer::new(PATH, true).await.unwrap();
+        let store = MemStore::new();
+        let data = archive().await;
+
+        pack_tar(
+            writer,
+            store.clone(),
+            data.as_slice(),
+            PackOptions {
+                concurrency: 4,
+                chunking: ChunkingStrategy::Fixed(1024),
+                ..PackOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        let root = reader.inode(1).await.unwrap();
+        assert_eq!(root.mode.mode(), Mode::new(FileType::Dir, 0o700).mode());
+
+        let usr = reader
+            .lookup_path(Path::new("/usr"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            usr.mode.mode(),
+            Mode::new(FileType::Dir, IMPLICIT_DIR_MODE).mode()
+        );
+
+        let tool = reader
+            .lookup_path(Path::new("/usr/bin/tool"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tool.size, 3000);
+        assert_eq!(tool.mode.mode(), Mode::new(FileType::Regular, 0o755).mode());
+        assert_eq!(tool.mtime, 1000);
+
+        let blocks = BlockStore::from(store);
+        let mut content = Vec::new();
+        for block in reader.blocks(tool.ino).await.unwrap() {
+            content.extend(blocks.get(&block).await.unwrap());
+        }
+        assert_eq!(content, vec![7u8; 3000]);
+
+        // hard links get the blocks of their target
+        let alias = reader
+            .lookup_path(Path::new("/usr/bin/alias"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(alias.size, 3000);
+        assert_eq!(
+            reader.blocks(alias.ino).await.unwrap(),
+            reader.blocks(tool.ino).await.unwrap()
+        );
+
+        let sym = reader
+            .lookup_path(Path::new("/usr/bin/sym"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(sym.mode.is(FileType::Link));
+        assert_eq!(sym.data.as_deref(), Some(&b"tool"[..]));
+
+        let long = Path::new("/").join("d".repeat(150)).join("file");
+        let long = reader.lookup_path(&long).await.unwrap().unwrap();
+        assert_eq!(long.size, 5);
+    }
+
+    #[tokio::test]
+    async fn test_pack_tar_invalid_entry() {
+        const PATH: &str = "/tmp/pack-tar-invalid-test.fl";
+        let writer = Writer::new(PATH, true).await.unwrap();
+
+        let mut builder = tokio_tar::Builder::new(Vec::new());
+        let data = vec![7u8; 3000];
+        builder
+            .append(
+                &header("file", EntryType::Regular, 0o644, data.len() as u64),
+                data.as_slice(),
+            )
+            .await
+            .unwrap();
+        let mut link = header("link", EntryType::Link, 0o644, 0);
+        link.set_link_name("missing").unwrap();
+        link.set_cksum();
+        builder.append(&link, &[][..]).await.unwrap();
+        let data = builder.into_inner().await.unwrap();
+
+        let err = pack_tar(
+            writer,
+            MemStore::new(),
+            data.as_slice(),
+            PackOptions {
+                chunking: ChunkingStrategy::Fixed(1024),
+                ..PackOptions::default()
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("target 'missing' not found"));
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Path::new("./a/b")).unwrap(), Path::new("a/b"));
+        assert_eq!(normalize(Path::new("/a")).unwrap(), Path::new("a"));
+        assert_eq!(normalize(Path::new("./")).unwrap(), Path::new(""));
+        assert!(normalize(Path::new("a/../../b")).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::fungi::{meta::Tag, Reader, Result, Writer};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// StartupConfig describes how the root filesystem of an FL is started (for example
+/// the entrypoint and environment of a docker image). It's recorded in the FL
+/// `startup` tag so runtimes don't need any other source to run the FL.
+///
+/// Fields are serialized in a fixed order and env is sorted, so the same config
+/// always gives the same tag value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StartupConfig {
+    #[serde(default)]
+    pub entrypoint: Vec<String>,
+    #[serde(default)]
+    pub cmd: Vec<String>,
+    #[serde(default = "default_workdir")]
+    pub workdir: String,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+fn default_workdir() -> String {
+    "/".into()
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            entrypoint: Vec::default(),
+            cmd: Vec::default(),
+            workdir: default_workdir(),
+            env: BTreeMap::default(),
+            user: None,
+        }
+    }
+}
+
+impl StartupConfig {
+    /// argv is the full command line, the cmd is appended to the entrypoint the same
+    /// way docker does
+    pub fn argv(&self) -> Vec<&str> {
+        self.entrypoint
+            .iter()
+            .chain(self.cmd.iter())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// load reads the startup config of the FL, if any
+    pub async fn load(reader: &Reader) -> Result<Option<Self>> {
+        let value = match reader.tag(Tag::Startup).await? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let config = serde_json::from_str(&value).context("invalid startup config")?;
+        Ok(Some(config))
+    }
+
+    /// store records the startup config in the FL
+    pub async fn store(&self, writer: &Writer) -> Result<()> {
+        let value = serde_json::to_string(self).context("failed to encode startup config")?;
+        writer.tag(Tag::Startup, value).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_startup_config() {
+        const PATH: &str = "/tmp/startup-test.fl";
+        let writer = Writer::new(PATH, true).await.unwrap();
+
+        let config = StartupConfig {
+            entrypoint: vec!["/entrypoint.sh".into(), "-v".into()],
+            cmd: vec!["redis-server".into()],
+            workdir: "/data".into(),
+            env: [("B", "2"), ("A", "1")]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            user: Some("redis".into()),
+        };
+        assert_eq!(config.argv(), vec!["/entrypoint.sh", "-v", "redis-server"]);
+
+        config.store(&writer).await.unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        assert_eq!(
+            reader.tag(Tag::Startup).await.unwrap().unwrap(),
+            r#"{"entrypoint":["/entrypoint.sh","-v"],"cmd":["redis-server"],"workdir":"/data","env":{"A":"1","B":"2"},"user":"redis"}"#
+        );
+        assert_eq!(StartupConfig::load(&reader).await.unwrap(), Some(config));
+    }
+
+    #[tokio::test]
+    async fn test_startup_config_missing() {
+        const PATH: &str = "/tmp/startup-missing-test.fl";
+        Writer::new(PATH, true).await.unwrap();
+
+        let reader = Reader::new(PATH).await.unwrap();
+        assert_eq!(StartupConfig::load(&reader).await.unwrap(), None);
+    }
+}
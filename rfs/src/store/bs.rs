@@ -1,4 +1,4 @@
-use super::{Error, Result, Store};
+use super::{Compression, Error, Result, Store};
 use crate::fungi::meta::Block;
 use aes_gcm::{
     aead::{
@@ -8,15 +8,22 @@ use aes_gcm::{
     Aes256Gcm, Nonce,
 };
 
-fn hash(input: &[u8]) -> GenericArray<u8, generic_array::typenum::U32> {
+pub(crate) fn hash(input: &[u8]) -> GenericArray<u8, generic_array::typenum::U32> {
     let hash = blake2b_simd::Params::new().hash_length(32).hash(input);
     GenericArray::from_slice(hash.as_bytes()).to_owned()
 }
 
 /// The block store builds on top of a store and adds encryption and compression
+///
+/// Blocks are encrypted with AES-256-GCM (authenticated encryption) using the hash of the
+/// plain data as key. The stored blob is the ciphertext followed by the 16 bytes GCM tag,
+/// the nonce is derived from the key so the same data always gives the same blob and can
+/// be deduplicated by the stores. A tampered blob fails the tag check and is reported
+/// as an InvalidBlob instead of being decrypted to garbage.
 #[derive(Clone, Debug)]
 pub struct BlockStore<S: Store> {
     store: S,
+    compression: Compression,
 }
 
 impl<S> From<S> for BlockStore<S>
@@ -24,7 +31,10 @@ where
     S: Store,
 {
     fn from(store: S) -> Self {
-        Self { store }
+        Self {
+            store,
+            compression: Compression::default(),
+        }
     }
 }
 
@@ -36,29 +46,64 @@ where
         self.store
     }
 
+    /// with_compression sets the compression of the blocks written to the store, blocks
+    /// are always read with the compression they were written with
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
     pub async fn get(&self, block: &Block) -> Result<Vec<u8>> {
         let encrypted = self.store.get(&block.id).await?;
 
         let cipher = Aes256Gcm::new_from_slice(&block.key).map_err(|_| Error::InvalidKey)?;
         let nonce = Nonce::from_slice(&block.key[..12]);
 
+        // decryption fails if the authentication tag doesn't match
         let compressed = cipher
             .decrypt(nonce, encrypted.as_slice())
-            .map_err(|_| Error::EncryptionError)?;
+            .map_err(|_| Error::InvalidBlob)?;
+
+        Compression::decompress(&compressed)
+    }
 
-        let mut decoder = snap::raw::Decoder::new();
-        let plain = decoder.decompress_vec(&compressed)?;
+    /// raw returns the block blob as it is stored (compressed and encrypted)
+    pub async fn raw(&self, block: &Block) -> Result<Vec<u8>> {
+        self.store.get(&block.id).await
+    }
+
+    /// exists checks if the block is in the store without downloading it
+    pub async fn exists(&self, block: &Block) -> Result<bool> {
+        self.store.exists(&block.id).await
+    }
+
+    /// verify downloads the block from the store (bypassing any cache) and makes
+    /// sure its content matches the block key. Returns InvalidBlob if the
+    /// content doesn't match.
+    pub async fn verify(&self, block: &Block) -> Result<()> {
+        let plain = self.get(block).await?;
+        if hash(&plain).as_slice() != block.key {
+            return Err(Error::InvalidBlob);
+        }
 
-        Ok(plain)
+        Ok(())
     }
 
     pub async fn set(&self, blob: &[u8]) -> Result<Block> {
+        let (block, encrypted) = self.encode(blob)?;
+        self.put(&block, &encrypted).await?;
+
+        Ok(block)
+    }
+
+    /// encode compresses and encrypts the blob. It returns the block (id and key) and the
+    /// encrypted data that need to be written to the store with [`BlockStore::put`]
+    pub fn encode(&self, blob: &[u8]) -> Result<(Block, Vec<u8>)> {
         // we first calculate the hash of the plain-text data
 
         let key = hash(blob);
-        let mut encoder = snap::raw::Encoder::new();
         // data is then compressed
-        let compressed = encoder.compress_vec(blob)?;
+        let compressed = self.compression.compress(blob)?;
 
         // we then encrypt it using the hash of the plain-text as a key
         let cipher = Aes256Gcm::new(&key);
@@ -77,50 +122,26 @@ where
         let block = Block {
             id: id.into(),
             key: key.into(),
+            size: Some(blob.len() as u64),
         };
 
-        self.store.set(&block.id, &encrypted).await?;
+        Ok((block, encrypted))
+    }
 
-        Ok(block)
+    /// put writes an encoded block to the store
+    pub async fn put(&self, block: &Block, encrypted: &[u8]) -> Result<()> {
+        self.store.set(&block.id, encrypted).await
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::super::Route;
-
+    use super::super::mem::MemStore;
     use super::*;
-    use std::collections::HashMap;
-    use std::sync::Arc;
-    use tokio::sync::Mutex;
-
-    #[derive(Default)]
-    struct InMemoryStore {
-        map: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
-    }
-
-    #[async_trait::async_trait]
-    impl Store for InMemoryStore {
-        async fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
-            let map = self.map.lock().await;
-            let v = map.get(key).ok_or(Error::KeyNotFound)?;
-            Ok(v.clone())
-        }
-        async fn set(&self, key: &[u8], blob: &[u8]) -> Result<()> {
-            let mut map = self.map.lock().await;
-            map.insert(key.into(), blob.into());
-
-            Ok(())
-        }
-
-        fn routes(&self) -> Vec<Route> {
-            vec![Route::url("mem://")]
-        }
-    }
 
     #[tokio::test]
     async fn test_block_store() {
-        let store = InMemoryStore::default();
+        let store = MemStore::new();
         let block_store = BlockStore::from(store);
 
         let blob = "some random data to store";
@@ -130,4 +151,57 @@ mod test {
 
         assert_eq!(blob.as_bytes(), received.as_slice());
     }
+
+    #[tokio::test]
+    async fn test_block_store_compression() {
+        let store = MemStore::new();
+        let blob = "some random data to store".repeat(100);
+
+        let zstd = BlockStore::from(store.clone()).with_compression(Compression::Zstd(5));
+        let block = zstd.set(blob.as_bytes()).await.unwrap();
+
+        // reading doesn't depend on the configured compression
+        let block_store = BlockStore::from(store);
+        let received = block_store.get(&block).await.unwrap();
+        assert_eq!(blob.as_bytes(), received.as_slice());
+        block_store.verify(&block).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_block_store_tampered() {
+        let store = MemStore::new();
+        let block_store = BlockStore::from(store.clone());
+
+        let block = block_store
+            .set("some random data".as_bytes())
+            .await
+            .unwrap();
+
+        let mut blob = store.get(&block.id).await.unwrap();
+        blob[0] ^= 0xff;
+        store.set(&block.id, &blob).await.unwrap();
+
+        assert!(matches!(
+            block_store.get(&block).await,
+            Err(Error::InvalidBlob)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_block_store_verify() {
+        let store = MemStore::new();
+        let block_store = BlockStore::from(store.clone());
+
+        let block = block_store
+            .set("some random data".as_bytes())
+            .await
+            .unwrap();
+        block_store.verify(&block).await.unwrap();
+
+        store.set(&block.id, "corrupted".as_bytes()).await.unwrap();
+        assert!(matches!(
+            block_store.verify(&block).await,
+            Err(Error::InvalidBlob)
+        ));
+    }
 }
@@ -0,0 +1,163 @@
+use super::{Error, Result};
+use std::fmt::Display;
+use std::str::FromStr;
+
+// blobs compressed with zstd or not compressed start with the magic followed by one
+// byte of the algorithm. Snappy blobs (the default) are written without a header, like
+// the blobs of older versions, so the same content keeps the same block id.
+const MAGIC: &[u8] = b"rfs";
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+const ZSTD_DEFAULT_LEVEL: i32 = 3;
+
+/// Compression is the algorithm used to compress blocks before encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Snappy,
+    /// zstd with the given compression level
+    Zstd(i32),
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::Snappy
+    }
+}
+
+impl Compression {
+    /// compress the data, the returned data starts with the compression header
+    /// except for snappy which is written as plain snappy
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let (id, body) = match self {
+            Self::None => (0, data.to_vec()),
+            Self::Snappy => return Ok(snap::raw::Encoder::new().compress_vec(data)?),
+            Self::Zstd(level) => (2, zstd::bulk::compress(data, *level)?),
+        };
+
+        let mut out = Vec::with_capacity(HEADER_LEN + body.len());
+        out.extend_from_slice(MAGIC);
+        out.push(id);
+        out.extend(body);
+
+        Ok(out)
+    }
+
+    /// decompress data compressed with any algorithm, the algorithm is detected
+    /// from the header. Data without a header is decompressed as snappy.
+    pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() >= HEADER_LEN && data.starts_with(MAGIC) {
+            let body = &data[HEADER_LEN..];
+            let plain = match data[MAGIC.len()] {
+                0 => Some(body.to_vec()),
+                1 => snap::raw::Decoder::new().decompress_vec(body).ok(),
+                2 => zstd::stream::decode_all(body).ok(),
+                _ => None,
+            };
+
+            // a legacy snappy blob can (unlikely) start with the magic
+            if let Some(plain) = plain {
+                return Ok(plain);
+            }
+        }
+
+        Ok(snap::raw::Decoder::new().decompress_vec(data)?)
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Snappy => write!(f, "snappy"),
+            Self::Zstd(level) => write!(f, "zstd:{}", level),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    /// parses `none`, `snappy`, `zstd` or `zstd:<level>`
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+
+        match (name, level) {
+            ("none", None) => Ok(Self::None),
+            ("snappy", None) => Ok(Self::Snappy),
+            ("zstd", None) => Ok(Self::Zstd(ZSTD_DEFAULT_LEVEL)),
+            ("zstd", Some(level)) => {
+                let level: i32 = level
+                    .parse()
+                    .map_err(|_| Error::Other(anyhow::anyhow!("invalid zstd level '{}'", level)))?;
+                if !zstd::compression_level_range().contains(&level) {
+                    return Err(Error::Other(anyhow::anyhow!(
+                        "zstd level '{}' is out of range",
+                        level
+                    )));
+                }
+                Ok(Self::Zstd(level))
+            }
+            _ => Err(Error::Other(anyhow::anyhow!("unknown compression '{}'", s))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compression() {
+        let data = "some text that compresses well, well, well, well".repeat(10);
+
+        for compression in [Compression::None, Compression::Snappy, Compression::Zstd(9)] {
+            let compressed = compression.compress(data.as_bytes()).unwrap();
+            let plain = Compression::decompress(&compressed).unwrap();
+            assert_eq!(plain, data.as_bytes());
+        }
+
+        assert!(Compression::Zstd(9)
+            .compress(data.as_bytes())
+            .unwrap()
+            .starts_with(MAGIC));
+        // snappy blobs are the same as the ones of older versions, so blocks keep
+        // their ids and can be read by older clients
+        assert_eq!(
+            Compression::Snappy.compress(data.as_bytes()).unwrap(),
+            snap::raw::Encoder::new()
+                .compress_vec(data.as_bytes())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decompress_legacy() {
+        let data = "legacy blob without a header";
+        let compressed = snap::raw::Encoder::new()
+            .compress_vec(data.as_bytes())
+            .unwrap();
+
+        let plain = Compression::decompress(&compressed).unwrap();
+        assert_eq!(plain, data.as_bytes());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("none".parse::<Compression>().unwrap(), Compression::None);
+        assert_eq!(
+            "zstd".parse::<Compression>().unwrap(),
+            Compression::Zstd(ZSTD_DEFAULT_LEVEL)
+        );
+        assert_eq!(
+            "zstd:19".parse::<Compression>().unwrap(),
+            Compression::Zstd(19)
+        );
+        assert!("zstd:100".parse::<Compression>().is_err());
+        assert!("gzip".parse::<Compression>().is_err());
+        assert_eq!(Compression::Zstd(19).to_string(), "zstd:19");
+    }
+}
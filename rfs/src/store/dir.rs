@@ -29,49 +29,98 @@ impl DirStore {
         fs::create_dir_all(&root).await?;
         Ok(Self { root })
     }
+
+    /// paths returns the possible locations of the blob with the given key. Blobs are
+    /// written to the first one (`xx/yy/<key>`), the others are the locations used by
+    /// older versions (`xx/<key>` then `<key>`) that are still supported for reading.
+    fn paths(&self, key: &[u8]) -> [PathBuf; 3] {
+        let file_name = hex::encode(key);
+        let level1 = self.root.join(&file_name[0..2]);
+        [
+            level1
+                .join(file_name.get(2..4).unwrap_or_default())
+                .join(&file_name),
+            level1.join(&file_name),
+            self.root.join(&file_name),
+        ]
+    }
 }
 
 #[async_trait::async_trait]
 impl Store for DirStore {
     async fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
-        let file_name = hex::encode(key);
-        let dir_path = self.root.join(&file_name[0..2]);
-
-        let mut path = dir_path.join(&file_name);
-        let data = match fs::read(&path).await {
-            Ok(data) => data,
-            Err(err) if err.kind() == ErrorKind::NotFound => {
-                path = self.root.join(file_name);
-                let data = match fs::read(&path).await {
-                    Ok(data) => data,
-                    Err(err) if err.kind() == ErrorKind::NotFound => {
-                        return Err(Error::KeyNotFound);
-                    }
-                    Err(err) => {
-                        return Err(Error::IO(err));
-                    }
-                };
-                data
-            }
-            Err(err) => {
-                return Err(Error::IO(err));
+        for path in self.paths(key).iter() {
+            match fs::read(path).await {
+                Ok(data) => return Ok(data),
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::IO(err)),
             }
-        };
+        }
 
-        Ok(data)
+        Err(Error::KeyNotFound)
     }
 
     async fn set(&self, key: &[u8], blob: &[u8]) -> Result<()> {
-        let file_name = hex::encode(key);
-        let dir_path = self.root.join(&file_name[0..2]);
-
-        fs::create_dir_all(&dir_path).await?;
+        let [path, ..] = self.paths(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
 
-        let file_path = dir_path.join(file_name);
-        fs::write(file_path, blob).await?;
+        fs::write(path, blob).await?;
         Ok(())
     }
 
+    async fn exists(&self, key: &[u8]) -> Result<bool> {
+        for path in self.paths(key).iter() {
+            match fs::metadata(path).await {
+                Ok(_) => return Ok(true),
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::IO(err)),
+            }
+        }
+
+        Ok(false)
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        for path in self.paths(key).iter() {
+            match fs::remove_file(path).await {
+                Ok(_) => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::IO(err)),
+            }
+        }
+
+        Err(Error::KeyNotFound)
+    }
+
+    async fn keys(&self) -> Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::default();
+        // blobs are stored under two levels of prefix directories, under one
+        // level or directly in the root (older layouts)
+        let mut dirs = vec![(self.root.clone(), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if entry.file_type().await?.is_dir() {
+                    if depth < 2 && name.len() == 2 {
+                        dirs.push((entry.path(), depth + 1));
+                    }
+                    continue;
+                }
+
+                match hex::decode(name.as_bytes()) {
+                    Ok(key) => keys.push(key),
+                    Err(_) => log::debug!("skipping unknown file {:?}", entry.path()),
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
     fn routes(&self) -> Vec<Route> {
         let r = Route::url(format!(
             "dir://{}",
@@ -81,3 +130,48 @@ impl Store for DirStore {
         vec![r]
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dir_store_layout() {
+        const ROOT: &str = "/tmp/dir-store-layout-test";
+        let _ = fs::remove_dir_all(ROOT).await;
+        let store = DirStore::new(ROOT).await.unwrap();
+
+        let key = [0xab, 0xcd, 0xef];
+        store.set(&key, b"sharded").await.unwrap();
+        assert_eq!(
+            fs::read(format!("{}/ab/cd/abcdef", ROOT)).await.unwrap(),
+            b"sharded"
+        );
+
+        // blobs written with the older layouts can still be read
+        let one_level = [0x01, 0x02, 0x03];
+        fs::create_dir_all(format!("{}/01", ROOT)).await.unwrap();
+        fs::write(format!("{}/01/010203", ROOT), b"one level")
+            .await
+            .unwrap();
+        let flat = [0x04, 0x05, 0x06];
+        fs::write(format!("{}/040506", ROOT), b"flat")
+            .await
+            .unwrap();
+
+        assert_eq!(store.get(&key).await.unwrap(), b"sharded");
+        assert_eq!(store.get(&one_level).await.unwrap(), b"one level");
+        assert_eq!(store.get(&flat).await.unwrap(), b"flat");
+        assert!(store.exists(&flat).await.unwrap());
+
+        let mut keys = store.keys().await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec![one_level.to_vec(), flat.to_vec(), key.to_vec()]);
+
+        store.delete(&one_level).await.unwrap();
+        assert!(matches!(
+            store.get(&one_level).await,
+            Err(Error::KeyNotFound)
+        ));
+    }
+}
@@ -1,10 +1,11 @@
-use super::{Error, Result, Route, Store};
+use super::{Error, Result, Retry, Route, Store};
 use reqwest::{self, StatusCode};
 use url::Url;
 
 #[derive(Clone)]
 pub struct HTTPStore {
     url: Url,
+    retry: Retry,
 }
 
 impl HTTPStore {
@@ -18,45 +19,78 @@ impl HTTPStore {
     }
     pub async fn new<U: Into<Url>>(url: U) -> Result<Self> {
         let url = url.into();
-        Ok(Self { url })
+        let retry = Retry::from_url(&url)?;
+        Ok(Self { url, retry })
+    }
+
+    /// paths returns the possible urls of the blob with the given key, in the same
+    /// order as the dir store locations: `xx/yy/<key>` then the locations used by
+    /// older versions (`xx/<key>` then `<key>`)
+    fn paths(&self, key: &[u8]) -> Result<[Url; 3]> {
+        let file = hex::encode(key);
+        // the query only holds the store options
+        let mut base = self.url.clone();
+        base.set_query(None);
+
+        let url = |segments: &[&str]| -> Result<Url> {
+            let mut url = base.clone();
+            url.path_segments_mut()
+                .map_err(|_| Error::Other(anyhow::Error::msg("cannot be base")))?
+                .pop_if_empty()
+                .extend(segments);
+            Ok(url)
+        };
+
+        Ok([
+            url(&[&file[0..2], file.get(2..4).unwrap_or_default(), &file])?,
+            url(&[&file[0..2], &file])?,
+            url(&[&file])?,
+        ])
+    }
+
+    async fn get_once(&self, key: &[u8]) -> Result<Vec<u8>> {
+        for path in self.paths(key)? {
+            let response = reqwest::get(path)
+                .await
+                .map_err(|e| Error::Other(e.into()))?;
+
+            match response.status() {
+                StatusCode::OK => {
+                    let data = response.bytes().await.map_err(|e| Error::Other(e.into()))?;
+                    return Ok(data.into());
+                }
+                StatusCode::NOT_FOUND => continue,
+                _ => return Err(Error::Unavailable),
+            }
+        }
+
+        Err(Error::KeyNotFound)
     }
 }
 
 #[async_trait::async_trait]
 impl Store for HTTPStore {
     async fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
-        let file = hex::encode(key);
-        let mut file_path = self.url.clone();
-        file_path
-            .path_segments_mut()
-            .map_err(|_| Error::Other(anyhow::Error::msg("cannot be base")))?
-            .push(&file[0..2])
-            .push(&file);
-        let mut legacy_path = self.url.clone();
-
-        legacy_path
-            .path_segments_mut()
-            .map_err(|_| Error::Other(anyhow::Error::msg("cannot be base")))?
-            .push(&file);
-
-        let data = match reqwest::get(file_path).await {
-            Ok(mut response) => {
-                if response.status() == StatusCode::NOT_FOUND {
-                    response = reqwest::get(legacy_path)
-                        .await
-                        .map_err(|_| Error::KeyNotFound)?;
-                    if response.status() != StatusCode::OK {
-                        return Err(Error::KeyNotFound);
-                    }
-                }
-                if response.status() != StatusCode::OK {
-                    return Err(Error::Unavailable);
-                }
-                response.bytes().await.map_err(|e| Error::Other(e.into()))?
+        self.retry.run(|| self.get_once(key)).await
+    }
+
+    async fn exists(&self, key: &[u8]) -> Result<bool> {
+        let client = reqwest::Client::new();
+        for path in self.paths(key)? {
+            let response = client
+                .head(path)
+                .send()
+                .await
+                .map_err(|e| Error::Other(e.into()))?;
+
+            match response.status() {
+                StatusCode::OK => return Ok(true),
+                StatusCode::NOT_FOUND => continue,
+                _ => return Err(Error::Unavailable),
             }
-            Err(err) => return Err(Error::Other(err.into())),
-        };
-        Ok(data.into())
+        }
+
+        Ok(false)
     }
 
     async fn set(&self, _key: &[u8], _blob: &[u8]) -> Result<()> {
@@ -65,9 +99,107 @@ impl Store for HTTPStore {
         )))
     }
 
+    async fn delete(&self, _key: &[u8]) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    /// health only checks that the server is reachable, the store is read only
+    async fn health(&self) -> Result<()> {
+        let key: [u8; 32] = rand::random();
+        self.exists(&key).await.map(|_| ())
+    }
+
     fn routes(&self) -> Vec<Route> {
         let r = Route::url(self.url.clone());
 
         vec![r]
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::dir::DirStore;
+    use std::path::{Path, PathBuf};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    /// serve answers GET and HEAD requests with the files under root, like a web
+    /// server in front of a dir store
+    async fn serve(root: PathBuf) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let root = root.clone();
+                tokio::spawn(async move {
+                    let mut reader = BufReader::new(&mut stream);
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    // skip the headers
+                    let mut header = String::new();
+                    while reader.read_line(&mut header).await.unwrap() > 2 {
+                        header.clear();
+                    }
+
+                    let mut parts = line.split_whitespace();
+                    let method = parts.next().unwrap_or_default();
+                    let path = parts.next().unwrap_or_default().trim_start_matches('/');
+                    let response = match tokio::fs::read(root.join(path)).await {
+                        Ok(data) => {
+                            let mut response = format!(
+                                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                                data.len()
+                            )
+                            .into_bytes();
+                            if method != "HEAD" {
+                                response.extend(data);
+                            }
+                            response
+                        }
+                        Err(_) => b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                            .to_vec(),
+                    };
+                    stream.write_all(&response).await.unwrap();
+                });
+            }
+        });
+
+        format!("http://{}/store", address)
+    }
+
+    #[tokio::test]
+    async fn test_http_store_dir_layout() {
+        const ROOT: &str = "/tmp/http-store-layout-test";
+        let _ = tokio::fs::remove_dir_all(ROOT).await;
+        let dir = DirStore::new(Path::new(ROOT).join("store")).await.unwrap();
+
+        let key = [0xab, 0xcd, 0xef];
+        dir.set(&key, b"sharded").await.unwrap();
+        // blobs written with the older layouts
+        let one_level = [0x01, 0x02, 0x03];
+        tokio::fs::create_dir_all(format!("{}/store/01", ROOT))
+            .await
+            .unwrap();
+        tokio::fs::write(format!("{}/store/01/010203", ROOT), b"one level")
+            .await
+            .unwrap();
+        let flat = [0x04, 0x05, 0x06];
+        tokio::fs::write(format!("{}/store/040506", ROOT), b"flat")
+            .await
+            .unwrap();
+
+        let url = serve(PathBuf::from(ROOT)).await;
+        let store = HTTPStore::make(&url).await.unwrap();
+
+        assert_eq!(store.get(&key).await.unwrap(), b"sharded");
+        assert_eq!(store.get(&one_level).await.unwrap(), b"one level");
+        assert_eq!(store.get(&flat).await.unwrap(), b"flat");
+        assert!(store.exists(&key).await.unwrap());
+        assert!(!store.exists(&[0x07, 0x08, 0x09]).await.unwrap());
+        assert!(matches!(
+            store.get(&[0x07, 0x08, 0x09]).await,
+            Err(Error::KeyNotFound)
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+use super::{Error, Result, Route, Store};
+use reqwest::{multipart, StatusCode};
+use serde::Deserialize;
+use url::Url;
+
+pub const SCHEME: &str = "ipfs";
+
+const DEFAULT_PORT: u16 = 5001;
+const DEFAULT_ROOT: &str = "/rfs";
+
+/// IpfsStore stores blobs on an IPFS node using its HTTP API. IPFS addresses the
+/// content by its own CID, so each blob is also linked in the node MFS (mutable
+/// file system) under `<root>/<hex key>` which maps the blob key to its CID.
+///
+/// The url is in the format `ipfs://<api-host>[:port][/root]`, root defaults to `/rfs`.
+#[derive(Clone)]
+pub struct IpfsStore {
+    url: String,
+    api: Url,
+    root: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct AddResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct StatResponse {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct LsEntry {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct LsResponse {
+    #[serde(rename = "Entries")]
+    entries: Option<Vec<LsEntry>>,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    #[serde(rename = "Message")]
+    message: String,
+}
+
+impl IpfsStore {
+    pub async fn make<U: AsRef<str>>(url: &U) -> Result<IpfsStore> {
+        let u = Url::parse(url.as_ref())?;
+        if u.scheme() != SCHEME {
+            return Err(Error::InvalidScheme(u.scheme().into(), SCHEME.into()));
+        }
+
+        let host = u
+            .host_str()
+            .ok_or_else(|| Error::Other(anyhow::anyhow!("ipfs url is missing the api host")))?;
+        let api = Url::parse(&format!(
+            "http://{}:{}/api/v0/",
+            host,
+            u.port().unwrap_or(DEFAULT_PORT)
+        ))?;
+
+        let root = match u.path().trim_end_matches('/') {
+            "" => DEFAULT_ROOT.into(),
+            path => path.into(),
+        };
+
+        Ok(IpfsStore {
+            url: url.as_ref().into(),
+            api,
+            root,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn path(&self, key: &[u8]) -> String {
+        format!("{}/{}", self.root, hex::encode(key))
+    }
+
+    /// call the api command with the given arguments, the node returns
+    /// the errors in a json body
+    async fn call(
+        &self,
+        command: &str,
+        args: &[(&str, &str)],
+        form: Option<multipart::Form>,
+    ) -> Result<reqwest::Response> {
+        let url = self.api.join(command)?;
+        let mut request = self.client.post(url).query(args);
+        if let Some(form) = form {
+            request = request.multipart(form);
+        }
+
+        let response = request.send().await.map_err(|e| Error::Other(e.into()))?;
+        if response.status() == StatusCode::OK {
+            return Ok(response);
+        }
+
+        let status = response.status();
+        let message = match response.json::<ApiError>().await {
+            Ok(err) => err.message,
+            Err(_) => status.to_string(),
+        };
+
+        if message.contains("does not exist") || message.contains("not found") {
+            return Err(Error::KeyNotFound);
+        }
+
+        Err(Error::Other(anyhow::anyhow!(
+            "ipfs {} failed: {}",
+            command,
+            message
+        )))
+    }
+
+    /// cid returns the CID of the blob with the given key
+    async fn cid(&self, key: &[u8]) -> Result<String> {
+        let path = self.path(key);
+        let stat: StatResponse = self
+            .call("files/stat", &[("arg", &path)], None)
+            .await?
+            .json()
+            .await
+            .map_err(|e| Error::Other(e.into()))?;
+
+        Ok(stat.hash)
+    }
+}
+
+#[async_trait::async_trait]
+impl Store for IpfsStore {
+    async fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let cid = self.cid(key).await?;
+        let data = self
+            .call("cat", &[("arg", &cid)], None)
+            .await?
+            .bytes()
+            .await
+            .map_err(|e| Error::Other(e.into()))?;
+
+        Ok(data.into())
+    }
+
+    async fn set(&self, key: &[u8], blob: &[u8]) -> Result<()> {
+        // blobs are content addressed, if the key is already linked
+        // the blob is already uploaded
+        if self.exists(key).await? {
+            return Ok(());
+        }
+
+        let form = multipart::Form::new().part(
+            "file",
+            multipart::Part::bytes(blob.to_vec()).file_name(hex::encode(key)),
+        );
+
+        let added: AddResponse = self
+            .call("add", &[("pin", "true")], Some(form))
+            .await?
+            .json()
+            .await
+            .map_err(|e| Error::Other(e.into()))?;
+
+        // link the blob in the MFS so it can be found by key
+        let path = self.path(key);
+        self.call(
+            "files/mkdir",
+            &[("arg", &self.root), ("parents", "true")],
+            None,
+        )
+        .await?;
+        self.call(
+            "files/cp",
+            &[("arg", &format!("/ipfs/{}", added.hash)), ("arg", &path)],
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn exists(&self, key: &[u8]) -> Result<bool> {
+        match self.cid(key).await {
+            Ok(_) => Ok(true),
+            Err(Error::KeyNotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        let cid = self.cid(key).await?;
+        self.call("files/rm", &[("arg", &self.path(key))], None)
+            .await?;
+        // unpin so the node garbage collector can free the blob
+        match self.call("pin/rm", &[("arg", &cid)], None).await {
+            Ok(_) | Err(Error::KeyNotFound) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn keys(&self) -> Result<Vec<Vec<u8>>> {
+        let ls: LsResponse = match self.call("files/ls", &[("arg", &self.root)], None).await {
+            Ok(response) => response.json().await.map_err(|e| Error::Other(e.into()))?,
+            Err(Error::KeyNotFound) => return Ok(Vec::default()),
+            Err(err) => return Err(err),
+        };
+
+        Ok(ls
+            .entries
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|entry| hex::decode(entry.name).ok())
+            .collect())
+    }
+
+    fn routes(&self) -> Vec<Route> {
+        vec![Route::url(&self.url)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_make() {
+        let store = IpfsStore::make(&"ipfs://localhost").await.unwrap();
+        assert_eq!(store.api.as_str(), "http://localhost:5001/api/v0/");
+        assert_eq!(store.path(&[0xab, 0xcd]), "/rfs/abcd");
+
+        let store = IpfsStore::make(&"ipfs://node:8080/blocks/").await.unwrap();
+        assert_eq!(store.api.as_str(), "http://node:8080/api/v0/");
+        assert_eq!(store.path(&[0xab]), "/blocks/ab");
+
+        assert!(IpfsStore::make(&"http://localhost").await.is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Result, Route, Store};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// LimitStore wraps a store to limit the number of concurrent operations
+/// on it, so a backend is not overwhelmed by parallel uploads or downloads.
+/// Clones of the store share the same limit.
+#[derive(Clone)]
+pub struct LimitStore<S> {
+    inner: S,
+    semaphore: Arc<Semaphore>,
+}
+
+impl<S> LimitStore<S>
+where
+    S: Store,
+{
+    pub fn new(inner: S, permits: usize) -> Self {
+        Self {
+            inner,
+            semaphore: Arc::new(Semaphore::new(permits)),
+        }
+    }
+
+    /// inner returns the limited store
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> Store for LimitStore<S>
+where
+    S: Store,
+{
+    async fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.get(key).await
+    }
+
+    async fn set(&self, key: &[u8], blob: &[u8]) -> Result<()> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.set(key, blob).await
+    }
+
+    async fn exists(&self, key: &[u8]) -> Result<bool> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.exists(key).await
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.delete(key).await
+    }
+
+    async fn keys(&self) -> Result<Vec<Vec<u8>>> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.keys().await
+    }
+
+    async fn health(&self) -> Result<()> {
+        let _permit = self.semaphore.acquire().await;
+        self.inner.health().await
+    }
+
+    fn routes(&self) -> Vec<Route> {
+        self.inner.routes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{mem::MemStore, Error};
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct CountingStore {
+        inner: MemStore,
+        current: Arc<AtomicUsize>,
+        max: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Store for CountingStore {
+        async fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+            self.inner.get(key).await
+        }
+
+        async fn set(&self, key: &[u8], blob: &[u8]) -> Result<()> {
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+
+            self.inner.set(key, blob).await
+        }
+
+        async fn delete(&self, _key: &[u8]) -> Result<()> {
+            Err(Error::Unsupported)
+        }
+
+        fn routes(&self) -> Vec<Route> {
+            self.inner.routes()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limit() {
+        let inner = CountingStore::default();
+        let store = LimitStore::new(inner.clone(), 2);
+
+        let uploads = (0..10u8).map(|i| {
+            let store = store.clone();
+            tokio::spawn(async move { store.set(&[i], &[i]).await })
+        });
+
+        for upload in futures::future::join_all(uploads).await {
+            upload.unwrap().unwrap();
+        }
+
+        assert_eq!(inner.max.load(Ordering::SeqCst), 2);
+        assert_eq!(store.get(&[5]).await.unwrap(), vec![5]);
+    }
+}
@@ -0,0 +1,98 @@
+use super::{Error, Result, Route, Store};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub const SCHEME: &str = "mem";
+
+/// MemStore is a store that keeps blobs in memory, it is mainly used for
+/// testing. Clones of the store share the same blobs.
+#[derive(Clone, Default)]
+pub struct MemStore {
+    map: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl MemStore {
+    pub async fn make<U: AsRef<str>>(url: &U) -> Result<MemStore> {
+        let u = url::Url::parse(url.as_ref())?;
+        if u.scheme() != SCHEME {
+            return Err(Error::InvalidScheme(u.scheme().into(), SCHEME.into()));
+        }
+
+        Ok(MemStore::new())
+    }
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl Store for MemStore {
+    async fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let map = self.map.lock().await;
+        let blob = map.get(key).ok_or(Error::KeyNotFound)?;
+
+        Ok(blob.clone())
+    }
+
+    async fn set(&self, key: &[u8], blob: &[u8]) -> Result<()> {
+        let mut map = self.map.lock().await;
+        map.insert(key.into(), blob.into());
+
+        Ok(())
+    }
+
+    async fn exists(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.map.lock().await.contains_key(key))
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut map = self.map.lock().await;
+        map.remove(key).ok_or(Error::KeyNotFound)?;
+
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self.map.lock().await.keys().cloned().collect())
+    }
+
+    fn routes(&self) -> Vec<Route> {
+        vec![Route::url(format!("{}://", SCHEME))]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mem_store() {
+        let store = MemStore::make(&"mem://").await.unwrap();
+
+        assert!(matches!(store.get(b"key").await, Err(Error::KeyNotFound)));
+
+        store.set(b"key", b"value").await.unwrap();
+        assert_eq!(store.get(b"key").await.unwrap(), b"value");
+        assert!(store.exists(b"key").await.unwrap());
+        assert_eq!(store.keys().await.unwrap(), vec![b"key".to_vec()]);
+
+        store.delete(b"key").await.unwrap();
+        assert!(!store.exists(b"key").await.unwrap());
+
+        let routes = store.routes();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].url, "mem://");
+        assert!(routes[0].start.is_none() && routes[0].end.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mem_store_health() {
+        let store = MemStore::new();
+        store.health().await.unwrap();
+
+        // the probe blob is removed
+        assert!(store.keys().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+use super::{Error, Result};
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+use url::Url;
+
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Retry is the retry policy of a store operation. Failed operations are retried with
+/// an exponential backoff (backoff * 2^attempt) plus a random jitter of up to half the
+/// delay. Only transient errors are retried, so a missing key fails immediately.
+///
+/// The policy is configured with query params of the store url, for example
+/// `zdb://hub:9900/ns?retries=5&backoff=200ms&jitter=false`. By default
+/// operations are not retried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retry {
+    /// max number of retries after the first attempt
+    pub retries: u32,
+    /// base delay between attempts
+    pub backoff: Duration,
+    pub jitter: bool,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: DEFAULT_BACKOFF,
+            jitter: true,
+        }
+    }
+}
+
+impl Retry {
+    /// from_url parses the retry policy from the url query params
+    pub fn from_url(u: &Url) -> Result<Self> {
+        let mut retry = Self::default();
+        for (key, value) in u.query_pairs() {
+            match key.as_ref() {
+                "retries" => {
+                    retry.retries = value.parse().map_err(|_| {
+                        Error::Other(anyhow::anyhow!("invalid retries value '{}'", value))
+                    })?
+                }
+                "backoff" => retry.backoff = parse_duration(&value)?,
+                "jitter" => {
+                    retry.jitter = value.parse().map_err(|_| {
+                        Error::Other(anyhow::anyhow!("invalid jitter value '{}'", value))
+                    })?
+                }
+                _ => {}
+            }
+        }
+
+        Ok(retry)
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.backoff.saturating_mul(2u32.saturating_pow(attempt));
+        if !self.jitter || delay.is_zero() {
+            return delay;
+        }
+
+        let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() as u64 / 2);
+        delay + Duration::from_millis(jitter)
+    }
+
+    /// run the operation until it succeeds, fails with a permanent error, or
+    /// runs out of retries
+    pub async fn run<F, Fut, T>(&self, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Err(err) if attempt < self.retries && is_transient(&err) => {
+                    let delay = self.delay(attempt);
+                    log::debug!("store operation failed, retrying in {:?}: {}", delay, err);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// is_transient returns true for errors that might succeed on retry, like
+/// connection errors or server errors
+fn is_transient(err: &Error) -> bool {
+    matches!(err, Error::Unavailable | Error::IO(_) | Error::Other(_))
+}
+
+/// parse_duration parses a duration in the format <n>ms or <n>s, a number
+/// without unit is in milliseconds
+fn parse_duration(value: &str) -> Result<Duration> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "ms"),
+    };
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| Error::Other(anyhow::anyhow!("invalid duration '{}'", value)))?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        _ => Err(Error::Other(anyhow::anyhow!(
+            "invalid duration unit '{}'",
+            unit
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_from_url() {
+        let u = Url::parse("zdb://localhost:9900/ns?retries=5&backoff=2s&jitter=false").unwrap();
+        let retry = Retry::from_url(&u).unwrap();
+        assert_eq!(
+            retry,
+            Retry {
+                retries: 5,
+                backoff: Duration::from_secs(2),
+                jitter: false
+            }
+        );
+
+        let u = Url::parse("http://localhost/store?backoff=200ms").unwrap();
+        let retry = Retry::from_url(&u).unwrap();
+        assert_eq!(retry.retries, 0);
+        assert_eq!(retry.backoff, Duration::from_millis(200));
+
+        let u = Url::parse("http://localhost/store?backoff=2h").unwrap();
+        assert!(Retry::from_url(&u).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let retry = Retry {
+            retries: 3,
+            backoff: Duration::from_millis(1),
+            jitter: true,
+        };
+
+        // transient errors are retried
+        let calls = AtomicU32::new(0);
+        let result = retry
+            .run(|| async {
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    return Err(Error::Unavailable);
+                }
+                Ok(())
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // missing keys are not
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = retry
+            .run(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(Error::KeyNotFound)
+            })
+            .await;
+        assert!(matches!(result, Err(Error::KeyNotFound)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // gives up after retries
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = retry
+            .run(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(Error::Unavailable)
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+}
@@ -1,22 +1,67 @@
+use rand::seq::SliceRandom;
 use std::ops::RangeInclusive;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
 /// route implements a naive prefix router by going through the complete set of
 /// available routers and find that ones that matches this given prefix
 #[derive(Default, Clone)]
 pub struct Router<T> {
-    pub(crate) routes: Vec<(RangeInclusive<u8>, T)>,
+    // range of the keys, store and priority of each route
+    pub(crate) routes: Vec<(RangeInclusive<u8>, T, u8)>,
+    replicas: usize,
+    quorum: usize,
+    next: Arc<AtomicUsize>,
 }
 
 impl<T> Router<T> {
     pub fn new() -> Self {
         Self {
             routes: Vec::default(),
+            replicas: 0,
+            quorum: 0,
+            next: Arc::default(),
         }
     }
 
+    /// set_quorum sets the minimum number of stores a key must be written to for
+    /// the write to succeed. 0 (default) means all the stores chosen for the key
+    pub fn set_quorum(&mut self, quorum: usize) {
+        self.quorum = quorum;
+    }
+
+    /// required returns the number of successful writes required out of the given
+    /// number of target stores
+    pub fn required(&self, targets: usize) -> usize {
+        if self.quorum == 0 {
+            return targets;
+        }
+
+        std::cmp::min(self.quorum, targets)
+    }
+
+    /// set_replicas sets the number of distinct stores a key is written to. 0 (default)
+    /// means the key is written to all the stores that matches the key
+    pub fn set_replicas(&mut self, replicas: usize) {
+        self.replicas = replicas;
+    }
+
+    /// replicas returns the number of stores a key is written to, 0 means all
+    pub fn replicas(&self) -> usize {
+        self.replicas
+    }
+
     /// add a range
     pub fn add(&mut self, start: u8, end: u8, route: T) {
-        self.routes.push((start..=end, route));
+        self.add_with_priority(start, end, 0, route);
+    }
+
+    /// add a range with a read priority, stores with a higher priority are read
+    /// first (see [`Router::ordered`])
+    pub fn add_with_priority(&mut self, start: u8, end: u8, priority: u8, route: T) {
+        self.routes.push((start..=end, route, priority));
     }
 
     /// return all stores that matches a certain key
@@ -28,6 +73,34 @@ impl<T> Router<T> {
             .filter(move |f| f.0.contains(&i))
             .map(|v| &v.1)
     }
+
+    /// return all stores that matches a certain key in the order they should be
+    /// read from: by priority (highest first), stores with the same priority are
+    /// shuffled so reads are spread over them
+    pub fn ordered(&self, i: u8) -> Vec<&T> {
+        let mut routes: Vec<&(RangeInclusive<u8>, T, u8)> =
+            self.routes.iter().filter(|f| f.0.contains(&i)).collect();
+        routes.shuffle(&mut rand::thread_rng());
+        // the sort is stable, so the shuffle order is kept for the same priority
+        routes.sort_by(|a, b| b.2.cmp(&a.2));
+
+        routes.into_iter().map(|v| &v.1).collect()
+    }
+
+    /// return the stores where a key should be written, this is all the stores that
+    /// matches the key unless replicas is set, then only replicas of them are chosen
+    /// round-robin
+    pub fn replicate(&self, i: u8) -> Vec<&T> {
+        let stores: Vec<&T> = self.route(i).collect();
+        if self.replicas == 0 || self.replicas >= stores.len() {
+            return stores;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.replicas)
+            .map(|n| stores[(start + n) % stores.len()])
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -53,4 +126,57 @@ mod test {
         assert_eq!(paths[1], "b");
         assert_eq!(paths[2], "c");
     }
+
+    #[test]
+    fn test_replicate() {
+        let mut router = Router::default();
+
+        router.add(0, 255, "a");
+        router.add(0, 255, "b");
+        router.add(0, 255, "c");
+        router.add(0, 128, "d");
+
+        // all stores by default
+        assert_eq!(router.replicate(200), vec![&"a", &"b", &"c"]);
+
+        router.set_replicas(2);
+        assert_eq!(router.replicate(200), vec![&"a", &"b"]);
+        assert_eq!(router.replicate(200), vec![&"b", &"c"]);
+        assert_eq!(router.replicate(200), vec![&"c", &"a"]);
+
+        // more replicas than stores
+        router.set_replicas(5);
+        assert_eq!(router.replicate(200).len(), 3);
+    }
+
+    #[test]
+    fn test_ordered() {
+        let mut router = Router::default();
+
+        router.add(0, 255, "a");
+        router.add(0, 255, "b");
+        router.add_with_priority(0, 255, 2, "c");
+        router.add_with_priority(0, 128, 1, "d");
+
+        for _ in 0..10 {
+            let stores = router.ordered(100);
+            assert_eq!(&stores[..2], &[&"c", &"d"]);
+            let mut rest = stores[2..].to_vec();
+            rest.sort();
+            assert_eq!(rest, vec![&"a", &"b"]);
+        }
+
+        assert_eq!(router.ordered(200)[0], &"c");
+        assert_eq!(router.ordered(200).len(), 3);
+    }
+
+    #[test]
+    fn test_required() {
+        let mut router: Router<&str> = Router::default();
+        assert_eq!(router.required(3), 3);
+
+        router.set_quorum(2);
+        assert_eq!(router.required(3), 2);
+        assert_eq!(router.required(1), 1);
+    }
 }
@@ -1,4 +1,4 @@
-use super::{Error, Result, Route, Store};
+use super::{Error, Result, Retry, Route, Store};
 use anyhow::Context;
 
 use bb8_redis::{
@@ -81,11 +81,13 @@ fn get_connection_info<U: AsRef<str>>(u: U) -> Result<(ConnectionInfo, Option<St
 pub struct ZdbStore {
     url: String,
     pool: Pool<RedisConnectionManager>,
+    retry: Retry,
 }
 
 impl ZdbStore {
     pub async fn make<U: AsRef<str>>(url: &U) -> Result<ZdbStore> {
         let (mut info, namespace) = get_connection_info(url.as_ref())?;
+        let retry = Retry::from_url(&url::Url::parse(url.as_ref())?)?;
 
         let namespace = WithNamespace {
             namespace,
@@ -108,13 +110,11 @@ impl ZdbStore {
         Ok(ZdbStore {
             url: url.as_ref().to_string(),
             pool,
+            retry,
         })
     }
-}
 
-#[async_trait::async_trait]
-impl Store for ZdbStore {
-    async fn get(&self, key: &[u8]) -> super::Result<Vec<u8>> {
+    async fn get_once(&self, key: &[u8]) -> Result<Vec<u8>> {
         let mut con = self.pool.get().await.context("failed to get connection")?;
 
         let result: Option<Vec<u8>> = con.get(key).await.context("failed to get blob")?;
@@ -127,7 +127,7 @@ impl Store for ZdbStore {
         Ok(result)
     }
 
-    async fn set(&self, key: &[u8], blob: &[u8]) -> Result<()> {
+    async fn set_once(&self, key: &[u8], blob: &[u8]) -> Result<()> {
         let mut con = self.pool.get().await.context("failed to get connection")?;
 
         if con
@@ -142,6 +142,38 @@ impl Store for ZdbStore {
 
         Ok(())
     }
+}
+
+#[async_trait::async_trait]
+impl Store for ZdbStore {
+    async fn get(&self, key: &[u8]) -> super::Result<Vec<u8>> {
+        self.retry.run(|| self.get_once(key)).await
+    }
+
+    async fn set(&self, key: &[u8], blob: &[u8]) -> Result<()> {
+        self.retry.run(|| self.set_once(key, blob)).await
+    }
+
+    async fn exists(&self, key: &[u8]) -> Result<bool> {
+        let mut con = self.pool.get().await.context("failed to get connection")?;
+
+        let exists = con
+            .exists(key)
+            .await
+            .context("failed to check if blob exists")?;
+
+        Ok(exists)
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut con = self.pool.get().await.context("failed to get connection")?;
+
+        con.del::<_, ()>(key)
+            .await
+            .context("failed to delete blob")?;
+
+        Ok(())
+    }
 
     fn routes(&self) -> Vec<Route> {
         vec![Route::url(self.url.clone())]
@@ -0,0 +1,281 @@
+use crate::fungi::{
+    meta::{FileType, Inode, Mode, Walk, WalkVisitor},
+    Reader, Result,
+};
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+/// TreeFormat is how the entries of an FL are printed by the [`TreeVisitor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TreeFormat {
+    /// indented tree of names, directories end with `/`
+    #[default]
+    Ascii,
+    /// one JSON object per entry and line
+    Ndjson,
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    path: &'a Path,
+    #[serde(rename = "type")]
+    typ: &'static str,
+    size: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+/// TreeVisitor writes each visited entry to the output as soon as it's visited,
+/// so the tree is never held in memory.
+pub struct TreeVisitor<W: Write> {
+    out: W,
+    format: TreeFormat,
+}
+
+impl<W: Write> TreeVisitor<W> {
+    pub fn new(out: W, format: TreeFormat) -> Self {
+        Self { out, format }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn ascii(&mut self, path: &Path, node: &Inode) -> std::io::Result<()> {
+        // the root is visited as `/` which has no name
+        let depth = path.components().count().saturating_sub(1);
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_else(|| "/".into());
+
+        write!(self.out, "{:width$}{}", "", name, width = depth * 2)?;
+        match node.mode.file_type() {
+            FileType::Dir if depth > 0 => write!(self.out, "/")?,
+            FileType::Link => {
+                let target = node.data.as_deref().unwrap_or_default();
+                write!(self.out, " -> {}", String::from_utf8_lossy(target))?
+            }
+            _ => {}
+        }
+
+        writeln!(self.out)
+    }
+
+    fn ndjson(&mut self, path: &Path, node: &Inode) -> std::io::Result<()> {
+        let entry = Entry {
+            path,
+            typ: type_name(node.mode.file_type()),
+            size: node.size,
+            mode: node.mode.permissions(),
+            uid: node.uid,
+            gid: node.gid,
+        };
+
+        serde_json::to_writer(&mut self.out, &entry)?;
+        writeln!(self.out)
+    }
+}
+
+// number of children fetched from the FL at once by ls
+const LS_PAGE: u32 = 1000;
+
+/// ls writes the children of the directory at the given path of the FL to the output,
+/// one per line. If the path is not a directory, only the entry itself is written. With
+/// long, the type and permissions, owner, size and name of each entry are written.
+pub async fn ls<P: AsRef<Path>, W: Write>(
+    meta: &Reader,
+    path: P,
+    long: bool,
+    out: &mut W,
+) -> Result<()> {
+    let path = path.as_ref();
+    let node = meta
+        .lookup_path(path)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("path '{}' doesn't exist in the FL", path.display()))?;
+
+    if !node.mode.is(FileType::Dir) {
+        return ls_entry(out, &node, long).map_err(Into::into);
+    }
+
+    let mut offset = 0;
+    loop {
+        let children = meta.children(node.ino, LS_PAGE, offset).await?;
+        for child in children.iter() {
+            ls_entry(out, child, long)?;
+        }
+
+        if children.len() < LS_PAGE as usize {
+            break;
+        }
+        offset += children.len() as u64;
+    }
+
+    Ok(())
+}
+
+fn ls_entry<W: Write>(out: &mut W, node: &Inode, long: bool) -> std::io::Result<()> {
+    if long {
+        write!(
+            out,
+            "{} {:>5} {:>5} {:>10} ",
+            mode_string(&node.mode),
+            node.uid,
+            node.gid,
+            node.size
+        )?;
+    }
+
+    write!(out, "{}", node.name)?;
+    match node.mode.file_type() {
+        FileType::Dir => write!(out, "/")?,
+        FileType::Link if long => {
+            let target = node.data.as_deref().unwrap_or_default();
+            write!(out, " -> {}", String::from_utf8_lossy(target))?
+        }
+        _ => {}
+    }
+
+    writeln!(out)
+}
+
+/// mode_string formats the mode the same way as `ls -l` (for example `drwxr-xr-x`)
+fn mode_string(mode: &Mode) -> String {
+    let typ = match mode.file_type() {
+        FileType::Regular => '-',
+        FileType::Dir => 'd',
+        FileType::Link => 'l',
+        FileType::Block => 'b',
+        FileType::Char => 'c',
+        FileType::Socket => 's',
+        FileType::FIFO => 'p',
+        FileType::Unknown => '?',
+    };
+
+    let perm = mode.permissions();
+    let mut s = String::with_capacity(10);
+    s.push(typ);
+    for shift in [6, 3, 0].iter() {
+        let bits = (perm >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+
+    s
+}
+
+fn type_name(typ: FileType) -> &'static str {
+    match typ {
+        FileType::Regular => "file",
+        FileType::Dir => "dir",
+        FileType::Link => "link",
+        FileType::Block => "block",
+        FileType::Char => "char",
+        FileType::Socket => "socket",
+        FileType::FIFO => "fifo",
+        FileType::Unknown => "unknown",
+    }
+}
+
+#[async_trait::async_trait]
+impl<W: Write + Send> WalkVisitor for TreeVisitor<W> {
+    async fn visit(&mut self, path: &Path, node: &Inode) -> Result<Walk> {
+        match self.format {
+            TreeFormat::Ascii => self.ascii(path, node)?,
+            TreeFormat::Ndjson => self.ndjson(path, node)?,
+        }
+
+        Ok(Walk::Continue)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fungi::{
+        meta::fixture::{child, root},
+        Writer,
+    };
+
+    async fn make(path: &str) -> Reader {
+        let writer = Writer::new(path, true).await.unwrap();
+        let root = root(&writer).await;
+        let dir = child(&writer, root, "dir", FileType::Dir, Inode::default()).await;
+        child(
+            &writer,
+            dir,
+            "file",
+            FileType::Regular,
+            Inode {
+                size: 10,
+                uid: 1000,
+                gid: 1000,
+                ..Inode::default()
+            },
+        )
+        .await;
+
+        Reader::new(path).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tree_ascii() {
+        let reader = make("/tmp/tree-ascii.fl").await;
+        let mut visitor = TreeVisitor::new(Vec::new(), TreeFormat::Ascii);
+        reader.walk(&mut visitor).await.unwrap();
+
+        let output = String::from_utf8(visitor.out).unwrap();
+        assert_eq!(output, "/\n  dir/\n    file\n");
+    }
+
+    #[tokio::test]
+    async fn test_tree_ndjson() {
+        let reader = make("/tmp/tree-ndjson.fl").await;
+        let mut visitor = TreeVisitor::new(Vec::new(), TreeFormat::Ndjson);
+        reader.walk(&mut visitor).await.unwrap();
+
+        let output = String::from_utf8(visitor.out).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["path"], "/");
+        assert_eq!(lines[0]["type"], "dir");
+        assert_eq!(lines[2]["path"], "/dir/file");
+        assert_eq!(lines[2]["type"], "file");
+        assert_eq!(lines[2]["size"], 10);
+        assert_eq!(lines[2]["mode"], 0o644);
+        assert_eq!(lines[2]["uid"], 1000);
+    }
+
+    #[tokio::test]
+    async fn test_ls() {
+        let reader = make("/tmp/tree-ls.fl").await;
+
+        let mut out = Vec::new();
+        ls(&reader, "/", false, &mut out).await.unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "dir/\n");
+
+        // trailing slashes are ignored
+        let mut out = Vec::new();
+        ls(&reader, "/dir/", true, &mut out).await.unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "-rw-r--r--  1000  1000         10 file\n"
+        );
+
+        let mut out = Vec::new();
+        ls(&reader, "/dir/file", false, &mut out).await.unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "file\n");
+
+        assert!(ls(&reader, "/missing", false, &mut Vec::new())
+            .await
+            .is_err());
+    }
+}
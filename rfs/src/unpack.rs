@@ -1,53 +1,406 @@
 use crate::cache::Cache;
 use crate::fungi::{
-    meta::{FileType, Inode, Result, Walk, WalkVisitor},
+    meta::{Block, FileType, Ino, Inode, Result, Walk, WalkVisitor, WALK_BATCH},
     Reader,
 };
-use crate::store::Store;
+use crate::store::{self, Store};
 use anyhow::Context;
-use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+use futures::FutureExt;
+use nix::sys::stat::{mknod, utimensat, Mode, SFlag, UtimensatFlags};
+use nix::sys::time::{TimeSpec, TimeValLike};
+use nix::unistd::{fchownat, mkfifo, FchownatFlags, Gid, Uid};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::Permissions;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 use std::{ffi::OsStr, fs};
 use tokio::fs::OpenOptions;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+// max number of links followed while resolving a link target, same as the
+// limit of linux path resolution
+const MAX_LINKS: usize = 40;
+
+/// UnpackOptions are the settings of an unpack, the default unpacks the whole FL
+/// without restoring ownership, extended attributes or timestamps
+#[derive(Debug, Clone, Default)]
+pub struct UnpackOptions {
+    /// restore the ownership of the entries and create device nodes
+    pub preserve: bool,
+    /// restore the extended attributes stored in the FL
+    pub xattrs: bool,
+    /// only unpack the file or directory at that path (and its content) under the
+    /// root location, for example a subpath `/etc` is unpacked to `<root>/etc`
+    pub subpath: Option<PathBuf>,
+    /// don't fail on symlinks that resolve (through other links of the FL) to a path
+    /// outside of the root location or that form a loop
+    pub unsafe_links: bool,
+    /// restore the modification time stored in the FL on all entries (the access
+    /// time is set to the same value since the FL doesn't store it)
+    pub timestamps: bool,
+}
 
 /// unpack an FL to the given root location. it will download the files and reconstruct
-/// the filesystem.
+/// the filesystem, see [`UnpackOptions`] for what is restored.
 pub async fn unpack<P: AsRef<Path>, S: Store>(
     meta: &Reader,
     cache: &Cache<S>,
     root: P,
-    preserve: bool,
+    options: UnpackOptions,
 ) -> Result<()> {
-    let mut visitor = CopyVisitor::new(meta, cache, root.as_ref(), preserve);
+    let mut visitor = CopyVisitor::new(meta, cache, root.as_ref(), options.clone());
+
+    match options.subpath {
+        None => meta.walk(&mut visitor).await?,
+        Some(subpath) => {
+            let node = meta.lookup_path(&subpath).await?.ok_or_else(|| {
+                anyhow::anyhow!("path '{}' doesn't exist in the FL", subpath.display())
+            })?;
+
+            fs::create_dir_all(root.as_ref())
+                .with_context(|| format!("failed to create directory '{:?}'", root.as_ref()))?;
+
+            let path = Path::new("/").join(&node.name);
+            meta.walk_from(node, path, &mut visitor).await?
+        }
+    };
+
+    // wait for the remaining file downloads
+    visitor.wait().await?;
+
+    // creating the content of a directory changes its mtime, so the directories
+    // are only restored once everything is unpacked
+    for (path, mtime) in visitor.dirs.iter() {
+        set_mtime(path, *mtime)?;
+    }
 
-    meta.walk(&mut visitor).await
+    Ok(())
 }
 
-struct CopyVisitor<'a, S>
+/// cat writes the content of the regular file at the given path of the FL to out,
+/// the file blocks are downloaded through the cache
+pub async fn cat<P, S, W>(meta: &Reader, cache: &Cache<S>, path: P, out: &mut W) -> Result<()>
+where
+    P: AsRef<Path>,
+    S: Store,
+    W: tokio::io::AsyncWrite + Unpin + ?Sized,
+{
+    use tokio::io::AsyncWriteExt;
+
+    let path = path.as_ref();
+    let node = meta
+        .lookup_path(path)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("path '{}' doesn't exist in the FL", path.display()))?;
+
+    if !node.mode.is(FileType::Regular) {
+        return Err(anyhow::anyhow!("path '{}' is not a regular file", path.display()).into());
+    }
+
+    let blocks = meta.blocks(node.ino).await?;
+    cache
+        .direct(&blocks, out)
+        .await
+        .with_context(|| format!("failed to download file '{}'", path.display()))?;
+
+    out.flush().await?;
+    Ok(())
+}
+
+/// VerifyReport lists the blocks of an FL that failed verification, each with
+/// the path of (one of) the files using it
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// number of (unique) blocks checked
+    pub blocks: u64,
+    /// blocks that couldn't be retrieved from the stores
+    pub missing: Vec<(PathBuf, Block)>,
+    /// blocks that were retrieved but their content doesn't match the block id
+    pub corrupt: Vec<(PathBuf, Block)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupt.is_empty()
+    }
+}
+
+/// verify walks the FL and makes sure every block of every file can be retrieved from
+/// the stores and that its content matches the block id. Nothing is written to disk.
+pub async fn verify<S: Store>(meta: &Reader, cache: &Cache<S>) -> Result<VerifyReport> {
+    let mut visitor = VerifyVisitor {
+        meta,
+        cache,
+        checked: HashSet::default(),
+        report: VerifyReport::default(),
+    };
+
+    meta.walk_streaming(WALK_BATCH, &mut visitor).await?;
+
+    Ok(visitor.report)
+}
+
+struct VerifyVisitor<'a, S>
 where
     S: Store,
 {
-    preserve: bool,
     meta: &'a Reader,
     cache: &'a Cache<S>,
+    checked: HashSet<[u8; 32]>,
+    report: VerifyReport,
+}
+
+#[async_trait::async_trait]
+impl<'a, S> WalkVisitor for VerifyVisitor<'a, S>
+where
+    S: Store,
+{
+    async fn visit(&mut self, path: &Path, node: &Inode) -> Result<Walk> {
+        if !node.mode.is(FileType::Regular) {
+            return Ok(Walk::Continue);
+        }
+
+        for block in self.meta.blocks(node.ino).await? {
+            if !self.checked.insert(block.id) {
+                continue;
+            }
+
+            self.report.blocks += 1;
+            match self.cache.verify(&block).await {
+                Ok(_) => {}
+                Err(store::Error::InvalidBlob) => {
+                    self.report.corrupt.push((path.into(), block));
+                }
+                Err(err) => {
+                    debug!("failed to get block {}: {}", hex::encode(block.id), err);
+                    self.report.missing.push((path.into(), block));
+                }
+            }
+        }
+
+        Ok(Walk::Continue)
+    }
+}
+
+struct CopyVisitor<'a, S>
+where
+    S: Store,
+{
+    options: UnpackOptions,
+    meta: &'a Reader,
+    // shared with the download tasks
+    cache: Cache<S>,
     root: &'a Path,
+    // path in the FL of the directory unpacked to root
+    base: PathBuf,
+    // directories and their mtime, restored at the end of the unpack
+    dirs: Vec<(PathBuf, i64)>,
+    // location of the first unpacked file of each hardlink group
+    links: HashMap<Ino, PathBuf>,
+    // limits the number of files that are downloaded at the same time
+    semaphore: Arc<Semaphore>,
+    // files that are being downloaded, in the background of the walk
+    downloads: JoinSet<Result<()>>,
 }
 
 impl<'a, S> CopyVisitor<'a, S>
 where
     S: Store,
 {
-    pub fn new(meta: &'a Reader, cache: &'a Cache<S>, root: &'a Path, preserve: bool) -> Self {
+    pub fn new(
+        meta: &'a Reader,
+        cache: &'a Cache<S>,
+        root: &'a Path,
+        options: UnpackOptions,
+    ) -> Self {
+        // links are resolved in the FL from the parent of the unpacked subpath
+        let base = match &options.subpath {
+            Some(subpath) => subpath.parent().unwrap_or_else(|| Path::new("/")).into(),
+            None => PathBuf::from("/"),
+        };
+
         Self {
+            options,
             meta,
-            cache,
+            cache: cache.clone(),
             root,
-            preserve,
+            base,
+            dirs: Vec::default(),
+            links: HashMap::default(),
+            semaphore: Arc::new(Semaphore::new(crate::PARALLEL_DOWNLOAD)),
+            downloads: JoinSet::new(),
+        }
+    }
+
+    /// check returns the error of the first failed download among the downloads
+    /// that are already done, so the unpack stops early
+    fn check(&mut self) -> Result<()> {
+        while let Some(Some(result)) = self.downloads.join_next().now_or_never() {
+            result.context("download task failed")??;
+        }
+
+        Ok(())
+    }
+
+    /// wait for all pending downloads to finish
+    async fn wait(&mut self) -> Result<()> {
+        while let Some(result) = self.downloads.join_next().await {
+            result.context("download task failed")??;
+        }
+
+        Ok(())
+    }
+}
+
+/// check_link resolves the target of the link at path inside the FL, following the other
+/// links of the FL it goes through. It fails if the target goes above the FL root, which
+/// would be outside of the unpack root once unpacked, or if there are too many links.
+/// Components of the target that don't exist in the FL are resolved lexically.
+///
+/// The path is relative to the unpack root, which is the base directory of the FL.
+async fn check_link(meta: &Reader, base: &Path, path: &Path, target: &Path) -> Result<()> {
+    let mut current: Vec<String> = match path.parent() {
+        Some(parent) => components(parent)
+            .into_iter()
+            .filter(|c| c != "/")
+            .collect(),
+        None => Vec::default(),
+    };
+    let mut pending: VecDeque<String> = components(target).into();
+    let mut hops = 0;
+
+    while let Some(component) = pending.pop_front() {
+        match component.as_str() {
+            "/" => current.clear(),
+            ".." => {
+                if current.pop().is_none() {
+                    return Err(anyhow::anyhow!(
+                        "link '{}' points outside of the unpack root",
+                        path.display()
+                    )
+                    .into());
+                }
+            }
+            _ => {
+                current.push(component);
+                let node = match meta
+                    .lookup_path(base.join(current.iter().collect::<PathBuf>()))
+                    .await?
+                {
+                    Some(node) if node.mode.is(FileType::Link) => node,
+                    _ => continue,
+                };
+
+                hops += 1;
+                if hops > MAX_LINKS {
+                    return Err(anyhow::anyhow!(
+                        "too many levels of links resolving '{}'",
+                        path.display()
+                    )
+                    .into());
+                }
+
+                // the link is replaced by its target, which is resolved
+                // relative to the link directory
+                current.pop();
+                let next = Path::new(OsStr::from_bytes(node.data.as_deref().unwrap_or_default()));
+                for component in components(next).into_iter().rev() {
+                    pending.push_front(component);
+                }
+            }
         }
     }
+
+    Ok(())
+}
+
+/// components returns the components of the path as strings, the root is `/`
+/// and `.` components are skipped
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::CurDir | Component::Prefix(_) => None,
+            component => Some(component.as_os_str().to_string_lossy().into_owned()),
+        })
+        .collect()
+}
+
+/// download writes the file blocks to the file, then restores the file
+/// permissions and attributes
+async fn download<S: Store>(
+    meta: &Reader,
+    cache: &Cache<S>,
+    rooted: PathBuf,
+    node: Inode,
+    options: &UnpackOptions,
+) -> Result<()> {
+    let mut fd = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(&rooted)
+        .await
+        .with_context(|| format!("failed to open file '{:?}'", rooted))?;
+
+    let blocks = meta.blocks(node.ino).await?;
+    cache
+        .direct(&blocks, &mut fd)
+        .await
+        .with_context(|| format!("failed to download file '{:?}'", rooted))?;
+
+    fd.set_permissions(Permissions::from_mode(node.mode.mode()))
+        .await?;
+
+    restore(meta, &rooted, &node, options).await
+}
+
+/// restore sets the ownership (if preserve is set), the extended attributes (if
+/// xattrs is set) and the mtime (if timestamps is set) of the unpacked entry.
+/// The mtime of directories is not set, see [`unpack`].
+async fn restore(
+    meta: &Reader,
+    rooted: &Path,
+    node: &Inode,
+    options: &UnpackOptions,
+) -> Result<()> {
+    if options.preserve {
+        fchownat(
+            None,
+            rooted,
+            Some(Uid::from_raw(node.uid)),
+            Some(Gid::from_raw(node.gid)),
+            FchownatFlags::NoFollowSymlink,
+        )
+        .with_context(|| format!("failed to change ownership of '{:?}'", rooted))?;
+    }
+
+    // xattrs are set after the ownership is changed because a chown
+    // clears the file capabilities
+    if options.xattrs {
+        for (name, value) in meta.xattrs(node.ino).await? {
+            if let Err(err) = xattr::set(rooted, &name, &value) {
+                warn!("failed to set xattr '{}' of '{:?}': {}", name, rooted, err);
+            }
+        }
+    }
+
+    if options.timestamps && !node.mode.is(FileType::Dir) {
+        set_mtime(rooted, node.mtime)?;
+    }
+
+    Ok(())
+}
+
+/// set_mtime sets the access and modification times of the path (without
+/// following symlinks) to mtime
+fn set_mtime(path: &Path, mtime: i64) -> Result<()> {
+    let time = TimeSpec::seconds(mtime);
+    utimensat(None, path, &time, &time, UtimensatFlags::NoFollowSymlink)
+        .with_context(|| format!("failed to set mtime of '{:?}'", path))?;
+
+    Ok(())
 }
 
 #[async_trait::async_trait]
@@ -56,30 +409,66 @@ where
     S: Store,
 {
     async fn visit(&mut self, path: &Path, node: &Inode) -> Result<Walk> {
-        let rooted = self.root.join(path.strip_prefix("/").unwrap());
+        // names with `..` or `/` would place the entry outside of its directory
+        let relative = path.strip_prefix("/").unwrap();
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            return Err(anyhow::anyhow!("invalid entry path '{}'", path.display()).into());
+        }
+        let rooted = self.root.join(relative);
 
         match node.mode.file_type() {
             FileType::Dir => {
                 fs::create_dir_all(&rooted)
                     .with_context(|| format!("failed to create directory '{:?}'", rooted))?;
+                if self.options.timestamps {
+                    self.dirs.push((rooted.clone(), node.mtime));
+                }
             }
             FileType::Regular => {
-                let mut fd = OpenOptions::new()
+                let group = self.meta.hardlink(node.ino).await?;
+                if let Some(first) = group.and_then(|group| self.links.get(&group)) {
+                    fs::hard_link(first, &rooted)
+                        .with_context(|| format!("failed to create hardlink '{:?}'", rooted))?;
+
+                    return Ok(Walk::Continue);
+                }
+
+                if let Some(group) = group {
+                    self.links.insert(group, rooted.clone());
+                }
+
+                // the file is created right away so hardlinks to it can be
+                // created before it's downloaded
+                OpenOptions::new()
                     .create_new(true)
                     .write(true)
-                    .truncate(true)
                     .open(&rooted)
                     .await
                     .with_context(|| format!("failed to create file '{:?}'", rooted))?;
 
-                let blocks = self.meta.blocks(node.ino).await?;
-                self.cache
-                    .direct(&blocks, &mut fd)
+                // bound the number of files downloaded in parallel, the walk goes
+                // on while the downloads run
+                let permit = Arc::clone(&self.semaphore)
+                    .acquire_owned()
                     .await
-                    .with_context(|| format!("failed to download file '{:?}'", rooted))?;
+                    .context("failed to acquire download permit")?;
+                self.check()?;
 
-                fd.set_permissions(Permissions::from_mode(node.mode.mode()))
-                    .await?;
+                // the permissions and attributes are restored once the content is
+                // written, since writing to a file clears its capabilities
+                let meta = self.meta.clone();
+                let cache = self.cache.clone();
+                let node = node.clone();
+                let options = self.options.clone();
+                self.downloads.spawn(async move {
+                    let _permit = permit;
+                    download(&meta, &cache, rooted, node, &options).await
+                });
+
+                return Ok(Walk::Continue);
             }
             FileType::Link => {
                 let target = node
@@ -88,6 +477,10 @@ where
                     .ok_or_else(|| anyhow::anyhow!("link has no target path"))?;
 
                 let target = Path::new(OsStr::from_bytes(target));
+                if !self.options.unsafe_links {
+                    check_link(self.meta, &self.base, path, target).await?;
+                }
+
                 let target = if target.is_relative() {
                     target.to_owned()
                 } else {
@@ -97,88 +490,200 @@ where
                 std::os::unix::fs::symlink(target, &rooted)
                     .with_context(|| format!("failed to create symlink '{:?}'", rooted))?;
             }
+            FileType::Block | FileType::Char => {
+                // creating device nodes requires privileges, so it's only done when
+                // the ownership is also preserved
+                if !self.options.preserve {
+                    warn!(
+                        "skipping device node '{:?}', ownership is not preserved",
+                        rooted
+                    );
+                    return Ok(Walk::Continue);
+                }
+
+                let kind = match node.mode.file_type() {
+                    FileType::Block => SFlag::S_IFBLK,
+                    _ => SFlag::S_IFCHR,
+                };
+
+                let perm = Mode::from_bits_truncate(node.mode.permissions());
+                match mknod(&rooted, kind, perm, node.rdev) {
+                    Ok(_) => {}
+                    Err(nix::errno::Errno::EPERM) => {
+                        warn!("skipping device node '{:?}', missing CAP_MKNOD", rooted);
+                        return Ok(Walk::Continue);
+                    }
+                    Err(err) => {
+                        return Err(anyhow::Error::from(err)
+                            .context(format!("failed to create device node '{:?}'", rooted))
+                            .into());
+                    }
+                }
+            }
+            FileType::FIFO => {
+                let perm = Mode::from_bits_truncate(node.mode.permissions());
+                mkfifo(&rooted, perm)
+                    .with_context(|| format!("failed to create fifo '{:?}'", rooted))?;
+            }
             _ => {
                 warn!("unknown file kind: {:?}", node.mode.file_type());
                 return Ok(Walk::Continue);
             }
         };
 
-        if self.preserve {
-            fchownat(
-                None,
-                &rooted,
-                Some(Uid::from_raw(node.uid)),
-                Some(Gid::from_raw(node.gid)),
-                FchownatFlags::NoFollowSymlink,
-            )
-            .with_context(|| format!("failed to change ownership of '{:?}'", &rooted))?;
-        }
+        restore(self.meta, &rooted, node, &self.options).await?;
 
         Ok(Walk::Continue)
     }
 }
 
-/*
-TODO: parallel download ?
-
-this is a download worker that can be used in a worker pool to download files
-in parallel
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fungi::{meta::Mode as FileMode, Writer};
+    use crate::store::mem::MemStore;
 
-struct Downloader<S>
-where
-    S: Store,
-{
-    cache: Arc<Cache<S>>,
-}
+    async fn unpack_links(name: &str, links: &[(&str, &str)]) -> Result<()> {
+        let root = Path::new("/tmp/unpack-links-test").join(name);
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
 
-impl<S> Downloader<S>
-where
-    S: Store,
-{
-    async fn download(&self, path: &Path, blocks: &[Block], mode: u32) -> Result<()> {
-        let mut fd = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .truncate(true)
-            .open(&path)
+        let path = root.join("test.fl");
+        let writer = Writer::new(&path, true).await.unwrap();
+        let ino = writer
+            .inode(Inode {
+                name: "/".into(),
+                mode: FileMode::new(FileType::Dir, 0o755),
+                ..Inode::default()
+            })
             .await
-            .with_context(|| format!("failed to create file '{:?}'", path))?;
-
-        self.cache
-            .direct(&blocks, &mut fd)
+            .unwrap();
+        let dir = writer
+            .inode(Inode {
+                parent: ino,
+                name: "dir".into(),
+                mode: FileMode::new(FileType::Dir, 0o755),
+                ..Inode::default()
+            })
             .await
-            .with_context(|| format!("failed to download file '{:?}'", path))?;
+            .unwrap();
 
-        fd.set_permissions(Permissions::from_mode(mode)).await?;
+        for (name, target) in links {
+            let (parent, name) = match name.strip_prefix("dir/") {
+                Some(name) => (dir, name),
+                None => (ino, *name),
+            };
+            writer
+                .inode(Inode {
+                    parent,
+                    name: name.to_string(),
+                    mode: FileMode::new(FileType::Link, 0o777),
+                    data: Some(target.as_bytes().into()),
+                    ..Inode::default()
+                })
+                .await
+                .unwrap();
+        }
 
-        Ok(())
+        let reader = Reader::new(&path).await.unwrap();
+        let cache = Cache::new(root.join("cache"), MemStore::new());
+        unpack(
+            &reader,
+            &cache,
+            root.join("destination"),
+            UnpackOptions::default(),
+        )
+        .await
     }
-}
 
-impl<S> Clone for Downloader<S>
-where
-    S: Store,
-{
-    fn clone(&self) -> Self {
-        Self {
-            cache: Arc::clone(&self.cache),
+    #[tokio::test]
+    async fn test_unpack_safe_links() {
+        unpack_links(
+            "safe",
+            &[
+                ("abs", "/dir"),
+                ("rel", "dir/../dir"),
+                ("dir/up", "../abs"),
+                ("dir/missing", "../nowhere"),
+            ],
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unpack_escaping_links() {
+        let cases: &[&[(&str, &str)]] = &[
+            &[("escape", "../../etc")],
+            &[("dir/escape", "../../etc/passwd")],
+            &[("abs", "/../etc")],
+            // each link stays inside the root, but not once chained
+            &[("root", "/"), ("chained", "root/..")],
+            &[("dir/root", ".."), ("dir/chained", "root/../..")],
+            // loop
+            &[("a", "b"), ("b", "a")],
+        ];
+
+        for (i, links) in cases.iter().enumerate() {
+            let result = unpack_links(&format!("escape-{}", i), links).await;
+            assert!(result.is_err(), "{:?} should fail", links);
         }
     }
-}
 
-#[async_trait::async_trait]
-impl<S> workers::Work for Downloader<S>
-where
-    S: Store,
-{
-    type Input = (PathBuf, Vec<Block>, Mode);
-    type Output = ();
+    #[tokio::test]
+    async fn test_unpack_xattrs() {
+        let root = Path::new("/tmp/unpack-xattrs-test");
+        let _ = fs::remove_dir_all(root);
+        let source = root.join("source");
+        fs::create_dir_all(source.join("dir")).unwrap();
+        fs::write(source.join("dir/file"), b"data").unwrap();
+        if let Err(err) = xattr::set(source.join("dir/file"), "user.rfs", b"file") {
+            // the filesystem has no user xattrs support
+            eprintln!("skipping xattrs test: {}", err);
+            return;
+        }
+        xattr::set(source.join("dir"), "user.rfs", b"dir").unwrap();
+
+        let path = root.join("test.fl");
+        let store = MemStore::new();
+        let writer = Writer::new(&path, true).await.unwrap();
+        crate::pack(
+            writer,
+            store.clone(),
+            &source,
+            crate::PackOptions {
+                strip_password: false,
+                ..crate::PackOptions::default()
+            },
+        )
+        .await
+        .unwrap();
 
-    async fn run(&mut self, (path, blocks, mode): Self::Input) -> Self::Output {
-        if let Err(err) = self.download(&path, &blocks, mode.mode()).await {
-            log::error!("failed to download file {:?}: {}", path, err);
+        let reader = Reader::new(&path).await.unwrap();
+        let cache = Cache::new(root.join("cache"), store);
+        for xattrs in [true, false] {
+            let destination = root.join(format!("destination-{}", xattrs));
+            unpack(
+                &reader,
+                &cache,
+                &destination,
+                UnpackOptions {
+                    xattrs,
+                    ..UnpackOptions::default()
+                },
+            )
+            .await
+            .unwrap();
+
+            let expected = |value: &[u8]| xattrs.then(|| value.to_vec());
+            assert_eq!(
+                xattr::get(destination.join("dir/file"), "user.rfs").unwrap(),
+                expected(b"file")
+            );
+            assert_eq!(
+                xattr::get(destination.join("dir"), "user.rfs").unwrap(),
+                expected(b"dir")
+            );
         }
     }
 }
-
-*/